@@ -0,0 +1,63 @@
+use chip8::gui::present::{scale_rect, SCALE};
+use chip8::gui::UserEvent;
+use chip8::internals::display::{Dirty, DisplayCommand, Frame, Rect, HEIGHT, WIDTH};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn pixels() -> [u32; WIDTH * HEIGHT] {
+    let mut fb = [0; WIDTH * HEIGHT];
+    for (i, p) in fb.iter_mut().enumerate() {
+        if i % 3 == 0 {
+            *p = 0xFFFF;
+        }
+    }
+    fb
+}
+
+fn draw(dirty: Dirty) -> DisplayCommand {
+    DisplayCommand::Draw(Box::new(Frame {
+        pixels: pixels(),
+        dirty,
+    }))
+}
+
+/// What every `Draw` used to cost: rescaling all 2048 pixels.
+fn full_frame(c: &mut Criterion) {
+    let fb = pixels();
+    let mut b = vec![0; WIDTH * SCALE * HEIGHT * SCALE];
+    c.bench_function("present full frame", |bench| {
+        bench.iter(|| scale_rect(black_box(fb.as_slice()), &mut b, Rect::full(), SCALE))
+    });
+}
+
+/// One 8x5 sprite changed, as when drawing a single hex digit.
+fn single_sprite(c: &mut Criterion) {
+    let cmd = draw(Dirty::sprite(20, 10, 5));
+    let mut native = [0; WIDTH * HEIGHT];
+    let mut b = vec![0; WIDTH * SCALE * HEIGHT * SCALE];
+    c.bench_function("present single sprite", |bench| {
+        bench.iter(|| {
+            let dirty = black_box(&cmd).transform(&mut native);
+            for r in dirty.rects() {
+                scale_rect(&native, &mut b, *r, SCALE);
+            }
+        })
+    });
+}
+
+/// A sprite wrapping around the corner, split into four regions.
+fn wrapped_sprite(c: &mut Criterion) {
+    let cmd = draw(Dirty::sprite(60, 30, 15));
+    let mut native = [0; WIDTH * HEIGHT];
+    let mut b = vec![0; WIDTH * SCALE * HEIGHT * SCALE];
+    c.bench_function("present wrapped sprite", |bench| {
+        bench.iter(|| {
+            let dirty = black_box(&cmd).transform(&mut native);
+            for r in dirty.rects() {
+                scale_rect(&native, &mut b, *r, SCALE);
+            }
+        })
+    });
+}
+
+criterion_group!(benches, full_frame, single_sprite, wrapped_sprite);
+criterion_main!(benches);
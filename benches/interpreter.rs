@@ -0,0 +1,95 @@
+use chip8::internals::{jit::Backend, parse_opcode, Chip8, InstructionResult};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// Long enough that setting up the machine doesn't count.
+const INSTRUCTIONS: u64 = 100_000;
+
+/// A busy loop of arithmetic and a store, that never waits or draws.
+fn machine() -> Chip8 {
+    let program = [
+        0x60, 0x00, // v0 = 0
+        0x71, 0x01, // v1 += 1
+        0x82, 0x14, // v2 += v1
+        0x83, 0x26, // v3 = v2 >> 1
+        0xA3, 0x00, // i = 0x300
+        0xF3, 0x33, // bcd v3
+        0x44, 0x00, // skip if v4 != 0, which it never is
+        0x12, 0x02, // loop
+    ];
+    let mut c8 = Chip8::new();
+    c8.memory.0[0x200..0x200 + program.len()].copy_from_slice(&program);
+    c8
+}
+
+/// How it was: build the opcode and decode it on every step.
+fn decode_every_time(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interpret");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function("decode every time", |bench| {
+        bench.iter(|| {
+            let mut c8 = machine();
+            for _ in 0..INSTRUCTIONS {
+                let pc = c8.registers.pc as usize;
+                let op = u16::from_be_bytes([c8.memory.0[pc], c8.memory.0[pc + 1]]);
+                c8.status = match c8.run_instruction(parse_opcode(op)).unwrap() {
+                    InstructionResult::Waiting => InstructionResult::Waiting,
+                    _ => InstructionResult::Success,
+                };
+            }
+            c8
+        })
+    });
+    group.bench_function("decode cache", |bench| {
+        bench.iter(|| {
+            let mut c8 = machine();
+            for _ in 0..INSTRUCTIONS {
+                c8.step().unwrap();
+            }
+            c8
+        })
+    });
+    group.finish();
+}
+
+/// Arithmetic and nothing else, where the JIT has the most to gain.
+fn arithmetic() -> Chip8 {
+    let program = [
+        0x71, 0x01, // v1 += 1
+        0x82, 0x14, // v2 += v1
+        0x83, 0x20, // v3 = v2
+        0x83, 0x12, // v3 &= v1
+        0x84, 0x33, // v4 ^= v3
+        0x31, 0x00, // skip if v1 == 0
+        0x12, 0x00, // loop
+        0x65, 0x01, // v5 = 1
+        0x12, 0x00, // loop
+    ];
+    let mut c8 = Chip8::new();
+    c8.memory.0[0x200..0x200 + program.len()].copy_from_slice(&program);
+    c8
+}
+
+/// A whole run in one frame, the way batch runs go through frames.
+fn backends(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run_frame");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    for backend in [Backend::Interpreter, Backend::Jit] {
+        for (name, program) in [
+            ("stores", machine as fn() -> Chip8),
+            ("arithmetic", arithmetic),
+        ] {
+            group.bench_function(format!("{backend} {name}"), |bench| {
+                bench.iter(|| {
+                    let mut c8 = program();
+                    c8.backend = backend;
+                    c8.run_frame(INSTRUCTIONS as usize).unwrap();
+                    c8
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode_every_time, backends);
+criterion_main!(benches);
@@ -0,0 +1,138 @@
+/* Generated from src/ffi.rs by cbindgen. Don't edit. */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Bumped whenever something here changes in a way that breaks callers.
+#define CHIP8_ABI_VERSION 1
+
+#define CHIP8_WIDTH 64
+
+#define CHIP8_HEIGHT 32
+
+#define CHIP8_MEMORY_SIZE 4096
+
+// How deep `Chip8Registers` can show the stack.
+#define CHIP8_STACK_SIZE 16
+
+// What every call that can fail returns. The values won't change.
+typedef enum {
+  CHIP8_STATUS_OK = 0,
+  // A pointer that can't be null was.
+  CHIP8_STATUS_NULL = 1,
+  // A ROM file couldn't be read.
+  CHIP8_STATUS_IO = 2,
+  CHIP8_STATUS_EMPTY_ROM = 3,
+  CHIP8_STATUS_ROM_TOO_BIG = 4,
+  CHIP8_STATUS_BAD_ADDRESS = 5,
+  // A cartridge couldn't be unpacked.
+  CHIP8_STATUS_BAD_FORMAT = 6,
+  // `00EE` with nothing on the stack.
+  CHIP8_STATUS_STACK_UNDERFLOW = 7,
+  // An address, key or length that's out of range.
+  CHIP8_STATUS_OUT_OF_RANGE = 8,
+  // A bug in the library. The machine may be left half way through
+  // the call, so it's best freed.
+  CHIP8_STATUS_PANICKED = 9,
+} Chip8Status;
+
+// A machine.
+typedef struct Chip8Machine Chip8Machine;
+
+// A machine's state, saved to be restored later.
+typedef struct Chip8State Chip8State;
+
+// The CPU's registers, laid out for C.
+typedef struct {
+  uint8_t v[16];
+  uint16_t i;
+  uint16_t pc;
+  uint8_t delay;
+  uint8_t sound;
+  // How many of `stack` are in use.
+  uint8_t sp;
+  uint16_t stack[CHIP8_STACK_SIZE];
+} Chip8Registers;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+uint32_t chip8_abi_version(void);
+
+// A powered-on machine with nothing loaded, or null if one couldn't be
+// made. Free it with `chip8_free`.
+Chip8Machine *chip8_new(void);
+
+void chip8_free(Chip8Machine *m);
+
+// Loads `len` bytes of program and powers the machine back on to run it.
+// Nothing changes if it doesn't fit.
+Chip8Status chip8_load_rom(Chip8Machine *m, const uint8_t *rom, size_t len);
+
+// Like `chip8_load_rom`, from a ROM or Octo cartridge file. A cartridge's
+// options are applied too.
+Chip8Status chip8_load_file(Chip8Machine *m, const char *path);
+
+// Starts the program over, wiping memory as well if `cold`.
+Chip8Status chip8_reset(Chip8Machine *m, bool cold);
+
+// Makes `Cxnn` repeatable from here on.
+Chip8Status chip8_seed(Chip8Machine *m, uint64_t seed);
+
+// Runs `instructions` instructions, without ticking the timers. An
+// `Fx0A` waiting for a key counts each time it's run.
+Chip8Status chip8_step(Chip8Machine *m, uint32_t instructions);
+
+// Runs `frames` frames of up to `instructions_per_frame` instructions,
+// ticking the timers after each.
+Chip8Status chip8_run_frames(Chip8Machine *m, uint32_t frames, uint32_t instructions_per_frame);
+
+// Presses or releases one of the 16 keys.
+Chip8Status chip8_set_key(Chip8Machine *m, uint8_t key, bool down);
+
+// Holds the keys whose bits are set in `keys`, key 0 in the lowest bit,
+// and releases the rest.
+Chip8Status chip8_set_keys(Chip8Machine *m, uint16_t keys);
+
+// Copies the screen into `out`, a row at a time from the top left, one
+// byte per pixel: 1 if it's lit, 0 if not. `len` has to be at least
+// `CHIP8_WIDTH * CHIP8_HEIGHT`.
+Chip8Status chip8_framebuffer(Chip8Machine *m, uint8_t *out, size_t len);
+
+// Fails if the stack is deeper than `CHIP8_STACK_SIZE`.
+Chip8Status chip8_get_registers(Chip8Machine *m, Chip8Registers *out);
+
+// Fails, changing nothing, if `sp` is more than `CHIP8_STACK_SIZE`, or
+// `pc` or an address on the stack is off the end of memory.
+Chip8Status chip8_set_registers(Chip8Machine *m, const Chip8Registers *r);
+
+// Copies `len` bytes of memory from `address` into `out`.
+Chip8Status chip8_read_memory(Chip8Machine *m, uint16_t address, uint8_t *out, size_t len);
+
+// Copies `len` bytes from `data` into memory at `address`. Code written
+// over is picked up from the next instruction on.
+Chip8Status chip8_write_memory(Chip8Machine *m, uint16_t address, const uint8_t *data, size_t len);
+
+// Everything needed to put the machine back where it is now, short of
+// the keys held. Null if `m` is, or if it couldn't be saved. Free it
+// with `chip8_free_state`.
+Chip8State *chip8_save_state(Chip8Machine *m);
+
+// Puts the machine back the way it was when `state` was saved. A state
+// can be restored any number of times, to any machine.
+Chip8Status chip8_restore_state(Chip8Machine *m, const Chip8State *state);
+
+void chip8_free_state(Chip8State *state);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8_H */
@@ -0,0 +1,330 @@
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::capture::screenshot::save_png;
+use crate::database::{Database, Settings};
+use crate::internals::{
+    display::{HEIGHT, WIDTH},
+    jit::Backend,
+    keypad::Button,
+    timing::Timing,
+    Chip8, InstructionResult, Reset,
+};
+use crate::panic_message;
+
+/// Decides from the frame buffer whether a run is done.
+pub type Predicate = Arc<dyn Fn(&[u32]) -> bool + Send + Sync>;
+
+/// One run of one ROM, from a cold start. Jobs are read from JSON, where
+/// everything but `rom` can be left out.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct Job {
+    /// What the report calls it, the ROM's path if it's empty.
+    pub name: String,
+    pub rom: PathBuf,
+    /// For `Cxnn`, so the same job always runs the same way.
+    pub seed: u64,
+    /// Keys pressed and released, by the frame they happen before.
+    pub movie: Vec<Input>,
+    /// The most frames to run.
+    pub frames: usize,
+    /// The most instructions to run, checked between frames.
+    pub instructions: Option<u64>,
+    /// Overrides what the ROM's settings say.
+    pub instructions_per_frame: Option<usize>,
+    /// Stop once the program has nothing left to do: it's jumping to
+    /// itself, or waiting for a key the movie will never press.
+    pub halt: bool,
+    /// Stop as soon as the pixel at `[x, y]` is lit.
+    pub until_lit: Option<(usize, usize)>,
+    /// Stop as soon as this says so.
+    #[serde(skip)]
+    pub until: Option<Predicate>,
+    /// Where to save the last frame, at 64x32.
+    pub screenshot: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Input {
+    pub frame: usize,
+    pub key: u8,
+    /// Pressed, or released.
+    pub down: bool,
+}
+
+impl Default for Job {
+    fn default() -> Self {
+        Job {
+            name: String::new(),
+            rom: PathBuf::new(),
+            seed: 0,
+            movie: Vec::new(),
+            frames: 600,
+            instructions: None,
+            instructions_per_frame: None,
+            halt: true,
+            until_lit: None,
+            until: None,
+            screenshot: None,
+        }
+    }
+}
+
+impl Job {
+    pub fn new(rom: impl Into<PathBuf>) -> Self {
+        Job {
+            rom: rom.into(),
+            ..Job::default()
+        }
+    }
+}
+
+/// Why a run stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stop {
+    Frames,
+    Instructions,
+    Halted,
+    Predicate,
+    /// The ROM wouldn't load, or the program did something it can't.
+    Error,
+}
+
+/// What a job came to.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Outcome {
+    pub name: String,
+    pub stop: Stop,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub frames: usize,
+    pub instructions: u64,
+    /// SHA-1 of the registers, stack, memory and frame buffer at the end,
+    /// so two runs can be compared without keeping either.
+    pub state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screenshot: Option<PathBuf>,
+}
+
+/// Runs jobs side by side, each on a machine of its own.
+pub struct Batch {
+    pub database: Database,
+    /// What ROMs the database doesn't know about run with.
+    pub settings: Settings,
+    pub backend: Backend,
+    pub timing: Timing,
+    pub threads: usize,
+}
+
+impl Batch {
+    /// As many threads as there are cores.
+    pub fn new(settings: Settings) -> Self {
+        Batch {
+            database: Database::default(),
+            settings,
+            backend: Backend::default(),
+            timing: Timing::default(),
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// Every job's outcome, in the order the jobs came in. Which thread
+    /// runs what makes no difference to them. Where there are no more
+    /// threads to be had, as under WASI, they all run on this one.
+    pub fn run(&self, jobs: &[Job]) -> Vec<Outcome> {
+        let next = AtomicUsize::new(0);
+        let work = || {
+            let mut done = Vec::new();
+            loop {
+                let n = next.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(n) else {
+                    return done;
+                };
+                done.push((n, self.run_one(job)));
+            }
+        };
+        let threads = self.threads.clamp(1, jobs.len().max(1));
+        let mut done: Vec<(usize, Outcome)> = std::thread::scope(|s| {
+            // this thread makes one of them
+            let workers: Vec<_> = (1..threads)
+                .map_while(|_| std::thread::Builder::new().spawn_scoped(s, work).ok())
+                .collect();
+            let mut done = work();
+            for w in workers {
+                done.extend(w.join().unwrap());
+            }
+            done
+        });
+        done.sort_by_key(|(n, _)| *n);
+        done.into_iter().map(|(_, o)| o).collect()
+    }
+
+    /// A job that panics comes back as an error, so it doesn't take the
+    /// rest of the batch with it.
+    pub fn run_one(&self, job: &Job) -> Outcome {
+        std::panic::catch_unwind(AssertUnwindSafe(|| self.play_one(job))).unwrap_or_else(|p| {
+            Outcome {
+                name: name(job),
+                stop: Stop::Error,
+                error: Some(format!("panicked: {}", panic_message(&*p))),
+                frames: 0,
+                instructions: 0,
+                state: String::new(),
+                screenshot: None,
+            }
+        })
+    }
+
+    fn play_one(&self, job: &Job) -> Outcome {
+        let mut chip8 = Chip8::new();
+        chip8.backend = self.backend;
+        chip8.timing = self.timing;
+        chip8.seed(job.seed);
+        let mut outcome = Outcome {
+            name: name(job),
+            stop: Stop::Error,
+            error: None,
+            frames: 0,
+            instructions: 0,
+            state: String::new(),
+            screenshot: None,
+        };
+        match self
+            .database
+            .load_file(&mut chip8, &job.rom, &self.settings, Reset::Cold)
+        {
+            Ok(settings) => {
+                let ipf = (job.instructions_per_frame).unwrap_or(settings.instructions_per_frame);
+                if let Err(e) = play(&mut chip8, job, ipf, &mut outcome) {
+                    outcome.error = Some(e);
+                }
+            }
+            Err(e) => outcome.error = Some(format!("{}: {e}", job.rom.display())),
+        }
+        outcome.instructions = chip8.executed();
+        outcome.state = state_hash(&chip8);
+        if let Some(path) = &job.screenshot {
+            match save_png(path, &chip8.frame_buffer, WIDTH, HEIGHT) {
+                Ok(()) => outcome.screenshot = Some(path.clone()),
+                Err(e) => outcome.error = Some(format!("screenshot failed: {e}")),
+            }
+        }
+        outcome
+    }
+}
+
+fn name(job: &Job) -> String {
+    match job.name.is_empty() {
+        true => job.rom.display().to_string(),
+        false => job.name.clone(),
+    }
+}
+
+/// Runs frames until one of `job`'s limits is reached.
+fn play(chip8: &mut Chip8, job: &Job, ipf: usize, outcome: &mut Outcome) -> Result<(), String> {
+    let mut movie = job.movie.clone();
+    movie.sort_by_key(|i| i.frame);
+    let mut movie = movie.into_iter().peekable();
+    let lit = |frame: &[u32]| match job.until_lit {
+        Some((x, y)) => x < WIDTH && y < HEIGHT && frame[y * WIDTH + x] != 0,
+        None => false,
+    };
+    outcome.stop = Stop::Frames;
+    while outcome.frames < job.frames {
+        while let Some(i) = movie.next_if(|i| i.frame <= outcome.frames) {
+            match i.down {
+                true => chip8.keypad.press(Button::from_u8(i.key)),
+                false => chip8.keypad.release(Button::from_u8(i.key)),
+            }
+        }
+        outcome.frames += 1;
+        if let Err(e) = chip8.run_frame(ipf) {
+            outcome.stop = Stop::Error;
+            return Err(format!("{e:?}"));
+        }
+        if lit(&chip8.frame_buffer) || job.until.as_ref().is_some_and(|p| p(&chip8.frame_buffer)) {
+            outcome.stop = Stop::Predicate;
+            break;
+        }
+        if job.halt
+            && (jumps_to_itself(chip8)
+                || chip8.status == InstructionResult::Waiting && movie.peek().is_none())
+        {
+            outcome.stop = Stop::Halted;
+            break;
+        }
+        if job.instructions.is_some_and(|n| chip8.executed() >= n) {
+            outcome.stop = Stop::Instructions;
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// `1nnn` to its own address, the usual way to end a program.
+pub(crate) fn jumps_to_itself(chip8: &Chip8) -> bool {
+    let pc = chip8.registers.pc as usize;
+    let memory = &chip8.memory.0;
+    pc + 1 < memory.len() && u16::from_be_bytes([memory[pc], memory[pc + 1]]) == 0x1000 | pc as u16
+}
+
+/// SHA-1 of everything a program can see or change.
+pub fn state_hash(chip8: &Chip8) -> String {
+    let r = &chip8.registers;
+    let mut h = sha1_smol::Sha1::new();
+    h.update(&r.r);
+    h.update(&r.vi.to_be_bytes());
+    h.update(&[r.delay, r.sound]);
+    h.update(&r.pc.to_be_bytes());
+    for s in &r.stack {
+        h.update(&s.to_be_bytes());
+    }
+    h.update(&chip8.memory.0);
+    for p in &chip8.frame_buffer {
+        h.update(&p.to_le_bytes());
+    }
+    h.digest().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{state_hash, Job, Stop};
+    use crate::internals::Chip8;
+
+    #[test]
+    fn test_job_defaults() {
+        let jobs: Vec<Job> = serde_json::from_str(
+            r#"[
+                {"rom": "a.ch8"},
+                {"rom": "b.ch8", "seed": 3, "halt": false, "until_lit": [1, 2],
+                 "movie": [{"frame": 10, "key": 5, "down": true}]}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(jobs[0].frames, 600);
+        assert!(jobs[0].halt);
+        assert_eq!(jobs[1].seed, 3);
+        assert!(!jobs[1].halt);
+        assert_eq!(jobs[1].until_lit, Some((1, 2)));
+        assert_eq!(jobs[1].movie[0].key, 5);
+        assert_eq!(
+            serde_json::to_string(&Stop::Instructions).unwrap(),
+            "\"instructions\""
+        );
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let mut a = Chip8::new();
+        let b = Chip8::new();
+        assert_eq!(state_hash(&a), state_hash(&b));
+        a.registers.stack.push(0x200);
+        assert_ne!(state_hash(&a), state_hash(&b));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod recorder;
+pub mod screenshot;
+pub mod wav;
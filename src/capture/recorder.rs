@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::internals::display::{HEIGHT, WIDTH};
+use crate::internals::FRAME_RATE;
+
+// browsers show frames with a shorter delay than this for a tenth of a
+// second, so a frame that would be shown for less gets replaced by the next
+const MIN_GIF_DELAY: u64 = 2;
+
+#[derive(Debug)]
+pub enum RecordError {
+    Io(io::Error),
+    Gif(gif::EncodingError),
+    /// Neither `.gif` nor `.y4m`.
+    UnknownFormat,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::Io(e) => write!(f, "{e}"),
+            RecordError::Gif(e) => write!(f, "{e}"),
+            RecordError::UnknownFormat => write!(f, "can only record to .gif or .y4m"),
+        }
+    }
+}
+
+impl From<io::Error> for RecordError {
+    fn from(e: io::Error) -> Self {
+        RecordError::Io(e)
+    }
+}
+
+impl From<gif::EncodingError> for RecordError {
+    fn from(e: gif::EncodingError) -> Self {
+        RecordError::Gif(e)
+    }
+}
+
+enum Sink {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        // the frame waiting for its delay to be known, and when it started
+        pending: Option<(Vec<u32>, u64)>,
+    },
+    /// Uncompressed 4:4:4 frames for feeding to an external encoder.
+    Y4m(BufWriter<File>),
+}
+
+/// Records completed frames, one per vblank, as an animated GIF or a raw
+/// Y4M stream depending on the file extension. Frames are native 64x32
+/// pixels, already filtered and coloured, upscaled by `scale`.
+pub struct Recorder {
+    sink: Sink,
+    scale: usize,
+    frames: u64,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>, scale: usize) -> Result<Self, RecordError> {
+        let path = path.as_ref();
+        let (width, height) = (WIDTH * scale, HEIGHT * scale);
+        let file = BufWriter::new(File::create(path)?);
+        let sink = match path.extension().and_then(|e| e.to_str()) {
+            Some("gif") => {
+                let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                Sink::Gif {
+                    encoder,
+                    pending: None,
+                }
+            }
+            Some("y4m") => {
+                let mut file = file;
+                writeln!(
+                    file,
+                    "YUV4MPEG2 W{width} H{height} F{FRAME_RATE}:1 Ip A1:1 C444"
+                )?;
+                Sink::Y4m(file)
+            }
+            _ => return Err(RecordError::UnknownFormat),
+        };
+        Ok(Recorder {
+            sink,
+            scale,
+            frames: 0,
+        })
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn push(&mut self, pixels: &[u32]) -> Result<(), RecordError> {
+        let now = centiseconds(self.frames);
+        self.frames += 1;
+        match &mut self.sink {
+            Sink::Gif { encoder, pending } => match pending {
+                Some((last, _)) if last == pixels => Ok(()),
+                Some((last, start)) if now - *start < MIN_GIF_DELAY => {
+                    last.copy_from_slice(pixels);
+                    Ok(())
+                }
+                _ => {
+                    if let Some((last, start)) = pending.take() {
+                        write_gif_frame(encoder, &last, self.scale, now - start)?;
+                    }
+                    *pending = Some((pixels.to_vec(), now));
+                    Ok(())
+                }
+            },
+            Sink::Y4m(file) => {
+                file.write_all(b"FRAME\n")?;
+                let planes = ycbcr_planes(&scale(pixels, self.scale));
+                for plane in planes {
+                    file.write_all(&plane)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes out the last frame and flushes the file.
+    pub fn finish(self) -> Result<(), RecordError> {
+        let end = centiseconds(self.frames);
+        match self.sink {
+            Sink::Gif {
+                mut encoder,
+                pending,
+            } => {
+                if let Some((last, start)) = pending {
+                    let delay = (end - start).max(MIN_GIF_DELAY);
+                    write_gif_frame(&mut encoder, &last, self.scale, delay)?;
+                }
+                encoder.into_inner()?.flush()?;
+            }
+            Sink::Y4m(mut file) => file.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// When frame `n` starts, in the centiseconds GIF delays are counted in.
+fn centiseconds(n: u64) -> u64 {
+    n * 100 / FRAME_RATE as u64
+}
+
+fn scale(pixels: &[u32], scale: usize) -> Vec<u32> {
+    let mut out = Vec::with_capacity(pixels.len() * scale * scale);
+    for row in pixels.chunks_exact(WIDTH) {
+        for _ in 0..scale {
+            for p in row {
+                out.extend(std::iter::repeat_n(*p, scale));
+            }
+        }
+    }
+    out
+}
+
+fn write_gif_frame(
+    encoder: &mut gif::Encoder<BufWriter<File>>,
+    pixels: &[u32],
+    scale_by: usize,
+    delay: u64,
+) -> Result<(), RecordError> {
+    let (width, height) = ((WIDTH * scale_by) as u16, (HEIGHT * scale_by) as u16);
+    let scaled = scale(pixels, scale_by);
+
+    let mut colours: HashMap<u32, u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(scaled.len());
+    for p in &scaled {
+        let next = colours.len();
+        if next > 255 && !colours.contains_key(p) {
+            break;
+        }
+        let i = *colours.entry(*p).or_insert_with(|| {
+            palette.extend_from_slice(&[(p >> 16) as u8, (p >> 8) as u8, *p as u8]);
+            next as u8
+        });
+        indices.push(i);
+    }
+
+    let mut frame = if indices.len() == scaled.len() {
+        gif::Frame::from_palette_pixels(width, height, indices, palette, None)
+    } else {
+        // too many colours for one palette, let the encoder quantize
+        let rgb: Vec<u8> = scaled
+            .iter()
+            .flat_map(|p| [(p >> 16) as u8, (p >> 8) as u8, *p as u8])
+            .collect();
+        gif::Frame::from_rgb_speed(width, height, &rgb, 10)
+    };
+    frame.delay = delay.min(u16::MAX as u64) as u16;
+    encoder.write_frame(&frame)?;
+    Ok(())
+}
+
+/// BT.601 studio range Y, Cb and Cr planes.
+fn ycbcr_planes(pixels: &[u32]) -> [Vec<u8>; 3] {
+    let mut planes = [
+        Vec::with_capacity(pixels.len()),
+        Vec::with_capacity(pixels.len()),
+        Vec::with_capacity(pixels.len()),
+    ];
+    for p in pixels {
+        let (r, g, b) = (
+            ((p >> 16) & 0xFF) as f32,
+            ((p >> 8) & 0xFF) as f32,
+            (p & 0xFF) as f32,
+        );
+        let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+        let cb = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+        let cr = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+        planes[0].push(y.round() as u8);
+        planes[1].push(cb.round() as u8);
+        planes[2].push(cr.round() as u8);
+    }
+    planes
+}
+
+#[cfg(test)]
+mod test {
+    use super::{centiseconds, Recorder};
+    use crate::internals::display::{HEIGHT, WIDTH};
+    use std::fs::{self, File};
+
+    fn frame(lit: usize) -> Vec<u32> {
+        let mut f = vec![0; WIDTH * HEIGHT];
+        f[lit] = 0x0000FFFF;
+        f
+    }
+
+    #[test]
+    fn test_sixty_frames_last_a_second() {
+        assert_eq!(centiseconds(60), 100);
+        assert_eq!(centiseconds(3), 5);
+    }
+
+    #[test]
+    fn test_gif_keeps_60hz_timing() {
+        let path = crate::temp_dir().join("chip8-test-recording.gif");
+        let mut r = Recorder::create(&path, 1).unwrap();
+        // a pixel moving every 3 frames for a second
+        for n in 0..60 {
+            r.push(&frame(n / 3)).unwrap();
+        }
+        r.finish().unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(f) = decoder.read_next_frame().unwrap() {
+            assert_eq!((f.width as usize, f.height as usize), (WIDTH, HEIGHT));
+            delays.push(f.delay as u64);
+        }
+        assert_eq!(delays.len(), 20);
+        assert_eq!(delays.iter().sum::<u64>(), 100);
+    }
+
+    #[test]
+    fn test_y4m_has_every_frame() {
+        let path = crate::temp_dir().join("chip8-test-recording.y4m");
+        let mut r = Recorder::create(&path, 2).unwrap();
+        for n in 0..5 {
+            r.push(&frame(n)).unwrap();
+        }
+        r.finish().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+        assert!(data.starts_with(header));
+        let frame_size = b"FRAME\n".len() + 128 * 64 * 3;
+        assert_eq!(data.len(), header.len() + 5 * frame_size);
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        let path = crate::temp_dir().join("chip8-test-recording.avi");
+        assert!(Recorder::create(path, 1).is_err());
+    }
+}
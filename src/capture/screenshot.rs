@@ -0,0 +1,99 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Encodes 0RGB pixels, as the frame buffer and the window surface hold
+/// them, into an RGB PNG.
+pub fn encode_png(
+    w: impl Write,
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    let data: Vec<u8> = pixels
+        .iter()
+        .flat_map(|p| [(p >> 16) as u8, (p >> 8) as u8, *p as u8])
+        .collect();
+    writer.write_image_data(&data)?;
+    writer.finish()
+}
+
+pub fn save_png(
+    path: impl AsRef<Path>,
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+) -> Result<(), png::EncodingError> {
+    let file = BufWriter::new(File::create(path)?);
+    encode_png(file, pixels, width, height)
+}
+
+/// Decodes a PNG written by `encode_png` back into 0RGB pixels, along with
+/// its width and height.
+pub fn decode_png(path: impl AsRef<Path>) -> Result<(Vec<u32>, usize, usize), png::DecodingError> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    let channels = info.color_type.samples();
+    let pixels = data[..info.buffer_size()]
+        .chunks_exact(channels)
+        .map(|c| (c[0] as u32) << 16 | (c[1] as u32) << 8 | c[2] as u32)
+        .collect();
+    Ok((pixels, info.width as usize, info.height as usize))
+}
+
+/// Compares an image with the golden PNG at `path`. On a mismatch what we
+/// actually got is written next to it as `<name>.actual.png`, so a failing
+/// test leaves behind something to look at. Setting UPDATE_GOLDEN rewrites
+/// the golden image instead.
+pub fn check_golden(
+    path: impl AsRef<Path>,
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+) -> Result<(), String> {
+    let path = path.as_ref();
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        save_png(path, pixels, width, height).map_err(|e| e.to_string())?;
+    }
+    let matches = match decode_png(path) {
+        Ok((golden, w, h)) => (w, h) == (width, height) && golden == pixels,
+        Err(png::DecodingError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+    let actual = path.with_extension("actual.png");
+    if matches {
+        let _ = fs::remove_file(actual);
+        Ok(())
+    } else {
+        save_png(&actual, pixels, width, height).map_err(|e| e.to_string())?;
+        Err(format!(
+            "{} doesn't match, got {}",
+            path.display(),
+            actual.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_png, save_png};
+
+    #[test]
+    fn test_png_round_trip() {
+        let pixels: Vec<u32> = (0..64 * 32).map(|i| i * 0x010203).collect();
+        let path = crate::temp_dir().join("chip8-test-round-trip.png");
+        save_png(&path, &pixels, 64, 32).unwrap();
+        let (decoded, w, h) = decode_png(&path).unwrap();
+        assert_eq!((w, h), (64, 32));
+        assert_eq!(
+            decoded,
+            pixels.iter().map(|p| p & 0x00FFFFFF).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_LEN: u32 = 44;
+
+/// Writes mono 16 bit PCM. The header is patched after every write, so the
+/// file is valid even if we never get to close it cleanly.
+pub struct WavWriter<W: Write + Seek> {
+    w: W,
+    samples: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut w: W, sample_rate: u32) -> io::Result<Self> {
+        w.write_all(b"RIFF")?;
+        w.write_all(&(HEADER_LEN - 8).to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?; // PCM
+        w.write_all(&1u16.to_le_bytes())?; // mono
+        w.write_all(&sample_rate.to_le_bytes())?;
+        w.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+        w.write_all(&2u16.to_le_bytes())?; // bytes per sample
+        w.write_all(&16u16.to_le_bytes())?; // bits per sample
+        w.write_all(b"data")?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.flush()?;
+        Ok(WavWriter { w, samples: 0 })
+    }
+
+    pub fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        for s in samples {
+            self.w.write_all(&s.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+
+        let data_len = self.samples * 2;
+        self.w.seek(SeekFrom::Start(4))?;
+        self.w
+            .write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+        self.w.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+        self.w.write_all(&data_len.to_le_bytes())?;
+        self.w.seek(SeekFrom::End(0))?;
+        self.w.flush()
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WavWriter;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_header_tracks_data() {
+        let mut w = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        w.write(&[1, -1, 2]).unwrap();
+        w.write(&[3]).unwrap();
+        let data = w.into_inner().into_inner();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 36 + 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 44100);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 8);
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[44..46], &1i16.to_le_bytes());
+        assert_eq!(&data[46..48], &(-1i16).to_le_bytes());
+    }
+}
@@ -0,0 +1,295 @@
+pub mod octo;
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::database::{colour, Settings};
+use crate::internals::memory::{read_rom, LoadError};
+
+use octo::AsmError;
+
+/// Why a cartridge couldn't be unpacked.
+#[derive(Debug)]
+pub enum CartridgeError {
+    Gif(gif::DecodingError),
+    /// The pixels ran out before the payload did.
+    Truncated,
+    Json(serde_json::Error),
+    Assembly(AsmError),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Gif(e) => write!(f, "not a readable GIF: {e}"),
+            CartridgeError::Truncated => write!(f, "the cartridge is cut short"),
+            CartridgeError::Json(e) => write!(f, "the cartridge's payload is broken: {e}"),
+            CartridgeError::Assembly(e) => write!(f, "the cartridge's program: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+/// The options Octo saves alongside a program. Only the ones this
+/// emulator can act on are here.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Options {
+    pub tickrate: Option<usize>,
+    pub fill_color: Option<String>,
+    pub background_color: Option<String>,
+    pub v_blank_quirks: Option<bool>,
+    pub font_style: Option<String>,
+}
+
+impl Options {
+    pub fn apply(&self, s: &mut Settings) {
+        if let Some(t) = self.tickrate {
+            s.instructions_per_frame = t;
+        }
+        if let Some(on) = self.fill_color.as_deref().and_then(colour) {
+            s.palette.on = on;
+        }
+        if let Some(off) = self.background_color.as_deref().and_then(colour) {
+            s.palette.off = off;
+        }
+        if let Some(wait) = self.v_blank_quirks {
+            s.quirks.display_wait = wait;
+        }
+        if let Some(set) = self.font_style.as_deref().and_then(|f| f.parse().ok()) {
+            s.font.set = set;
+        }
+    }
+}
+
+/// An Octo cartridge: an animated GIF with the program's source and options
+/// hidden in the low two bits of every pixel's palette index.
+pub struct Cartridge {
+    pub source: String,
+    pub program: Vec<u8>,
+    pub options: Options,
+}
+
+impl Cartridge {
+    pub fn decode(gif: &[u8]) -> Result<Self, CartridgeError> {
+        #[derive(Deserialize)]
+        struct Payload {
+            program: String,
+            #[serde(default)]
+            options: Options,
+        }
+
+        let payload = payload(gif)?;
+        let payload: Payload = serde_json::from_slice(&payload).map_err(CartridgeError::Json)?;
+        Ok(Cartridge {
+            program: octo::assemble(&payload.program).map_err(CartridgeError::Assembly)?,
+            source: payload.program,
+            options: payload.options,
+        })
+    }
+}
+
+pub fn is_cartridge(file: &[u8]) -> bool {
+    file.starts_with(b"GIF87a") || file.starts_with(b"GIF89a")
+}
+
+/// Reads a ROM file, unpacking it first if it's a cartridge. Cartridges
+/// come with the options they were saved with.
+pub fn read_program(path: impl AsRef<Path>) -> Result<(Vec<u8>, Option<Options>), LoadError> {
+    let mut magic = Vec::new();
+    File::open(&path)?.take(6).read_to_end(&mut magic)?;
+    if !is_cartridge(&magic) {
+        return Ok((read_rom(path)?, None));
+    }
+    unpack(&std::fs::read(path)?)
+}
+
+/// Like `read_program`, for a file that's already been read.
+pub fn unpack(file: &[u8]) -> Result<(Vec<u8>, Option<Options>), LoadError> {
+    if !is_cartridge(file) {
+        return Ok((file.to_vec(), None));
+    }
+    let c = Cartridge::decode(file).map_err(|e| LoadError::Format(e.to_string()))?;
+    Ok((c.program, Some(c.options)))
+}
+
+/// The bytes in the pixels, four pixels to a byte with the first pixel on
+/// top, frame after frame. A big endian length comes first.
+fn payload(gif: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(gif).map_err(CartridgeError::Gif)?;
+
+    let mut bytes = Vec::new();
+    let mut byte = 0u8;
+    let mut dibits = 0;
+    while let Some(frame) = decoder.read_next_frame().map_err(CartridgeError::Gif)? {
+        for index in frame.buffer.iter() {
+            byte = (byte << 2) | (index & 3);
+            dibits += 1;
+            if dibits == 4 {
+                bytes.push(byte);
+                dibits = 0;
+            }
+        }
+    }
+
+    let (len, rest) = bytes
+        .split_first_chunk::<4>()
+        .ok_or(CartridgeError::Truncated)?;
+    let len = u32::from_be_bytes(*len) as usize;
+    rest.get(..len)
+        .map(<[u8]>::to_vec)
+        .ok_or(CartridgeError::Truncated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_cartridge, read_program, Cartridge};
+    use crate::database::Settings;
+    use crate::internals::{display::Palette, font::FontSet, Chip8};
+
+    #[test]
+    fn test_decode_sample() {
+        let gif = std::fs::read("./data/cartridges/bounce.gif").unwrap();
+        assert!(is_cartridge(&gif));
+        let c = Cartridge::decode(&gif).unwrap();
+        assert!(c.source.contains(": main"));
+        assert_eq!(&c.program[..2], &[0x12, 0x14]);
+
+        let mut s = Settings::of(&Chip8::new(), 10);
+        c.options.apply(&mut s);
+        assert_eq!(s.instructions_per_frame, 15);
+        assert_eq!(
+            s.palette,
+            Palette {
+                off: 0x996600,
+                on: 0xFFCC00
+            }
+        );
+        assert!(s.quirks.display_wait);
+        assert_eq!(s.font.set, FontSet::Vip);
+    }
+
+    #[test]
+    fn test_read_program() {
+        let (rom, options) = read_program("./data/cartridges/bounce.gif").unwrap();
+        assert!(options.is_some());
+        assert_eq!(&rom[..2], &[0x12, 0x14]);
+
+        let (rom, options) = read_program("./data/test.ch8").unwrap();
+        assert!(options.is_none());
+        assert_eq!(rom, std::fs::read("./data/test.ch8").unwrap());
+    }
+
+    /// The options Octo saves, all of them, as it wrote them out.
+    const OCTO_OPTIONS: &str = r##"{"tickrate":15,"fillColor":"#FFCC00","fillColor2":"#FF6600","blendColor":"#662200","backgroundColor":"#996600","buzzColor":"#FFAA00","quietColor":"#000000","shiftQuirks":false,"loadStoreQuirks":false,"vfOrderQuirks":false,"clipQuirks":false,"vBlankQuirks":true,"jumpQuirks":false,"screenRotation":0,"maxSize":3584,"touchInputMode":"none","fontStyle":"vip"}"##;
+
+    /// A cartridge the way Octo lays one out: 128x64 frames, each palette
+    /// index a label colour in the high bits and two bits of payload in
+    /// the low. `len` goes in the header, whatever the payload's length.
+    fn encode(payload: &[u8], len: u32) -> Vec<u8> {
+        const W: usize = 128;
+        const H: usize = 64;
+        let colours = [0x996600u32, 0xFFCC00, 0x662200, 0x000000];
+        let palette: Vec<u8> = colours
+            .iter()
+            .flat_map(|c| {
+                (0..4).flat_map(move |i| (c ^ (i * 0x010101)).to_be_bytes()[1..].to_vec())
+            })
+            .collect();
+        // the label: a border round the background
+        let label: Vec<u8> = (0..W * H)
+            .map(|p| (p % W == 0 || p % W == W - 1 || !(W..W * (H - 1)).contains(&p)) as u8)
+            .collect();
+
+        let mut bytes = len.to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        let frame_bytes = W * H / 4;
+        bytes.resize(bytes.len().div_ceil(frame_bytes) * frame_bytes, 0);
+
+        let mut gif = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut gif, W as u16, H as u16, &palette).unwrap();
+        encoder.set_repeat(gif::Repeat::Infinite).unwrap();
+        for chunk in bytes.chunks(frame_bytes) {
+            let pixels: Vec<u8> = chunk
+                .iter()
+                .flat_map(|b| [b >> 6, b >> 4, b >> 2, *b].map(|d| d & 3))
+                .zip(&label)
+                .map(|(d, l)| l << 2 | d)
+                .collect();
+            let mut frame = gif::Frame::from_indexed_pixels(W as u16, H as u16, pixels, None);
+            frame.delay = 10;
+            encoder.write_frame(&frame).unwrap();
+        }
+        drop(encoder);
+        gif
+    }
+
+    /// The cartridges under data/cartridges are made here, from
+    /// bounce.8o; see the README there. Setting UPDATE_GOLDEN rewrites
+    /// them.
+    #[test]
+    fn test_fixtures_are_current() {
+        let source = std::fs::read_to_string("./data/cartridges/bounce.8o").unwrap();
+        let payload = format!(
+            r#"{{"program":{},"options":{OCTO_OPTIONS}}}"#,
+            serde_json::to_string(&source).unwrap()
+        );
+        let payload = payload.as_bytes();
+        for (name, len) in [
+            ("bounce.gif", payload.len() as u32),
+            ("truncated.gif", 100_000),
+        ] {
+            let path = std::path::Path::new("./data/cartridges").join(name);
+            let gif = encode(payload, len);
+            if std::env::var_os("UPDATE_GOLDEN").is_some() {
+                std::fs::write(&path, &gif).unwrap();
+            }
+            assert!(
+                std::fs::read(&path).unwrap() == gif,
+                "{} is out of date, run the tests with UPDATE_GOLDEN set",
+                path.display()
+            );
+        }
+    }
+
+    /// A cartridge Octo saved itself, which `encode` can't stand in for:
+    /// it shows the decoder reads Octo's layout, not just our own.
+    #[test]
+    #[ignore = "needs data/cartridges/octo-bounce.gif, saved from Octo; see the README there"]
+    fn test_octo_export() {
+        let gif = std::fs::read("./data/cartridges/octo-bounce.gif").unwrap();
+        assert!(is_cartridge(&gif));
+        let c = Cartridge::decode(&gif).unwrap();
+        let source = std::fs::read_to_string("./data/cartridges/bounce.8o").unwrap();
+        // Octo may tidy the line endings
+        assert_eq!(
+            c.source.lines().collect::<Vec<_>>(),
+            source.lines().collect::<Vec<_>>()
+        );
+        assert_eq!(c.program, super::octo::assemble(&source).unwrap());
+        assert_eq!(
+            c.options,
+            super::Options {
+                tickrate: Some(15),
+                fill_color: Some("#FFCC00".to_string()),
+                background_color: Some("#996600".to_string()),
+                v_blank_quirks: Some(true),
+                font_style: Some("vip".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_broken_cartridges() {
+        let gif = std::fs::read("./data/cartridges/truncated.gif").unwrap();
+        assert!(Cartridge::decode(&gif).is_err());
+        assert!(read_program("./data/cartridges/truncated.gif").is_err());
+    }
+}
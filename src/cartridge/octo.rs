@@ -0,0 +1,566 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::internals::memory::{MEMORY_SIZE, PROGRAM_START};
+
+/// Why some Octo source didn't assemble.
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles Octo source into a ROM to load at 0x200. This covers the core
+/// of the language: labels, constants, aliases, structured control flow
+/// and the CHIP-8 and SCHIP instructions. Macros, `:calc`, the `<`-style
+/// comparisons and XO-CHIP's additions aren't supported.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(n, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace().map(move |t| (n + 1, t))
+        })
+        .collect();
+    Assembler::new(tokens).run()
+}
+
+/// Something to fill in once a label's address is known.
+enum Fixup {
+    /// The low 12 bits of the instruction here.
+    Address,
+    /// `:unpack`'s pair of loads, with this nibble on top.
+    Unpack(u8),
+}
+
+/// Open `begin`/`else` blocks and loops, with the jumps that need to land
+/// at their end.
+enum Block {
+    If(usize),
+    Else(usize),
+    Loop { start: usize, breaks: Vec<usize> },
+}
+
+/// A test `if` and `while` can make, as the skip that goes past the next
+/// instruction when it holds and the one that skips when it doesn't.
+struct Condition {
+    when_true: u16,
+    when_false: u16,
+}
+
+struct Assembler<'a> {
+    tokens: Vec<(usize, &'a str)>,
+    next: usize,
+    line: usize,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    labels: HashMap<&'a str, usize>,
+    constants: HashMap<&'a str, u16>,
+    aliases: HashMap<&'a str, u8>,
+    fixups: Vec<(usize, &'a str, Fixup, usize)>,
+    blocks: Vec<Block>,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(tokens: Vec<(usize, &'a str)>) -> Self {
+        Assembler {
+            tokens,
+            next: 0,
+            line: 0,
+            memory: vec![0; MEMORY_SIZE],
+            here: PROGRAM_START,
+            end: PROGRAM_START,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        // like Octo, start with a jump to main when there is one
+        let has_main = self
+            .tokens
+            .windows(2)
+            .any(|w| w[0].1 == ":" && w[1].1 == "main");
+        if has_main {
+            self.jump_to(0x1000, "main")?;
+        }
+        while let Some(t) = self.token() {
+            self.statement(t)?;
+        }
+        if !self.blocks.is_empty() {
+            return self.error("a block is missing its end or again");
+        }
+        for (at, name, fixup, line) in std::mem::take(&mut self.fixups) {
+            let Some(&address) = self.labels.get(name) else {
+                self.line = line;
+                return self.error(format!("undefined name {name}"));
+            };
+            match fixup {
+                Fixup::Address => {
+                    let op = u16::from_be_bytes([self.memory[at], self.memory[at + 1]]);
+                    let op = (op & 0xF000) | (address as u16 & 0xFFF);
+                    self.memory[at..at + 2].copy_from_slice(&op.to_be_bytes());
+                }
+                Fixup::Unpack(nibble) => {
+                    self.memory[at + 1] = (nibble << 4) | (address >> 8) as u8;
+                    self.memory[at + 3] = address as u8;
+                }
+            }
+        }
+        self.memory.truncate(self.end);
+        Ok(self.memory.split_off(PROGRAM_START))
+    }
+
+    fn statement(&mut self, t: &'a str) -> Result<(), AsmError> {
+        match t {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.insert(name, self.here).is_some() {
+                    return self.error(format!("{name} is already defined"));
+                }
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.number()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":org" => {
+                let address = self.number()? as usize;
+                if !(PROGRAM_START..MEMORY_SIZE).contains(&address) {
+                    return self.error(format!("can't assemble at {address:#x}"));
+                }
+                self.here = address;
+            }
+            ":byte" => {
+                let b = self.byte()?;
+                self.emit_byte(b)?;
+            }
+            ":call" => {
+                let name = self.name()?;
+                self.jump_to(0x2000, name)?;
+            }
+            ":unpack" => {
+                let nibble = self.number()? as u8 & 0xF;
+                let name = self.name()?;
+                self.fixups
+                    .push((self.here, name, Fixup::Unpack(nibble), self.line));
+                self.emit(0x6000)?;
+                self.emit(0x6100)?;
+            }
+            ":proto" | ":breakpoint" => {
+                self.name()?;
+            }
+            ":monitor" => {
+                self.token();
+                self.token();
+            }
+            "clear" => self.emit(0x00E0)?,
+            "return" | ";" => self.emit(0x00EE)?,
+            "hires" => self.emit(0x00FF)?,
+            "lores" => self.emit(0x00FE)?,
+            "exit" => self.emit(0x00FD)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)?;
+            }
+            "scroll-right" => self.emit(0x00FB)?,
+            "scroll-left" => self.emit(0x00FC)?,
+            "jump" => self.address_op(0x1000)?,
+            "jump0" => self.address_op(0xB000)?,
+            "native" => self.address_op(0x0000)?,
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.nibble()?;
+                self.emit(0xD000 | (x << 8) | (y << 4) | n)?;
+            }
+            "bcd" => self.register_op(0xF033)?,
+            "save" => self.register_op(0xF055)?,
+            "load" => self.register_op(0xF065)?,
+            "saveflags" => self.register_op(0xF075)?,
+            "loadflags" => self.register_op(0xF085)?,
+            "delay" => {
+                self.expect(":=")?;
+                self.register_op(0xF015)?;
+            }
+            "buzzer" => {
+                self.expect(":=")?;
+                self.register_op(0xF018)?;
+            }
+            "i" => self.i()?,
+            "if" => {
+                let c = self.condition()?;
+                match self.token() {
+                    Some("then") => self.emit(c.when_false)?,
+                    Some("begin") => {
+                        self.emit(c.when_true)?;
+                        self.blocks.push(Block::If(self.here));
+                        self.emit(0x1000)?;
+                    }
+                    _ => return self.error("if needs then or begin"),
+                }
+            }
+            "else" => {
+                let Some(Block::If(jump)) = self.blocks.pop() else {
+                    return self.error("else without if ... begin");
+                };
+                self.blocks.push(Block::Else(self.here));
+                self.emit(0x1000)?;
+                self.patch(jump, self.here);
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If(jump) | Block::Else(jump)) => self.patch(jump, self.here),
+                _ => return self.error("end without begin"),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here,
+                breaks: Vec::new(),
+            }),
+            "while" => {
+                let c = self.condition()?;
+                self.emit(c.when_true)?;
+                let jump = self.here;
+                self.emit(0x1000)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|b| matches!(b, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return self.error("while outside of a loop"),
+                }
+            }
+            "again" => {
+                let Some(Block::Loop { start, breaks }) = self.blocks.pop() else {
+                    return self.error("again without loop");
+                };
+                self.emit(0x1000 | start as u16)?;
+                for b in breaks {
+                    self.patch(b, self.here);
+                }
+            }
+            _ if t.starts_with(':') => {
+                return self.error(format!("{t} isn't supported"));
+            }
+            _ if self.is_register(t) => self.register_statement(t)?,
+            _ if self.labels.contains_key(t) => self.jump_to(0x2000, t)?,
+            _ => match self.value(t) {
+                Some(n) => self.emit_byte(n as u8)?,
+                // anything else names a subroutine
+                None => self.jump_to(0x2000, t)?,
+            },
+        }
+        Ok(())
+    }
+
+    fn i(&mut self) -> Result<(), AsmError> {
+        match self.token() {
+            Some(":=") => match self.peek() {
+                Some("hex") => {
+                    self.token();
+                    self.register_op(0xF029)
+                }
+                Some("bighex") => {
+                    self.token();
+                    self.register_op(0xF030)
+                }
+                _ => self.address_op(0xA000),
+            },
+            Some("+=") => self.register_op(0xF01E),
+            _ => self.error("i only takes := and +="),
+        }
+    }
+
+    fn register_statement(&mut self, t: &'a str) -> Result<(), AsmError> {
+        let x = self.register_named(t)? as u16;
+        let op = self.token().unwrap_or_default();
+        let rhs = self.token().unwrap_or_default();
+        let y = self
+            .register_named(rhs)
+            .ok()
+            .map(|y| (x << 8) | ((y as u16) << 4));
+        let code = match (op, y) {
+            (":=", Some(xy)) => 0x8000 | xy,
+            ("|=", Some(xy)) => 0x8001 | xy,
+            ("&=", Some(xy)) => 0x8002 | xy,
+            ("^=", Some(xy)) => 0x8003 | xy,
+            ("+=", Some(xy)) => 0x8004 | xy,
+            ("-=", Some(xy)) => 0x8005 | xy,
+            (">>=", Some(xy)) => 0x8006 | xy,
+            ("=-", Some(xy)) => 0x8007 | xy,
+            ("<<=", Some(xy)) => 0x800E | xy,
+            (":=", None) => match rhs {
+                "random" => {
+                    let mask = self.byte()?;
+                    0xC000 | (x << 8) | mask as u16
+                }
+                "key" => 0xF00A | (x << 8),
+                "delay" => 0xF007 | (x << 8),
+                _ => 0x6000 | (x << 8) | self.byte_from(rhs)? as u16,
+            },
+            ("+=", None) => 0x7000 | (x << 8) | self.byte_from(rhs)? as u16,
+            ("-=", None) => 0x7000 | (x << 8) | self.byte_from(rhs)?.wrapping_neg() as u16,
+            _ => return self.error(format!("can't do {t} {op} {rhs}")),
+        };
+        self.emit(code)
+    }
+
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.register()? as u16;
+        let op = self.token().unwrap_or_default();
+        let (when_true, when_false) = match op {
+            "key" => (0xE09E, 0xE0A1),
+            "-key" => (0xE0A1, 0xE09E),
+            "==" | "!=" => {
+                let rhs = self.token().unwrap_or_default();
+                let (equal, differ) = match self.register_named(rhs) {
+                    Ok(y) => (0x5000 | ((y as u16) << 4), 0x9000 | ((y as u16) << 4)),
+                    Err(_) => {
+                        let n = self.byte_from(rhs)? as u16;
+                        (0x3000 | n, 0x4000 | n)
+                    }
+                };
+                match op {
+                    "==" => (equal, differ),
+                    _ => (differ, equal),
+                }
+            }
+            _ => return self.error(format!("can't test {op}")),
+        };
+        Ok(Condition {
+            when_true: when_true | (x << 8),
+            when_false: when_false | (x << 8),
+        })
+    }
+
+    fn address_op(&mut self, op: u16) -> Result<(), AsmError> {
+        let t = self.token_or("an address")?;
+        match self.value(t) {
+            Some(n) => self.emit(op | (n & 0xFFF)),
+            None => self.jump_to(op, t),
+        }
+    }
+
+    fn register_op(&mut self, op: u16) -> Result<(), AsmError> {
+        let x = self.register()? as u16;
+        self.emit(op | (x << 8))
+    }
+
+    /// Emits `op` with `name`'s address, filled in later if need be.
+    fn jump_to(&mut self, op: u16, name: &'a str) -> Result<(), AsmError> {
+        self.fixups
+            .push((self.here, name, Fixup::Address, self.line));
+        self.emit(op)
+    }
+
+    fn patch(&mut self, at: usize, address: usize) {
+        self.memory[at] = 0x10 | (address >> 8) as u8;
+        self.memory[at + 1] = address as u8;
+    }
+
+    fn emit(&mut self, op: u16) -> Result<(), AsmError> {
+        let [hi, lo] = op.to_be_bytes();
+        self.emit_byte(hi)?;
+        self.emit_byte(lo)
+    }
+
+    fn emit_byte(&mut self, b: u8) -> Result<(), AsmError> {
+        if self.here >= MEMORY_SIZE {
+            return self.error("the program doesn't fit in memory");
+        }
+        self.memory[self.here] = b;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn token(&mut self) -> Option<&'a str> {
+        let (line, t) = *self.tokens.get(self.next)?;
+        self.next += 1;
+        self.line = line;
+        Some(t)
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.next).map(|(_, t)| *t)
+    }
+
+    fn token_or(&mut self, what: &str) -> Result<&'a str, AsmError> {
+        match self.token() {
+            Some(t) => Ok(t),
+            None => self.error(format!("expected {what}")),
+        }
+    }
+
+    fn expect(&mut self, want: &str) -> Result<(), AsmError> {
+        match self.token() {
+            Some(t) if t == want => Ok(()),
+            _ => self.error(format!("expected {want}")),
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, AsmError> {
+        self.token_or("a name")
+    }
+
+    fn is_register(&self, t: &str) -> bool {
+        self.register_named(t).is_ok()
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let t = self.token_or("a register")?;
+        self.register_named(t)
+    }
+
+    fn register_named(&self, t: &str) -> Result<u8, AsmError> {
+        if let Some(r) = self.aliases.get(t) {
+            return Ok(*r);
+        }
+        match t.strip_prefix(['v', 'V']) {
+            Some(n) if n.len() == 1 => u8::from_str_radix(n, 16)
+                .map_err(|_| self.error_at(format!("{t} isn't a register"))),
+            _ => Err(self.error_at(format!("{t} isn't a register"))),
+        }
+    }
+
+    fn number(&mut self) -> Result<u16, AsmError> {
+        let t = self.token_or("a number")?;
+        match self.value(t) {
+            Some(n) => Ok(n),
+            None => self.error(format!("{t} isn't a number")),
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u16, AsmError> {
+        Ok(self.number()? & 0xF)
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        let t = self.token_or("a number")?;
+        self.byte_from(t)
+    }
+
+    fn byte_from(&self, t: &str) -> Result<u8, AsmError> {
+        match self.value(t) {
+            Some(n) if n <= 0xFF || n >= 0xFF80 => Ok(n as u8),
+            Some(_) => Err(self.error_at(format!("{t} doesn't fit in a byte"))),
+            None => Err(self.error_at(format!("{t} isn't a number"))),
+        }
+    }
+
+    /// A number, constant or label that's already been defined.
+    fn value(&self, t: &str) -> Option<u16> {
+        if let Some(n) = self.constants.get(t) {
+            return Some(*n);
+        }
+        if let Some(a) = self.labels.get(t) {
+            return Some(*a as u16);
+        }
+        let (negative, digits) = match t.strip_prefix('-') {
+            Some(d) => (true, d),
+            None => (false, t),
+        };
+        let n = if let Some(hex) = digits.strip_prefix("0x") {
+            u16::from_str_radix(hex, 16).ok()?
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            u16::from_str_radix(bin, 2).ok()?
+        } else {
+            digits.parse().ok()?
+        };
+        Some(if negative { n.wrapping_neg() } else { n })
+    }
+
+    fn error_at(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, AsmError> {
+        Err(self.error_at(message))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::assemble;
+
+    #[test]
+    fn test_instructions() {
+        let rom = assemble(
+            "clear
+            v0 := 5  v1 += -1  v2 := v3  v4 <<= v5
+            i := hex v0  sprite v0 v1 5  v6 := random 0xFF
+            delay := v7  vf := key  bcd v8  load v9  ;",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            [
+                0x00, 0xE0, 0x60, 0x05, 0x71, 0xFF, 0x82, 0x30, 0x84, 0x5E, 0xF0, 0x29, 0xD0, 0x15,
+                0xC6, 0xFF, 0xF7, 0x15, 0xFF, 0x0A, 0xF8, 0x33, 0xF9, 0x65, 0x00, 0xEE
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_and_control_flow() {
+        let rom = assemble(
+            ":const SPEED 3
+            :alias x v2
+            : draw  sprite x x 1  return
+            : main
+              i := dot
+              loop
+                x += SPEED
+                if x == 60 then x := 0
+                if vf != 0 begin draw else clear end
+                while x key
+              again
+            : dot  0x80",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            [
+                0x12, 0x06, // jump main
+                0xD2, 0x21, 0x00, 0xEE, // draw
+                0xA2, 0x1E, // main: i := dot
+                0x72, 0x03, // loop: 0x208
+                0x42, 0x3C, 0x62, 0x00, // if ... then
+                0x4F, 0x00, 0x12, 0x16, 0x22, 0x02, 0x12, 0x18, 0x00, 0xE0, // begin else end
+                0xE2, 0x9E, 0x12, 0x1E, // while
+                0x12, 0x08, // again
+                0x80, // dot
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(assemble("jump nowhere").unwrap_err().line, 1);
+        assert_eq!(assemble("\nv0 := 300").unwrap_err().line, 2);
+        assert!(assemble(":macro foo { }").is_err());
+        assert!(assemble("loop").is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use chip8::config::Config;
+use chip8::emulator::Reload;
+use chip8::gui::effects::EffectKind;
+use chip8::gui::phosphor::Persistence;
+use chip8::gui::present::RECORD_SCALE;
+use chip8::internals::font::Font;
+use chip8::internals::jit::Backend;
+use chip8::internals::memory::PROGRAM_START;
+use chip8::internals::quirks::Quirks;
+use chip8::internals::timing::Timing;
+
+const USAGE: &str = r#"usage: chip8 [OPTIONS] [ROM]
+
+ROM is a raw CHIP-8 program or an Octo cartridge .gif. Without one, the
+most recently loaded ROM runs.
+
+Defaults come from a TOML config: the user's, in the platform's config
+directory under chip8/config.toml, with ./chip8.toml on top of it.
+
+options:
+    --ipf <N>         instructions to run per 60 Hz frame (default 10)
+    --scale <N>       window pixels per CHIP-8 pixel (default 20)
+    --display-wait    Dxyn waits for vblank, like the COSMAC VIP
+    --font <SET>      hex digits to use: vip, dream6800, eti660, schip, or
+                      octo (default)
+    --font-base <ADDR>
+                      where the font goes, in decimal or 0x hex (default 0)
+    --load-address <ADDR>
+                      where programs go and start, like --font-base (default
+                      0x200, the ETI-660 used 0x600)
+    --timing <MODEL>  how much runs in a frame: instructions (default), as
+                      many as --ipf says, or vip, as long as each took on
+                      the COSMAC VIP, with Dxyn waiting for vblank
+    --backend <NAME>  how instructions run: interpreter (default), or jit to
+                      compile them into blocks first
+    --phosphor <MODE> flicker filter: off, max2, decay or decay:<0..1>
+    --effects <LIST>  comma separated post-processing stages, run in order:
+                      scanlines, grid, bloom, mask, curvature
+    --database <DIR>  a local copy of the CHIP-8 program database, to set up
+                      ROMs it knows (default ./data/chip-8-database), or off
+    --overrides <FILE>
+                      your own per-ROM settings, keyed by SHA-1 like the
+                      database (default ./data/overrides.json)
+    --reload <MODE>   when the ROM file changes: off, reset (default), or
+                      keep-ram to leave memory outside the program alone
+    --tui             draw to the terminal instead of a window
+    --headless        run without a window, then exit
+    --frames <N>      how many frames a headless run lasts (default 600)
+    --screenshot <PATH>
+                      save the last frame of a headless run as a PNG
+    --screenshot-native
+                      save screenshots at 64x32 instead of as displayed
+    --screenshot-dir <DIR>
+                      where F12 saves screenshots and F9 recordings (default .)
+    --record <PATH>   record every frame to a .gif, or raw to a .y4m
+    --record-scale <N>
+                      upscale recordings by this much (default 4)
+    --wav <PATH>      write the buzzer to a WAV file
+    --batch <FILE>    run the jobs in a JSON file side by side, each on its
+                      own machine, then exit; see below
+    --report <PATH>   where a batch's JSON report goes (default stdout)
+    --threads <N>     how many jobs run at once (default one per core)
+    -h, --help        print this message
+
+keys:
+    0-9, a-f          the keypad
+    arrows, enter, tab
+                      whatever the database binds them to for the game
+    space             pause and resume
+    F6, F7            while paused, step a frame or an instruction
+    page up/down      run faster or slower: 1/4x to 4x, then uncapped
+    F2, F4            warm and cold reset
+    F3                pick a ROM to load, or drop one on the window
+    F5, F8            save and load the quick save slot
+    F9                start or stop recording
+    F10               save the ROMs loaded since to the config
+    F12               screenshot
+    escape            quit
+
+batch jobs are a JSON list of objects, all but rom optional:
+    {"name": "pong", "rom": "data/pong.ch8", "seed": 1,
+     "frames": 600, "instructions": 100000, "instructions_per_frame": 15,
+     "halt": true, "until_lit": [0, 0], "screenshot": "pong.png",
+     "movie": [{"frame": 60, "key": 5, "down": true}]}
+    a job stops after its frames or instructions, once it halts (jumps to
+    itself, or waits for a key its movie never presses), or once the pixel
+    at until_lit lights up. Its seed makes Cxnn repeatable."#;
+
+pub struct Options {
+    pub rom: String,
+    pub instructions_per_frame: usize,
+    pub scale: usize,
+    pub quirks: Quirks,
+    pub font: Font,
+    pub load_address: usize,
+    pub backend: Backend,
+    pub timing: Timing,
+    pub persistence: Persistence,
+    pub effects: Vec<EffectKind>,
+    pub database: Option<String>,
+    pub overrides: String,
+    pub reload: Reload,
+    pub tui: bool,
+    pub headless: bool,
+    pub frames: usize,
+    pub screenshot: Option<String>,
+    pub screenshot_native: bool,
+    pub screenshot_dir: String,
+    pub record: Option<String>,
+    pub record_scale: usize,
+    pub wav: Option<String>,
+    pub batch: Option<String>,
+    pub report: Option<String>,
+    pub threads: Option<usize>,
+    /// What the defaults came from.
+    pub config: Config,
+}
+
+impl Options {
+    fn from_config(config: Config) -> Self {
+        Options {
+            rom: (config.recent.first())
+                .cloned()
+                .unwrap_or_else(|| "./data/pong.ch8".to_string()),
+            instructions_per_frame: config.instructions_per_frame,
+            scale: config.scale,
+            quirks: config.quirks(),
+            font: Font::default(),
+            load_address: PROGRAM_START,
+            backend: Backend::default(),
+            timing: Timing::default(),
+            // Config::load already turned away ones that don't parse
+            persistence: config.persistence().unwrap_or_default(),
+            effects: config.effects().unwrap_or_default(),
+            database: Some("./data/chip-8-database".to_string()),
+            overrides: "./data/overrides.json".to_string(),
+            reload: Reload::default(),
+            tui: false,
+            headless: false,
+            frames: 600,
+            screenshot: None,
+            screenshot_native: false,
+            screenshot_dir: ".".to_string(),
+            record: None,
+            record_scale: RECORD_SCALE,
+            wav: None,
+            batch: None,
+            report: None,
+            threads: None,
+            config,
+        }
+    }
+
+    /// Parses the process arguments over the config, exiting with the
+    /// usage message when they don't make sense.
+    pub fn from_env() -> Self {
+        let config = Config::load().unwrap_or_else(|e| {
+            eprintln!("ERR: {e}");
+            Config::default()
+        });
+        match Options::parse(std::env::args().skip(1), config) {
+            Ok(o) => o,
+            Err(e) => {
+                eprintln!("{e}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>, config: Config) -> Result<Self, String> {
+        let mut o = Options::from_config(config);
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--ipf" => o.instructions_per_frame = number(&arg, args.next())?,
+                "--scale" => o.scale = number(&arg, args.next())?,
+                "--display-wait" => o.quirks.display_wait = true,
+                "--font" => o.font.set = value(&arg, args.next())?.parse()?,
+                "--font-base" => o.font.base = address(&arg, args.next())?,
+                "--load-address" => o.load_address = address(&arg, args.next())?,
+                "--timing" => o.timing = value(&arg, args.next())?.parse()?,
+                "--backend" => o.backend = value(&arg, args.next())?.parse()?,
+                "--phosphor" => o.persistence = value(&arg, args.next())?.parse()?,
+                "--effects" => o.effects = EffectKind::parse_list(&value(&arg, args.next())?)?,
+                "--database" => o.database = Some(value(&arg, args.next())?).filter(|d| d != "off"),
+                "--overrides" => o.overrides = value(&arg, args.next())?,
+                "--reload" => o.reload = value(&arg, args.next())?.parse()?,
+                "--tui" => o.tui = true,
+                "--headless" => o.headless = true,
+                "--frames" => o.frames = number(&arg, args.next())?,
+                "--screenshot" => o.screenshot = Some(value(&arg, args.next())?),
+                "--screenshot-native" => o.screenshot_native = true,
+                "--screenshot-dir" => o.screenshot_dir = value(&arg, args.next())?,
+                "--record" => o.record = Some(value(&arg, args.next())?),
+                "--record-scale" => o.record_scale = number(&arg, args.next())?,
+                "--wav" => o.wav = Some(value(&arg, args.next())?),
+                "--batch" => o.batch = Some(value(&arg, args.next())?),
+                "--report" => o.report = Some(value(&arg, args.next())?),
+                "--threads" => o.threads = Some(number(&arg, args.next())?),
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+                _ => o.rom = arg,
+            }
+        }
+        if o.scale == 0 {
+            return Err("--scale can't be 0".to_string());
+        }
+        if o.font.base + o.font.size() > o.load_address {
+            return Err(format!(
+                "the {} font doesn't fit below {:#x} at {:#x}",
+                o.font.set, o.load_address, o.font.base
+            ));
+        }
+        Ok(o)
+    }
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("{flag} needs a value"))
+}
+
+fn address(flag: &str, v: Option<String>) -> Result<usize, String> {
+    let v = value(flag, v)?;
+    match v.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => v.parse(),
+    }
+    .map_err(|_| format!("{flag} needs an address"))
+}
+
+fn number<T: std::str::FromStr>(flag: &str, v: Option<String>) -> Result<T, String> {
+    value(flag, v)?
+        .parse()
+        .map_err(|_| format!("{flag} needs a number"))
+}
@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::database::colour;
+use crate::gui::effects::EffectKind;
+use crate::gui::phosphor::Persistence;
+use crate::gui::present::SCALE;
+use crate::internals::{
+    audio::Beeper,
+    display::Palette,
+    keypad::{Button, Control, Keymap},
+    quirks::Quirks,
+};
+
+/// Where a project's own config goes, next to wherever it's run from. It
+/// wins over the user's.
+pub const PROJECT_FILE: &str = "chip8.toml";
+
+/// How many ROMs `recent` remembers.
+pub const RECENT: usize = 10;
+
+/// The defaults the command line starts from, kept in TOML. Anything a file
+/// leaves out keeps its default, and the command line wins over all of it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub instructions_per_frame: usize,
+    /// Window pixels per CHIP-8 pixel.
+    pub scale: usize,
+    /// Most recently loaded first. The first one runs when no ROM is given.
+    pub recent: Vec<String>,
+    pub quirks: QuirkConfig,
+    pub palette: PaletteConfig,
+    /// Controls named the way the program database names them, to buttons.
+    pub keymap: BTreeMap<String, u8>,
+    pub audio: AudioConfig,
+    /// Post-processing stages by name, run in order.
+    pub effects: Vec<String>,
+    /// The flicker filter: off, max2, decay or decay:<0..1>.
+    pub phosphor: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuirkConfig {
+    pub display_wait: bool,
+}
+
+/// `#rrggbb` colours, the way the program database writes them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaletteConfig {
+    pub off: String,
+    pub on: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// Of the buzzer's square wave, in Hz.
+    pub frequency: u32,
+    /// From 0 to 1.
+    pub volume: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            instructions_per_frame: 10,
+            scale: SCALE,
+            recent: Vec::new(),
+            quirks: QuirkConfig::default(),
+            palette: PaletteConfig::default(),
+            keymap: BTreeMap::new(),
+            audio: AudioConfig::default(),
+            effects: Vec::new(),
+            phosphor: "off".to_string(),
+        }
+    }
+}
+
+impl Default for PaletteConfig {
+    fn default() -> Self {
+        let p = Palette::default();
+        PaletteConfig {
+            off: format!("#{:06x}", p.off),
+            on: format!("#{:06x}", p.on),
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        let b = Beeper::default();
+        AudioConfig {
+            frequency: b.frequency,
+            volume: b.volume as f32 / i16::MAX as f32,
+        }
+    }
+}
+
+impl Config {
+    /// The user's config, under the platform's config directory.
+    pub fn user_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("chip8").join("config.toml"))
+    }
+
+    /// Where the layers come from, the user's first.
+    fn paths() -> Vec<PathBuf> {
+        Config::user_path()
+            .into_iter()
+            .chain([PROJECT_FILE.into()])
+            .collect()
+    }
+
+    /// The user's config with the project's on top.
+    pub fn load() -> Result<Self, String> {
+        Config::load_from(&Config::paths())
+    }
+
+    /// The files at `paths`, later ones winning key by key. Missing files
+    /// are fine, broken ones aren't.
+    fn load_from(paths: &[PathBuf]) -> Result<Self, String> {
+        let mut merged = toml::Table::new();
+        for path in paths {
+            if let Some(layer) = read_layer(path)? {
+                merge(&mut merged, layer);
+            }
+        }
+        Config::checked(merged)
+    }
+
+    /// Layers parsed in order, later ones winning key by key.
+    pub fn parse(layers: &[&str]) -> Result<Self, String> {
+        let mut merged = toml::Table::new();
+        for s in layers {
+            merge(
+                &mut merged,
+                s.parse().map_err(|e| format!("bad config: {e}"))?,
+            );
+        }
+        Config::checked(merged)
+    }
+
+    /// The merged layers, as long as the effects and phosphor make sense.
+    fn checked(merged: toml::Table) -> Result<Self, String> {
+        let c = Config::deserialize(merged).map_err(|e| format!("bad config: {e}"))?;
+        c.effects().map_err(|e| format!("bad config: {e}"))?;
+        c.persistence().map_err(|e| format!("bad config: {e}"))?;
+        Ok(c)
+    }
+
+    /// Writes `recent`, the one thing that changes while running, back to
+    /// the layer that set it, or the user's if none did, returning where it
+    /// went. The rest of that file is left alone, so one layer's settings
+    /// never end up in another's.
+    pub fn save(&self) -> Result<PathBuf, String> {
+        self.save_to(&Config::paths())
+    }
+
+    fn save_to(&self, paths: &[PathBuf]) -> Result<PathBuf, String> {
+        let mut layers = Vec::new();
+        for path in paths {
+            layers.push((path, read_layer(path)?.unwrap_or_default()));
+        }
+        let (path, mut layer) = match layers.iter().rposition(|(_, l)| l.contains_key("recent")) {
+            Some(i) => layers.swap_remove(i),
+            None => layers.swap_remove(0),
+        };
+        let recent = self.recent.iter().map(|r| r.as_str().into()).collect();
+        layer.insert("recent".to_string(), toml::Value::Array(recent));
+        let toml = toml::to_string(&layer).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+        }
+        std::fs::write(path, toml).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(path.clone())
+    }
+
+    /// Puts `rom` at the front of `recent`.
+    pub fn add_recent(&mut self, rom: &str) {
+        self.recent.retain(|r| r != rom);
+        self.recent.insert(0, rom.to_string());
+        self.recent.truncate(RECENT);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        Quirks {
+            display_wait: self.quirks.display_wait,
+        }
+    }
+
+    /// Colours that aren't `#rrggbb` keep their defaults.
+    pub fn palette(&self) -> Palette {
+        let default = Palette::default();
+        Palette {
+            off: colour(&self.palette.off).unwrap_or(default.off),
+            on: colour(&self.palette.on).unwrap_or(default.on),
+        }
+    }
+
+    /// Controls that aren't known are left out.
+    pub fn keymap(&self) -> Keymap {
+        let mut keymap = Keymap::default();
+        for (name, key) in &self.keymap {
+            if let Ok(c) = name.parse::<Control>() {
+                keymap.bind(c, Button::from_u8(*key));
+            }
+        }
+        keymap
+    }
+
+    pub fn effects(&self) -> Result<Vec<EffectKind>, String> {
+        EffectKind::parse_list(&self.effects.join(","))
+    }
+
+    pub fn persistence(&self) -> Result<Persistence, String> {
+        self.phosphor.parse()
+    }
+
+    pub fn beeper(&self) -> Beeper {
+        let mut b = Beeper::default();
+        b.frequency = self.audio.frequency;
+        b.volume = (self.audio.volume.clamp(0.0, 1.0) * i16::MAX as f32) as i16;
+        b
+    }
+}
+
+/// The keys set in the file at `path`, or None if there isn't one.
+fn read_layer(path: &Path) -> Result<Option<toml::Table>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => s
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Tables merge key by key, anything else in `over` replaces what's there.
+fn merge(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(o)) => merge(b, o),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, RECENT};
+    use crate::gui::{effects::EffectKind, phosphor::Persistence};
+    use crate::internals::{
+        display::Palette,
+        keypad::{Button, Control},
+    };
+
+    #[test]
+    fn test_layers() {
+        let user = r##"
+            instructions_per_frame = 15
+            effects = ["scanlines"]
+            phosphor = "max2"
+            [palette]
+            off = "#102030"
+            on = "#f0e0d0"
+            [keymap]
+            up = 1
+        "##;
+        let project = r##"
+            scale = 8
+            effects = ["bloom", "grid"]
+            [palette]
+            on = "#ffffff"
+            [quirks]
+            display_wait = true
+        "##;
+        let c = Config::parse(&[user, project]).unwrap();
+        assert_eq!(c.instructions_per_frame, 15);
+        assert_eq!(c.scale, 8);
+        assert!(c.quirks().display_wait);
+        assert_eq!(
+            c.palette(),
+            Palette {
+                off: 0x102030,
+                on: 0xFFFFFF
+            }
+        );
+        assert_eq!(c.keymap().get(Control::Up), Some(Button::B1));
+        assert_eq!(c.audio, Config::default().audio);
+        assert_eq!(
+            c.effects(),
+            Ok(vec![EffectKind::Bloom, EffectKind::PixelGrid])
+        );
+        assert_eq!(c.persistence(), Ok(Persistence::MaxOfTwo));
+
+        assert_eq!(Config::parse(&[]).unwrap(), Config::default());
+        assert!(Config::parse(&["scale = \"big\""]).is_err());
+        assert!(Config::parse(&["effects = [\"sparkle\"]"]).is_err());
+        assert!(Config::parse(&["phosphor = \"decay:2\""]).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut c = Config {
+            scale: 12,
+            effects: vec!["mask".to_string(), "bloom".to_string()],
+            phosphor: "decay:0.5".to_string(),
+            ..Config::default()
+        };
+        c.keymap.insert("a".to_string(), 5);
+        c.add_recent("pong.ch8");
+        let toml = toml::to_string(&c).unwrap();
+        assert_eq!(Config::parse(&[&toml]).unwrap(), c);
+    }
+
+    #[test]
+    fn test_save_keeps_layers_apart() {
+        let dir = crate::temp_dir().join(format!("chip8-config-{}", std::process::id()));
+        let user = dir.join("user").join("config.toml");
+        let project = dir.join("chip8.toml");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&project, "scale = 8\n[palette]\non = \"#ffffff\"\n").unwrap();
+        let paths = [user.clone(), project.clone()];
+
+        // no user config yet, so recent starts one
+        let mut c = Config::load_from(&paths).unwrap();
+        c.add_recent("pong.ch8");
+        assert_eq!(c.save_to(&paths), Ok(user.clone()));
+        std::fs::write(
+            &user,
+            std::fs::read_to_string(&user).unwrap() + "instructions_per_frame = 15\n",
+        )
+        .unwrap();
+
+        let mut c = Config::load_from(&paths).unwrap();
+        assert_eq!((c.scale, c.instructions_per_frame), (8, 15));
+        c.add_recent("tetris.ch8");
+        assert_eq!(c.save_to(&paths), Ok(user.clone()));
+        let project_keys: toml::Table = std::fs::read_to_string(&project).unwrap().parse().unwrap();
+        assert_eq!(
+            project_keys,
+            "scale = 8\n[palette]\non = \"#ffffff\"\n".parse().unwrap()
+        );
+        let user_keys: toml::Table = std::fs::read_to_string(&user).unwrap().parse().unwrap();
+        assert_eq!(
+            user_keys,
+            "instructions_per_frame = 15\nrecent = [\"tetris.ch8\", \"pong.ch8\"]\n"
+                .parse()
+                .unwrap()
+        );
+
+        // a project that keeps its own recent list keeps it to itself
+        std::fs::write(&project, "scale = 8\nrecent = []\n").unwrap();
+        let mut c = Config::load_from(&paths).unwrap();
+        c.add_recent("maze.ch8");
+        assert_eq!(c.save_to(&paths), Ok(project.clone()));
+        let c = Config::load_from(&paths[..1]).unwrap();
+        assert_eq!(c.recent, ["tetris.ch8", "pong.ch8"]);
+        let c = Config::load_from(&paths[1..]).unwrap();
+        assert_eq!(c.recent, ["maze.ch8"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recent() {
+        let mut c = Config::default();
+        for i in 0..RECENT + 2 {
+            c.add_recent(&format!("{i}.ch8"));
+        }
+        c.add_recent("5.ch8");
+        assert_eq!(c.recent.len(), RECENT);
+        assert_eq!(c.recent[0], "5.ch8");
+        assert_eq!(c.recent[1], "11.ch8");
+        assert_eq!(c.recent.iter().filter(|r| *r == "5.ch8").count(), 1);
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::cartridge::{read_program, Options};
+use crate::internals::{
+    display::Palette,
+    font::Font,
+    keypad::{Button, Control, Keymap},
+    memory::{sha1, LoadError, RomInfo, PROGRAM_START},
+    quirks::Quirks,
+    Chip8, Reset,
+};
+
+/// How a ROM wants to be run. Whatever the database and overrides don't
+/// say comes from the settings given on the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// The program's name, if the database knows it.
+    pub title: Option<String>,
+    pub platform: Option<String>,
+    pub quirks: Quirks,
+    pub font: Font,
+    pub program_start: usize,
+    pub instructions_per_frame: usize,
+    pub keymap: Keymap,
+    pub palette: Palette,
+}
+
+impl Settings {
+    /// What `chip8` is already set up to do.
+    pub fn of(chip8: &Chip8, instructions_per_frame: usize) -> Self {
+        Settings {
+            title: None,
+            platform: None,
+            quirks: chip8.quirks,
+            font: chip8.font,
+            program_start: chip8.program_start,
+            instructions_per_frame,
+            keymap: Keymap::default(),
+            palette: Palette::default(),
+        }
+    }
+
+    /// Sets up the machine side of things. The font and load address only
+    /// take effect on the next load.
+    pub fn apply(&self, chip8: &mut Chip8) {
+        chip8.quirks = self.quirks;
+        chip8.font = self.font;
+        chip8.program_start = self.program_start;
+    }
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, Entry>,
+}
+
+/// One ROM's entry, in the database or the overrides file. Only the
+/// fields we can do something with are here.
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    // the database keeps titles on the program, overrides can set one here
+    title: Option<String>,
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<usize>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    colors: Option<Colours>,
+    start_address: Option<usize>,
+    font_style: Option<String>,
+}
+
+impl Entry {
+    fn merge(&mut self, over: &Entry) {
+        if over.title.is_some() {
+            self.title.clone_from(&over.title);
+        }
+        if !over.platforms.is_empty() {
+            self.platforms.clone_from(&over.platforms);
+        }
+        self.tickrate = over.tickrate.or(self.tickrate);
+        self.keys
+            .extend(over.keys.iter().map(|(k, v)| (k.clone(), *v)));
+        if over.colors.is_some() {
+            self.colors.clone_from(&over.colors);
+        }
+        self.start_address = over.start_address.or(self.start_address);
+        if over.font_style.is_some() {
+            self.font_style.clone_from(&over.font_style);
+        }
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+struct Colours {
+    /// Unlit first, then lit. XO-CHIP programs list more for their extra
+    /// planes, which we don't have.
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    default_tickrate: Option<usize>,
+    #[serde(default)]
+    quirks: HashMap<String, bool>,
+}
+
+/// A local copy of the community CHIP-8 program database, plus the user's
+/// own overrides, both keyed by the ROM's SHA-1.
+#[derive(Default)]
+pub struct Database {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+    platforms: Vec<Platform>,
+    overrides: HashMap<String, Entry>,
+}
+
+impl Database {
+    /// Reads `programs.json`, `sha1-hashes.json` and `platforms.json` from
+    /// `dir`, laid out the way the database repository has them.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let read = |name: &str| {
+            let path = dir.as_ref().join(name);
+            std::fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))
+        };
+        Database::parse(
+            &read("programs.json")?,
+            &read("sha1-hashes.json")?,
+            &read("platforms.json")?,
+        )
+    }
+
+    pub fn parse(programs: &str, hashes: &str, platforms: &str) -> Result<Self, String> {
+        let json = |e: serde_json::Error| format!("bad program database: {e}");
+        Ok(Database {
+            programs: serde_json::from_str(programs).map_err(json)?,
+            hashes: serde_json::from_str(hashes).map_err(json)?,
+            platforms: serde_json::from_str(platforms).map_err(json)?,
+            overrides: HashMap::new(),
+        })
+    }
+
+    /// Reads the user's overrides: a JSON object from SHA-1 to an entry
+    /// laid out like one of the database's ROMs, plus an optional title.
+    pub fn read_overrides(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| self.parse_overrides(&s))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn parse_overrides(&mut self, overrides: &str) -> Result<(), String> {
+        let overrides: HashMap<String, Entry> =
+            serde_json::from_str(overrides).map_err(|e| format!("bad overrides: {e}"))?;
+        self.overrides.extend(overrides);
+        Ok(())
+    }
+
+    /// `base` with whatever the database and overrides say about the ROM
+    /// with this hash on top.
+    pub fn settings(&self, sha1: &str, base: &Settings) -> Settings {
+        let program = self.hashes.get(sha1).and_then(|i| self.programs.get(*i));
+        let mut entry = program
+            .and_then(|p| p.roms.get(sha1))
+            .cloned()
+            .unwrap_or_default();
+        if let Some(o) = self.overrides.get(sha1) {
+            entry.merge(o);
+        }
+
+        let mut s = base.clone();
+        s.title = entry.title.or_else(|| program.map(|p| p.title.clone()));
+        // the first platform listed is the one it runs best on
+        if let Some(id) = entry.platforms.first() {
+            s.platform = Some(id.clone());
+            if let Some(p) = self.platforms.iter().find(|p| &p.id == id) {
+                if let Some(vblank) = p.quirks.get("vblank") {
+                    s.quirks.display_wait = *vblank;
+                }
+                if let Some(t) = p.default_tickrate {
+                    s.instructions_per_frame = t;
+                }
+            }
+        }
+        if let Some(t) = entry.tickrate {
+            s.instructions_per_frame = t;
+        }
+        for (name, key) in &entry.keys {
+            if let Ok(c) = name.parse::<Control>() {
+                s.keymap.bind(c, Button::from_u8(*key));
+            }
+        }
+        if let Some(set) = entry.font_style.and_then(|f| f.parse().ok()) {
+            s.font.set = set;
+        }
+        if let Some(a) = entry.start_address {
+            s.program_start = a;
+        }
+        if let Some(c) = entry.colors {
+            let mut pixels = c.pixels.iter().map(|p| colour(p));
+            if let (Some(Some(off)), Some(Some(on))) = (pixels.next(), pixels.next()) {
+                s.palette = Palette { off, on };
+            }
+        }
+        s
+    }
+
+    /// Reads a ROM or cartridge file and loads it like `load_rom`. The
+    /// options a cartridge was saved with go on top of `base`.
+    pub fn load_file(
+        &self,
+        chip8: &mut Chip8,
+        path: impl AsRef<Path>,
+        base: &Settings,
+        kind: Reset,
+    ) -> Result<Settings, LoadError> {
+        let (rom, options) = read_program(path)?;
+        self.load_program(chip8, &rom, options.as_ref(), base, kind)
+    }
+
+    /// Loads what `read_program` or `unpack` gave back, with a cartridge's
+    /// options on top of `base`. Cartridges are assembled for 0x200, so
+    /// they load there whatever else says otherwise.
+    pub fn load_program(
+        &self,
+        chip8: &mut Chip8,
+        rom: &[u8],
+        options: Option<&Options>,
+        base: &Settings,
+        kind: Reset,
+    ) -> Result<Settings, LoadError> {
+        let mut base = base.clone();
+        if let Some(o) = options {
+            o.apply(&mut base);
+        }
+        let mut settings = self.settings(&sha1(rom), &base);
+        if options.is_some() {
+            settings.program_start = PROGRAM_START;
+        }
+        self.load(chip8, rom, settings, kind)
+    }
+
+    /// Sets `chip8` up the way `rom` wants and loads it, returning the
+    /// settings it got. Nothing changes if the ROM doesn't fit.
+    pub fn load_rom(
+        &self,
+        chip8: &mut Chip8,
+        rom: &[u8],
+        base: &Settings,
+        kind: Reset,
+    ) -> Result<Settings, LoadError> {
+        self.load(chip8, rom, self.settings(&sha1(rom), base), kind)
+    }
+
+    fn load(
+        &self,
+        chip8: &mut Chip8,
+        rom: &[u8],
+        settings: Settings,
+        kind: Reset,
+    ) -> Result<Settings, LoadError> {
+        RomInfo::check(rom, settings.program_start)?;
+        settings.apply(chip8);
+        chip8.load_rom(rom, kind)?;
+        Ok(settings)
+    }
+}
+
+/// `#rrggbb` as a pixel.
+pub(crate) fn colour(s: &str) -> Option<u32> {
+    let hex = s.strip_prefix('#').filter(|h| h.len() == 6)?;
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Database, Settings};
+    use crate::cartridge::Options;
+    use crate::internals::{
+        display::Palette,
+        font::FontSet,
+        keypad::{Button, Control},
+        memory::sha1,
+        Chip8, Reset,
+    };
+
+    const PROGRAMS: &str = r##"[
+        {"title": "Pong", "roms": {"aaaa": {
+            "platforms": ["originalChip8", "modernChip8"],
+            "keys": {"up": 1, "down": 4, "player2Up": 12},
+            "colors": {"pixels": ["#102030", "#f0e0d0"], "buzzer": "#ffffff"},
+            "fontStyle": "vip"
+        }}},
+        {"title": "Blinky", "roms": {"bbbb": {"platforms": ["modernChip8"], "tickrate": 30}}}
+    ]"##;
+    const HASHES: &str = r#"{"aaaa": 0, "bbbb": 1}"#;
+    const PLATFORMS: &str = r#"[
+        {"id": "originalChip8", "defaultTickrate": 15, "quirks": {"vblank": true, "shift": false}},
+        {"id": "modernChip8", "defaultTickrate": 12, "quirks": {"vblank": false}}
+    ]"#;
+
+    fn database() -> Database {
+        Database::parse(PROGRAMS, HASHES, PLATFORMS).unwrap()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let base = Settings::of(&Chip8::new(), 10);
+        let s = database().settings("aaaa", &base);
+        assert_eq!(s.title.as_deref(), Some("Pong"));
+        assert_eq!(s.platform.as_deref(), Some("originalChip8"));
+        assert!(s.quirks.display_wait);
+        assert_eq!(s.instructions_per_frame, 15);
+        assert_eq!(s.keymap.get(Control::Up), Some(Button::B1));
+        assert_eq!(s.keymap.get(Control::Down), Some(Button::B4));
+        assert_eq!(s.font.set, FontSet::Vip);
+        assert_eq!(
+            s.palette,
+            Palette {
+                off: 0x102030,
+                on: 0xF0E0D0
+            }
+        );
+
+        assert_eq!(
+            database().settings("bbbb", &base).instructions_per_frame,
+            30
+        );
+        assert_eq!(database().settings("cccc", &base), base);
+    }
+
+    #[test]
+    fn test_overrides_win() {
+        let mut db = database();
+        db.parse_overrides(r#"{"bbbb": {"title": "Mine", "tickrate": 50, "keys": {"a": 5}}}"#)
+            .unwrap();
+        let s = db.settings("bbbb", &Settings::of(&Chip8::new(), 10));
+        assert_eq!(s.title.as_deref(), Some("Mine"));
+        assert_eq!(s.platform.as_deref(), Some("modernChip8"));
+        assert_eq!(s.instructions_per_frame, 50);
+        assert_eq!(s.keymap.get(Control::A), Some(Button::B5));
+    }
+
+    #[test]
+    fn test_load_applies_settings() {
+        let rom = [0x12, 0x00];
+        let mut db = Database::default();
+        db.parse_overrides(&format!(
+            r#"{{"{}": {{"startAddress": 1536}}}}"#,
+            sha1(&rom)
+        ))
+        .unwrap();
+        let mut c8 = Chip8::new();
+        let base = Settings::of(&c8, 10);
+        let s = db.load_rom(&mut c8, &rom, &base, Reset::Cold).unwrap();
+        assert_eq!(s.program_start, 0x600);
+        assert_eq!(c8.registers.pc, 0x600);
+        assert!(db
+            .load_rom(&mut c8, &[0; 4000], &base, Reset::Cold)
+            .is_err());
+        assert_eq!(c8.program_start, 0x600);
+    }
+
+    #[test]
+    fn test_cartridges_load_at_0x200() {
+        let rom = [0x12, 0x00];
+        let mut db = Database::default();
+        db.parse_overrides(&format!(
+            r#"{{"{}": {{"startAddress": 1536}}}}"#,
+            sha1(&rom)
+        ))
+        .unwrap();
+        let mut c8 = Chip8::new();
+        let mut base = Settings::of(&c8, 10);
+        base.program_start = 0x400;
+        let options = Options {
+            tickrate: Some(20),
+            ..Options::default()
+        };
+        let s = db
+            .load_program(&mut c8, &rom, Some(&options), &base, Reset::Cold)
+            .unwrap();
+        assert_eq!(s.program_start, 0x200);
+        assert_eq!(s.instructions_per_frame, 20);
+        assert_eq!(c8.registers.pc, 0x200);
+    }
+}
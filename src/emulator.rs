@@ -0,0 +1,506 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::database::{Database, Settings};
+use crate::internals::{
+    display::{Dirty, Frame},
+    keypad::Button,
+    quirks::Quirks,
+    snapshot::Snapshot,
+    Chip8, Reset, FRAME_RATE,
+};
+
+/// How fast to run relative to real time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Speed {
+    Quarter,
+    Half,
+    #[default]
+    Normal,
+    Double,
+    Quadruple,
+    /// As fast as the host can go.
+    Uncapped,
+}
+
+const SPEEDS: [Speed; 6] = [
+    Speed::Quarter,
+    Speed::Half,
+    Speed::Normal,
+    Speed::Double,
+    Speed::Quadruple,
+    Speed::Uncapped,
+];
+
+impl Speed {
+    pub fn faster(self) -> Self {
+        let i = SPEEDS.iter().position(|s| *s == self).unwrap();
+        SPEEDS[(i + 1).min(SPEEDS.len() - 1)]
+    }
+
+    pub fn slower(self) -> Self {
+        let i = SPEEDS.iter().position(|s| *s == self).unwrap();
+        SPEEDS[i.saturating_sub(1)]
+    }
+
+    /// Real time per emulated frame, or nothing when uncapped.
+    pub fn frame_time(self) -> Option<Duration> {
+        let normal = Duration::from_secs(1) / FRAME_RATE;
+        match self {
+            Speed::Quarter => Some(normal * 4),
+            Speed::Half => Some(normal * 2),
+            Speed::Normal => Some(normal),
+            Speed::Double => Some(normal / 2),
+            Speed::Quadruple => Some(normal / 4),
+            Speed::Uncapped => None,
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Speed::Quarter => write!(f, "1/4x"),
+            Speed::Half => write!(f, "1/2x"),
+            Speed::Normal => write!(f, "1x"),
+            Speed::Double => write!(f, "2x"),
+            Speed::Quadruple => write!(f, "4x"),
+            Speed::Uncapped => write!(f, "uncapped"),
+        }
+    }
+}
+
+/// How often to look at the ROM file for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What to do when the running ROM changes on disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Reload {
+    Off,
+    /// Start the new version on a fresh machine.
+    #[default]
+    Reset,
+    /// A warm start, leaving memory outside of the program alone so
+    /// whatever state the program keeps there survives.
+    KeepRam,
+}
+
+impl FromStr for Reload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Reload::Off),
+            "reset" => Ok(Reload::Reset),
+            "keep-ram" => Ok(Reload::KeepRam),
+            _ => Err(format!("unknown reload mode {s}")),
+        }
+    }
+}
+
+/// Sent from the front end to the emulator thread.
+#[derive(Clone)]
+pub enum Request {
+    Press(Button),
+    Release(Button),
+    Pause(bool),
+    SetSpeed(Speed),
+    /// Runs one frame while paused.
+    StepFrame,
+    /// Runs one instruction while paused, without ticking the timers.
+    StepInstruction,
+    /// Starts the current ROM over.
+    Reset(Reset),
+    LoadRom(PathBuf),
+    /// Asks for a [`Reply::State`].
+    SaveState,
+    LoadState(Box<Snapshot>),
+    SetQuirks(Quirks),
+    SetInstructionsPerFrame(usize),
+}
+
+/// Sent from the emulator thread back to the front end.
+pub enum Reply {
+    Frame(Box<Frame>),
+    /// Whenever pausing or the speed changes.
+    Status {
+        paused: bool,
+        speed: Speed,
+    },
+    /// A new ROM is running, with these settings.
+    Loaded(PathBuf, Box<Settings>),
+    State(Box<Snapshot>),
+    Error(String),
+}
+
+/// Runs a machine in real time on its own thread, taking requests from the
+/// front end and replying through a callback.
+pub struct Emulator {
+    pub chip8: Chip8,
+    pub instructions_per_frame: usize,
+    pub reload: Reload,
+    pub database: Database,
+    /// What ROMs the database doesn't know about run with.
+    pub settings: Settings,
+    // where the ROM came from and when that last changed, to notice edits
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+    paused: bool,
+    speed: Speed,
+}
+
+impl Emulator {
+    /// Settings for ROMs the database doesn't know come from how `chip8`
+    /// is set up.
+    pub fn new(chip8: Chip8, instructions_per_frame: usize) -> Self {
+        Emulator {
+            settings: Settings::of(&chip8, instructions_per_frame),
+            chip8,
+            instructions_per_frame,
+            reload: Reload::default(),
+            database: Database::default(),
+            path: None,
+            modified: None,
+            last_check: Instant::now(),
+            paused: false,
+            speed: Speed::Normal,
+        }
+    }
+
+    /// Reads a ROM and starts running it on a fresh machine.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<Settings, String> {
+        let path = path.as_ref();
+        let settings = self.load_file(path, Reset::Cold)?;
+        self.path = Some(path.to_path_buf());
+        self.modified = modified(path);
+        Ok(settings)
+    }
+
+    fn load_file(&mut self, path: &Path, kind: Reset) -> Result<Settings, String> {
+        let settings = self
+            .database
+            .load_file(&mut self.chip8, path, &self.settings, kind)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        self.instructions_per_frame = settings.instructions_per_frame;
+        Ok(settings)
+    }
+
+    /// Reloads the ROM if its file changed since we last looked.
+    fn watch(&mut self) -> Option<Result<(PathBuf, Settings), String>> {
+        if self.reload == Reload::Off || self.last_check.elapsed() < WATCH_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+        let path = self.path.clone()?;
+        // a file that's gone is probably halfway through being saved
+        let modified = modified(&path)?;
+        if Some(modified) == self.modified {
+            return None;
+        }
+        self.modified = Some(modified);
+
+        let kind = match self.reload {
+            Reload::KeepRam => Reset::Warm,
+            _ => Reset::Cold,
+        };
+        Some(self.load_file(&path, kind).map(|s| (path, s)))
+    }
+
+    /// Runs until `reply` reports the front end has gone away or the
+    /// request channel closes. `on_frame` sees every emulated frame, while
+    /// the front end only gets as many as the display can use: past normal
+    /// speed frames are merged down to the real vblank rate.
+    pub fn run(
+        mut self,
+        requests: Receiver<Request>,
+        mut on_frame: impl FnMut(&Chip8),
+        mut reply: impl FnMut(Reply) -> bool,
+    ) {
+        let vblank = Duration::from_secs(1) / FRAME_RATE;
+        let mut next_frame = Instant::now();
+        let mut last_publish = Instant::now();
+        loop {
+            let request = if self.paused {
+                match requests.recv_timeout(WATCH_INTERVAL) {
+                    Ok(r) => Some(r),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            } else {
+                match requests.try_recv() {
+                    Ok(r) => Some(r),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            };
+
+            if let Some(r) = request {
+                let keep_time = matches!(r, Request::Press(_) | Request::Release(_));
+                if !self.handle(r, &mut on_frame, &mut reply) {
+                    return;
+                }
+                if !keep_time {
+                    next_frame = Instant::now();
+                }
+                continue;
+            }
+
+            let reloaded = match self.watch() {
+                Some(Ok((path, settings))) => {
+                    reply(Reply::Loaded(path, Box::new(settings)))
+                        && reply(Reply::Frame(Box::new(self.full_frame())))
+                }
+                Some(Err(e)) => reply(Reply::Error(e)),
+                None => true,
+            };
+            if !reloaded {
+                return;
+            }
+            if self.paused {
+                continue;
+            }
+
+            self.frame(&mut on_frame);
+            let fast = self.speed.frame_time().is_none_or(|t| t < vblank);
+            if !fast || last_publish.elapsed() >= vblank {
+                last_publish = Instant::now();
+                if !reply(Reply::Frame(Box::new(self.chip8.take_frame()))) {
+                    return;
+                }
+            }
+            if let Some(t) = self.speed.frame_time() {
+                next_frame += t;
+                std::thread::sleep(next_frame.saturating_duration_since(Instant::now()));
+            }
+        }
+    }
+
+    /// Acts on a request, returning false if the front end has gone away.
+    fn handle(
+        &mut self,
+        r: Request,
+        on_frame: &mut impl FnMut(&Chip8),
+        reply: &mut impl FnMut(Reply) -> bool,
+    ) -> bool {
+        let status = |e: &Self| Reply::Status {
+            paused: e.paused,
+            speed: e.speed,
+        };
+        match r {
+            Request::Press(b) => self.chip8.keypad.press(b),
+            Request::Release(b) => self.chip8.keypad.release(b),
+            Request::Pause(paused) => {
+                self.paused = paused;
+                return reply(status(self));
+            }
+            Request::SetSpeed(speed) => {
+                self.speed = speed;
+                return reply(status(self));
+            }
+            Request::StepFrame if self.paused => {
+                self.frame(on_frame);
+                return reply(Reply::Frame(Box::new(self.chip8.take_frame())));
+            }
+            Request::StepInstruction if self.paused => {
+                if let Err(e) = self.chip8.step() {
+                    println!("{:?}", e)
+                }
+                return reply(Reply::Frame(Box::new(self.chip8.take_frame())));
+            }
+            Request::StepFrame | Request::StepInstruction => (),
+            Request::Reset(kind) => {
+                self.chip8.reset(kind);
+                return reply(Reply::Frame(Box::new(self.full_frame())));
+            }
+            Request::LoadRom(path) => {
+                return match self.load(&path) {
+                    Ok(settings) => {
+                        reply(Reply::Loaded(path, Box::new(settings)))
+                            && reply(Reply::Frame(Box::new(self.full_frame())))
+                    }
+                    Err(e) => reply(Reply::Error(e)),
+                };
+            }
+            Request::SaveState => return reply(Reply::State(Box::new(self.chip8.snapshot()))),
+            Request::LoadState(s) => {
+                self.chip8.restore(&s);
+                return reply(Reply::Frame(Box::new(self.chip8.take_frame())));
+            }
+            Request::SetQuirks(q) => self.chip8.quirks = q,
+            Request::SetInstructionsPerFrame(n) => self.instructions_per_frame = n,
+        }
+        true
+    }
+
+    /// The whole frame, for when the front end's copy can't be trusted.
+    fn full_frame(&mut self) -> Frame {
+        let mut frame = self.chip8.take_frame();
+        frame.dirty = Dirty::full();
+        frame
+    }
+
+    fn frame(&mut self, on_frame: &mut impl FnMut(&Chip8)) {
+        if let Err(e) = self.chip8.run_frame(self.instructions_per_frame) {
+            println!("{:?}", e)
+        }
+        on_frame(&self.chip8);
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Emulator, Reload, Reply, Request, Speed, WATCH_INTERVAL};
+    use crate::internals::{Chip8, Reset};
+    use std::fs::{self, File};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn test_speed_steps() {
+        assert_eq!(Speed::Normal.faster(), Speed::Double);
+        assert_eq!(Speed::Uncapped.faster(), Speed::Uncapped);
+        assert_eq!(Speed::Half.slower(), Speed::Quarter);
+        assert_eq!(Speed::Quarter.slower(), Speed::Quarter);
+        assert_eq!(Speed::Uncapped.frame_time(), None);
+    }
+
+    #[test]
+    fn test_steps_while_paused() {
+        // v0 += 1, loop
+        let mut c8 = Chip8::new();
+        c8.memory.0[0x200..0x204].copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+        let (tx, rx) = mpsc::channel();
+        tx.send(Request::Pause(true)).unwrap();
+        tx.send(Request::StepInstruction).unwrap();
+        tx.send(Request::StepFrame).unwrap();
+        tx.send(Request::SaveState).unwrap();
+        drop(tx);
+
+        let mut frames = 0;
+        let mut replies = Vec::new();
+        Emulator::new(c8, 10).run(
+            rx,
+            |_| frames += 1,
+            |r| {
+                replies.push(r);
+                true
+            },
+        );
+        // nothing runs on its own once paused, and the thread stops when
+        // the channel closes
+        assert_eq!(frames, 1);
+        assert!(matches!(
+            replies[..],
+            [
+                Reply::Status { paused: true, .. },
+                Reply::Frame(_),
+                Reply::Frame(_),
+                Reply::State(_)
+            ]
+        ));
+        let Reply::State(s) = &replies[3] else {
+            unreachable!()
+        };
+        // one instruction, then the rest of a 10 instruction frame
+        assert_eq!(s.registers.r[0], 6);
+    }
+
+    #[test]
+    fn test_reset_reloads_rom() {
+        let mut c8 = Chip8::new();
+        c8.load_rom(&[0x70, 0x01, 0x12, 0x00], Reset::Cold).unwrap();
+        let (tx, rx) = mpsc::channel();
+        for r in [
+            Request::Pause(true),
+            Request::StepFrame,
+            Request::Reset(Reset::Warm),
+            Request::SaveState,
+        ] {
+            tx.send(r).unwrap();
+        }
+        drop(tx);
+
+        let mut state = None;
+        Emulator::new(c8, 10).run(
+            rx,
+            |_| (),
+            |r| {
+                if let Reply::State(s) = r {
+                    state = Some(s);
+                }
+                true
+            },
+        );
+        let state = state.unwrap();
+        assert_eq!(state.registers.r[0], 0);
+        assert_eq!(state.registers.pc, 0x200);
+        assert_eq!(state.memory.0[0x200], 0x70);
+    }
+
+    /// Writes `rom` to a temporary file, making sure its modification time
+    /// moves even on filesystems with coarse timestamps.
+    fn write_rom(path: &std::path::Path, rom: &[u8], age: u64) {
+        fs::write(path, rom).unwrap();
+        let when = SystemTime::now() - Duration::from_secs(age);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(when)
+            .unwrap();
+    }
+
+    fn watch_now(e: &mut Emulator) -> bool {
+        e.last_check = Instant::now() - WATCH_INTERVAL;
+        matches!(e.watch(), Some(Ok(_)))
+    }
+
+    #[test]
+    fn test_reloads_changed_rom() {
+        let path = crate::temp_dir().join("chip8-test-reload.ch8");
+        // v0 += 1, loop
+        write_rom(&path, &[0x70, 0x01, 0x12, 0x00], 60);
+        let mut e = Emulator::new(Chip8::new(), 10);
+        e.load(&path).unwrap();
+        e.chip8.run_frame(10).unwrap();
+        assert!(!watch_now(&mut e));
+
+        // v0 += 2, loop
+        write_rom(&path, &[0x70, 0x02, 0x12, 0x00], 30);
+        assert!(watch_now(&mut e));
+        assert_eq!(e.chip8.registers.r[0], 0);
+        e.chip8.run_frame(10).unwrap();
+        assert_eq!(e.chip8.registers.r[0], 10);
+    }
+
+    #[test]
+    fn test_reload_can_keep_ram() {
+        let path = crate::temp_dir().join("chip8-test-reload-keep.ch8");
+        // i = 0x300, v0 = 0x42, store v0, loop
+        let rom = [0xA3, 0x00, 0x60, 0x42, 0xF0, 0x55, 0x12, 0x06];
+        write_rom(&path, &rom, 60);
+        let mut e = Emulator::new(Chip8::new(), 10);
+        e.reload = Reload::KeepRam;
+        e.load(&path).unwrap();
+        e.chip8.run_frame(10).unwrap();
+        assert_eq!(e.chip8.memory.0[0x300], 0x42);
+
+        // a shorter program: loop
+        write_rom(&path, &[0x12, 0x00], 30);
+        assert!(watch_now(&mut e));
+        assert_eq!(e.chip8.registers.pc, 0x200);
+        assert_eq!(e.chip8.memory.0[0x300], 0x42);
+        assert_eq!(
+            &e.chip8.memory.0[0x200..0x208],
+            &[0x12, 0x00, 0, 0, 0, 0, 0, 0]
+        );
+    }
+}
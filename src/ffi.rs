@@ -0,0 +1,579 @@
+//! The core as a C library, for C, C++ and anything with a C FFI, like
+//! Python's ctypes. `include/chip8.h` is generated from this file.
+//!
+//! Machines and saved states are opaque pointers, made and freed here.
+//! Every function that takes one accepts a null pointer and reports it,
+//! but anything else has to be a live pointer from this library, and
+//! buffers have to be at least as long as they're said to be.
+//! Nothing here is thread safe, but a machine can move between threads.
+//! A panic stops at the boundary rather than aborting the caller.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::database::{Database, Settings};
+use crate::internals::{
+    display::{HEIGHT, WIDTH},
+    keypad::Button,
+    memory::{LoadError, Registers, MEMORY_SIZE},
+    snapshot::Snapshot,
+    Chip8, Chip8Error, Reset,
+};
+
+/// Bumped whenever something here changes in a way that breaks callers.
+pub const CHIP8_ABI_VERSION: u32 = 1;
+// spelled out, since the header can't see the core's
+pub const CHIP8_WIDTH: usize = 64;
+pub const CHIP8_HEIGHT: usize = 32;
+pub const CHIP8_MEMORY_SIZE: usize = 4096;
+/// How deep `Chip8Registers` can show the stack.
+pub const CHIP8_STACK_SIZE: usize = 16;
+
+const _: () = assert!(CHIP8_WIDTH == WIDTH && CHIP8_HEIGHT == HEIGHT);
+const _: () = assert!(CHIP8_MEMORY_SIZE == MEMORY_SIZE);
+
+/// What every call that can fail returns. The values won't change.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip8Status {
+    Ok = 0,
+    /// A pointer that can't be null was.
+    Null = 1,
+    /// A ROM file couldn't be read.
+    Io = 2,
+    EmptyRom = 3,
+    RomTooBig = 4,
+    BadAddress = 5,
+    /// A cartridge couldn't be unpacked.
+    BadFormat = 6,
+    /// `00EE` with nothing on the stack.
+    StackUnderflow = 7,
+    /// An address, key or length that's out of range.
+    OutOfRange = 8,
+    /// A bug in the library. The machine may be left half way through
+    /// the call, so it's best freed.
+    Panicked = 9,
+}
+
+/// A machine.
+pub struct Chip8Machine {
+    chip8: Chip8,
+}
+
+/// A machine's state, saved to be restored later.
+pub struct Chip8State {
+    snapshot: Snapshot,
+}
+
+/// The CPU's registers, laid out for C.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Chip8Registers {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub delay: u8,
+    pub sound: u8,
+    /// How many of `stack` are in use.
+    pub sp: u8,
+    pub stack: [u16; CHIP8_STACK_SIZE],
+}
+
+impl From<LoadError> for Chip8Status {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::Io(_) => Chip8Status::Io,
+            LoadError::Empty => Chip8Status::EmptyRom,
+            LoadError::TooBig { .. } => Chip8Status::RomTooBig,
+            LoadError::BadAddress(_) => Chip8Status::BadAddress,
+            LoadError::Format(_) => Chip8Status::BadFormat,
+        }
+    }
+}
+
+impl From<Chip8Error> for Chip8Status {
+    fn from(e: Chip8Error) -> Self {
+        match e {
+            Chip8Error::StackUnderflow => Chip8Status::StackUnderflow,
+        }
+    }
+}
+
+/// Turns a `Result` into a status.
+fn status<E: Into<Chip8Status>>(r: Result<(), E>) -> Chip8Status {
+    match r {
+        Ok(()) => Chip8Status::Ok,
+        Err(e) => e.into(),
+    }
+}
+
+/// Runs `f`, reporting a panic instead of unwinding into C, which
+/// would abort.
+fn guard(f: impl FnOnce() -> Chip8Status) -> Chip8Status {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(Chip8Status::Panicked)
+}
+
+/// Runs `f` on the machine behind `m`, unless it's null.
+unsafe fn with(m: *mut Chip8Machine, f: impl FnOnce(&mut Chip8) -> Chip8Status) -> Chip8Status {
+    match m.as_mut() {
+        Some(m) => guard(|| f(&mut m.chip8)),
+        None => Chip8Status::Null,
+    }
+}
+
+/// `len` bytes of memory from `address`, if they're all there.
+fn span(address: u16, len: usize) -> Option<std::ops::Range<usize>> {
+    let start = address as usize;
+    let end = start.checked_add(len)?;
+    (end <= MEMORY_SIZE).then_some(start..end)
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_abi_version() -> u32 {
+    CHIP8_ABI_VERSION
+}
+
+/// A powered-on machine with nothing loaded, or null if one couldn't be
+/// made. Free it with `chip8_free`.
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8Machine {
+    catch_unwind(|| {
+        Box::into_raw(Box::new(Chip8Machine {
+            chip8: Chip8::new(),
+        }))
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(m: *mut Chip8Machine) {
+    if !m.is_null() {
+        drop(Box::from_raw(m));
+    }
+}
+
+/// Loads `len` bytes of program and powers the machine back on to run it.
+/// Nothing changes if it doesn't fit.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(
+    m: *mut Chip8Machine,
+    rom: *const u8,
+    len: usize,
+) -> Chip8Status {
+    if rom.is_null() {
+        return Chip8Status::Null;
+    }
+    let rom = std::slice::from_raw_parts(rom, len);
+    with(m, |c8| status(c8.load_rom(rom, Reset::Cold).map(|_| ())))
+}
+
+/// Like `chip8_load_rom`, from a ROM or Octo cartridge file. A cartridge's
+/// options are applied too.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_file(m: *mut Chip8Machine, path: *const c_char) -> Chip8Status {
+    if path.is_null() {
+        return Chip8Status::Null;
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(p) => p,
+        Err(_) => return Chip8Status::Io,
+    };
+    with(m, |c8| {
+        let base = Settings::of(c8, 0);
+        status(
+            Database::default()
+                .load_file(c8, path, &base, Reset::Cold)
+                .map(|_| ()),
+        )
+    })
+}
+
+/// Starts the program over, wiping memory as well if `cold`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_reset(m: *mut Chip8Machine, cold: bool) -> Chip8Status {
+    with(m, |c8| {
+        c8.reset(if cold { Reset::Cold } else { Reset::Warm });
+        Chip8Status::Ok
+    })
+}
+
+/// Makes `Cxnn` repeatable from here on.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_seed(m: *mut Chip8Machine, seed: u64) -> Chip8Status {
+    with(m, |c8| {
+        c8.seed(seed);
+        Chip8Status::Ok
+    })
+}
+
+/// Runs `instructions` instructions, without ticking the timers. An
+/// `Fx0A` waiting for a key counts each time it's run.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(m: *mut Chip8Machine, instructions: u32) -> Chip8Status {
+    with(m, |c8| {
+        status((0..instructions).try_for_each(|_| c8.step().map(|_| ())))
+    })
+}
+
+/// Runs `frames` frames of up to `instructions_per_frame` instructions,
+/// ticking the timers after each.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frames(
+    m: *mut Chip8Machine,
+    frames: u32,
+    instructions_per_frame: u32,
+) -> Chip8Status {
+    with(m, |c8| {
+        status((0..frames).try_for_each(|_| c8.run_frame(instructions_per_frame as usize)))
+    })
+}
+
+/// Presses or releases one of the 16 keys.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(m: *mut Chip8Machine, key: u8, down: bool) -> Chip8Status {
+    if key > 0xF {
+        return Chip8Status::OutOfRange;
+    }
+    with(m, |c8| {
+        match down {
+            true => c8.keypad.press(Button::from_u8(key)),
+            false => c8.keypad.release(Button::from_u8(key)),
+        }
+        Chip8Status::Ok
+    })
+}
+
+/// Holds the keys whose bits are set in `keys`, key 0 in the lowest bit,
+/// and releases the rest.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_keys(m: *mut Chip8Machine, keys: u16) -> Chip8Status {
+    with(m, |c8| {
+        for k in 0..16 {
+            match keys & 1 << k != 0 {
+                true => c8.keypad.press(Button::from_u8(k)),
+                false => c8.keypad.release(Button::from_u8(k)),
+            }
+        }
+        Chip8Status::Ok
+    })
+}
+
+/// Copies the screen into `out`, a row at a time from the top left, one
+/// byte per pixel: 1 if it's lit, 0 if not. `len` has to be at least
+/// `CHIP8_WIDTH * CHIP8_HEIGHT`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(
+    m: *mut Chip8Machine,
+    out: *mut u8,
+    len: usize,
+) -> Chip8Status {
+    if out.is_null() {
+        return Chip8Status::Null;
+    }
+    if len < WIDTH * HEIGHT {
+        return Chip8Status::OutOfRange;
+    }
+    let out = std::slice::from_raw_parts_mut(out, WIDTH * HEIGHT);
+    with(m, |c8| {
+        for (o, p) in out.iter_mut().zip(c8.frame_buffer.iter()) {
+            *o = (*p != 0) as u8;
+        }
+        Chip8Status::Ok
+    })
+}
+
+/// Fails if the stack is deeper than `CHIP8_STACK_SIZE`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_get_registers(
+    m: *mut Chip8Machine,
+    out: *mut Chip8Registers,
+) -> Chip8Status {
+    let Some(out) = out.as_mut() else {
+        return Chip8Status::Null;
+    };
+    with(m, |c8| {
+        let r = &c8.registers;
+        if r.stack.len() > CHIP8_STACK_SIZE {
+            return Chip8Status::OutOfRange;
+        }
+        *out = Chip8Registers {
+            v: r.r,
+            i: r.vi,
+            pc: r.pc,
+            delay: r.delay,
+            sound: r.sound,
+            sp: r.stack.len() as u8,
+            stack: [0; CHIP8_STACK_SIZE],
+        };
+        out.stack[..r.stack.len()].copy_from_slice(&r.stack);
+        Chip8Status::Ok
+    })
+}
+
+/// Fails, changing nothing, if `sp` is more than `CHIP8_STACK_SIZE`, or
+/// `pc` or an address on the stack is off the end of memory.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_registers(
+    m: *mut Chip8Machine,
+    r: *const Chip8Registers,
+) -> Chip8Status {
+    let Some(r) = r.as_ref() else {
+        return Chip8Status::Null;
+    };
+    if r.sp as usize > CHIP8_STACK_SIZE {
+        return Chip8Status::OutOfRange;
+    }
+    let fits = |address: u16| (address as usize) < MEMORY_SIZE;
+    if !fits(r.pc) || !r.stack[..r.sp as usize].iter().all(|&a| fits(a)) {
+        return Chip8Status::OutOfRange;
+    }
+    with(m, |c8| {
+        let mut stack = std::mem::take(&mut c8.registers.stack);
+        stack.clear();
+        stack.extend_from_slice(&r.stack[..r.sp as usize]);
+        c8.registers = Registers {
+            r: r.v,
+            vi: r.i,
+            delay: r.delay,
+            sound: r.sound,
+            pc: r.pc,
+            stack,
+        };
+        Chip8Status::Ok
+    })
+}
+
+/// Copies `len` bytes of memory from `address` into `out`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_read_memory(
+    m: *mut Chip8Machine,
+    address: u16,
+    out: *mut u8,
+    len: usize,
+) -> Chip8Status {
+    if out.is_null() {
+        return Chip8Status::Null;
+    }
+    let Some(span) = span(address, len) else {
+        return Chip8Status::OutOfRange;
+    };
+    let out = std::slice::from_raw_parts_mut(out, len);
+    with(m, |c8| {
+        out.copy_from_slice(&c8.memory.0[span]);
+        Chip8Status::Ok
+    })
+}
+
+/// Copies `len` bytes from `data` into memory at `address`. Code written
+/// over is picked up from the next instruction on.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_write_memory(
+    m: *mut Chip8Machine,
+    address: u16,
+    data: *const u8,
+    len: usize,
+) -> Chip8Status {
+    if data.is_null() {
+        return Chip8Status::Null;
+    }
+    let Some(span) = span(address, len) else {
+        return Chip8Status::OutOfRange;
+    };
+    let data = std::slice::from_raw_parts(data, len);
+    with(m, |c8| {
+        c8.memory.0[span.clone()].copy_from_slice(data);
+        c8.invalidate(span);
+        Chip8Status::Ok
+    })
+}
+
+/// Everything needed to put the machine back where it is now, short of
+/// the keys held. Null if `m` is, or if it couldn't be saved. Free it
+/// with `chip8_free_state`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(m: *mut Chip8Machine) -> *mut Chip8State {
+    let Some(m) = m.as_ref() else {
+        return std::ptr::null_mut();
+    };
+    catch_unwind(AssertUnwindSafe(|| {
+        Box::into_raw(Box::new(Chip8State {
+            snapshot: m.chip8.snapshot(),
+        }))
+    }))
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Puts the machine back the way it was when `state` was saved. A state
+/// can be restored any number of times, to any machine.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_restore_state(
+    m: *mut Chip8Machine,
+    state: *const Chip8State,
+) -> Chip8Status {
+    let Some(state) = state.as_ref() else {
+        return Chip8Status::Null;
+    };
+    with(m, |c8| {
+        c8.restore(&state.snapshot);
+        Chip8Status::Ok
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free_state(state: *mut Chip8State) {
+    if !state.is_null() {
+        drop(Box::from_raw(state));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        unsafe {
+            let m = chip8_new();
+            // v0 += 1, draw the 0 glyph at v1, v1, loop
+            let program = [0x70, 0x01, 0xF1, 0x29, 0xD1, 0x15, 0x12, 0x00];
+            assert_eq!(chip8_load_rom(m, program.as_ptr(), 8), Chip8Status::Ok);
+            assert_eq!(chip8_step(m, 3), Chip8Status::Ok);
+            let saved = chip8_save_state(m);
+
+            let mut r = Chip8Registers::default();
+            assert_eq!(chip8_get_registers(m, &mut r), Chip8Status::Ok);
+            assert_eq!((r.v[0], r.pc, r.sp), (1, 0x206, 0));
+            let mut screen = [0; CHIP8_WIDTH * CHIP8_HEIGHT];
+            assert_eq!(
+                chip8_framebuffer(m, screen.as_mut_ptr(), screen.len()),
+                Chip8Status::Ok
+            );
+            // the top of the 0 glyph
+            assert_eq!(screen[..5], [1, 1, 1, 1, 0]);
+
+            assert_eq!(chip8_run_frames(m, 3, 10), Chip8Status::Ok);
+            r.v[5] = 9;
+            r.sp = 1;
+            r.stack[0] = 0x208;
+            assert_eq!(chip8_set_registers(m, &r), Chip8Status::Ok);
+            assert_eq!((*m).chip8.registers.stack, [0x208]);
+            assert_eq!((*m).chip8.registers.r[5], 9);
+
+            assert_eq!(chip8_restore_state(m, saved), Chip8Status::Ok);
+            assert_eq!(chip8_get_registers(m, &mut r), Chip8Status::Ok);
+            assert_eq!((r.v[0], r.v[5], r.sp), (1, 0, 0));
+            chip8_free_state(saved);
+            chip8_free(m);
+        }
+    }
+
+    #[test]
+    fn test_memory_and_keys() {
+        unsafe {
+            let m = chip8_new();
+            // loop until key 5 is held, then v0 = 7
+            let program = [0x61, 0x05, 0xE1, 0x9E, 0x12, 0x02, 0x60, 0x07];
+            assert_eq!(chip8_load_rom(m, program.as_ptr(), 8), Chip8Status::Ok);
+            assert_eq!(chip8_step(m, 5), Chip8Status::Ok);
+            assert_eq!((*m).chip8.registers.r[0], 0);
+            assert_eq!(chip8_set_keys(m, 1 << 5), Chip8Status::Ok);
+            assert_eq!(chip8_step(m, 3), Chip8Status::Ok);
+            assert_eq!((*m).chip8.registers.r[0], 7);
+            assert_eq!(chip8_set_key(m, 5, false), Chip8Status::Ok);
+            assert!((*m).chip8.keypad.pressing.is_empty());
+            assert_eq!(chip8_set_key(m, 16, true), Chip8Status::OutOfRange);
+
+            // start over with the loop's jump made an add
+            assert_eq!(chip8_reset(m, false), Chip8Status::Ok);
+            assert_eq!(
+                chip8_write_memory(m, 0x204, [0x72, 0x01].as_ptr(), 2),
+                Chip8Status::Ok
+            );
+            let mut bytes = [0; 4];
+            assert_eq!(
+                chip8_read_memory(m, 0x202, bytes.as_mut_ptr(), 4),
+                Chip8Status::Ok
+            );
+            assert_eq!(bytes, [0xE1, 0x9E, 0x72, 0x01]);
+            assert_eq!(
+                chip8_read_memory(m, 0xFFE, bytes.as_mut_ptr(), 4),
+                Chip8Status::OutOfRange
+            );
+            assert_eq!(chip8_step(m, 3), Chip8Status::Ok);
+            assert_eq!((*m).chip8.registers.r[2], 1);
+            chip8_free(m);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        unsafe {
+            let null = std::ptr::null_mut();
+            assert_eq!(chip8_step(null, 1), Chip8Status::Null);
+            assert!(chip8_save_state(null).is_null());
+            chip8_free(null);
+
+            let m = chip8_new();
+            assert_eq!(chip8_load_rom(m, [0].as_ptr(), 0), Chip8Status::EmptyRom);
+            let big = [0; MEMORY_SIZE];
+            assert_eq!(
+                chip8_load_rom(m, big.as_ptr(), big.len()),
+                Chip8Status::RomTooBig
+            );
+            assert_eq!(
+                chip8_load_file(m, c"./data/no-such-rom.ch8".as_ptr()),
+                Chip8Status::Io
+            );
+            assert_eq!(
+                chip8_load_file(m, c"./data/test.ch8".as_ptr()),
+                Chip8Status::Ok
+            );
+            assert_eq!(chip8_load_rom(m, [0x00, 0xEE].as_ptr(), 2), Chip8Status::Ok);
+            assert_eq!(chip8_run_frames(m, 1, 10), Chip8Status::StackUnderflow);
+
+            let pc = (*m).chip8.registers.pc;
+            let mut r = Chip8Registers {
+                pc: 0x1000,
+                ..Default::default()
+            };
+            assert_eq!(chip8_set_registers(m, &r), Chip8Status::OutOfRange);
+            r.pc = 0x200;
+            r.sp = 1;
+            r.stack[0] = 0xFFFF;
+            assert_eq!(chip8_set_registers(m, &r), Chip8Status::OutOfRange);
+            assert_eq!((*m).chip8.registers.pc, pc);
+            assert!((*m).chip8.registers.stack.is_empty());
+
+            // I at the top of memory, then store v0..vF through it
+            let program = [0xAF, 0xFF, 0xFF, 0x55];
+            assert_eq!(chip8_load_rom(m, program.as_ptr(), 4), Chip8Status::Ok);
+            assert_eq!(chip8_step(m, 2), Chip8Status::Ok);
+
+            // what comes out at the end of memory goes back in
+            let program = [0x2F, 0xFF];
+            assert_eq!(chip8_load_rom(m, program.as_ptr(), 2), Chip8Status::Ok);
+            assert_eq!(chip8_step(m, 1), Chip8Status::Ok);
+            assert_eq!(chip8_get_registers(m, &mut r), Chip8Status::Ok);
+            assert_eq!((r.pc, r.sp), (0xFFF, 1));
+            r.stack[0] = 0xFFF;
+            assert_eq!(chip8_set_registers(m, &r), Chip8Status::Ok);
+            assert_eq!((*m).chip8.registers.pc, 0xFFF);
+            assert_eq!((*m).chip8.registers.stack, [0xFFF]);
+            chip8_free(m);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(target_family = "wasm", ignore = "panics abort on wasm")]
+    fn test_panics_are_reported() {
+        unsafe {
+            let m = chip8_new();
+            assert_eq!(with(m, |_| panic!("boom")), Chip8Status::Panicked);
+            // and the machine still works
+            assert_eq!(chip8_reset(m, true), Chip8Status::Ok);
+            chip8_free(m);
+        }
+    }
+}
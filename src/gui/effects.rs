@@ -0,0 +1,338 @@
+use std::str::FromStr;
+
+/// A post-processing stage run over the scaled 0RGB buffer, after the
+/// nearest-neighbour upscale.
+pub trait Effect {
+    fn apply(&mut self, b: &mut [u32], width: usize, height: usize);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectKind {
+    Scanlines,
+    PixelGrid,
+    Bloom,
+    ShadowMask,
+    Curvature,
+}
+
+impl FromStr for EffectKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanlines" => Ok(EffectKind::Scanlines),
+            "grid" => Ok(EffectKind::PixelGrid),
+            "bloom" => Ok(EffectKind::Bloom),
+            "mask" => Ok(EffectKind::ShadowMask),
+            "curvature" => Ok(EffectKind::Curvature),
+            _ => Err(format!("unknown effect {s}")),
+        }
+    }
+}
+
+impl EffectKind {
+    /// Parses a comma separated list, eg. `scanlines,bloom`.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, String> {
+        s.split(',')
+            .filter(|e| !e.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    /// The stage with its default settings, for a buffer upscaled by `scale`.
+    pub fn stage(&self, scale: usize) -> Box<dyn Effect> {
+        match self {
+            EffectKind::Scanlines => Box::new(Scanlines { strength: 0.5 }),
+            EffectKind::PixelGrid => Box::new(PixelGrid {
+                scale,
+                strength: 0.3,
+            }),
+            EffectKind::Bloom => Box::new(Bloom {
+                radius: scale.div_ceil(2),
+                strength: 0.6,
+                scratch: Vec::new(),
+            }),
+            EffectKind::ShadowMask => Box::new(ShadowMask { strength: 0.3 }),
+            EffectKind::Curvature => Box::new(Curvature {
+                amount: 0.08,
+                scratch: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// The stages to run, in order.
+#[derive(Default)]
+pub struct Pipeline(Vec<Box<dyn Effect>>);
+
+impl Pipeline {
+    pub fn new(kinds: &[EffectKind], scale: usize) -> Self {
+        Pipeline(kinds.iter().map(|k| k.stage(scale)).collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn apply(&mut self, b: &mut [u32], width: usize, height: usize) {
+        for stage in self.0.iter_mut() {
+            stage.apply(b, width, height);
+        }
+    }
+}
+
+/// Darkens every other line.
+pub struct Scanlines {
+    pub strength: f32,
+}
+
+impl Effect for Scanlines {
+    fn apply(&mut self, b: &mut [u32], width: usize, height: usize) {
+        for y in (1..height).step_by(2) {
+            for p in &mut b[y * width..(y + 1) * width] {
+                *p = scale_rgb(*p, [1.0 - self.strength; 3]);
+            }
+        }
+    }
+}
+
+/// Darkens the right and bottom edge of every emulated pixel, like the
+/// gaps between cells on an LCD.
+pub struct PixelGrid {
+    pub scale: usize,
+    pub strength: f32,
+}
+
+impl Effect for PixelGrid {
+    fn apply(&mut self, b: &mut [u32], width: usize, height: usize) {
+        if self.scale < 2 {
+            return;
+        }
+        for y in 0..height {
+            for x in 0..width {
+                if x % self.scale == self.scale - 1 || y % self.scale == self.scale - 1 {
+                    let p = &mut b[y * width + x];
+                    *p = scale_rgb(*p, [1.0 - self.strength; 3]);
+                }
+            }
+        }
+    }
+}
+
+/// Adds a blurred copy of the image back onto itself so lit pixels glow
+/// into their neighbours.
+pub struct Bloom {
+    pub radius: usize,
+    pub strength: f32,
+    scratch: Vec<[u32; 3]>,
+}
+
+impl Effect for Bloom {
+    fn apply(&mut self, b: &mut [u32], width: usize, height: usize) {
+        self.scratch.clear();
+        self.scratch
+            .extend(b.iter().map(|p| rgb(*p).map(u32::from)));
+        box_blur(&mut self.scratch, width, height, self.radius, 1, width);
+        box_blur(&mut self.scratch, height, width, self.radius, width, 1);
+        for (p, glow) in b.iter_mut().zip(&self.scratch) {
+            let c = rgb(*p);
+            let mut out = [0; 3];
+            for i in 0..3 {
+                out[i] = (c[i] as f32 + glow[i] as f32 * self.strength).min(255.0) as u8;
+            }
+            *p = from_rgb(out);
+        }
+    }
+}
+
+/// Box blur along one axis. `lines` runs of `len` samples, where samples in
+/// a run are `step` apart and runs start `stride` apart.
+fn box_blur(
+    data: &mut [[u32; 3]],
+    len: usize,
+    lines: usize,
+    radius: usize,
+    step: usize,
+    stride: usize,
+) {
+    let window = (2 * radius + 1) as u32;
+    let mut line = vec![[0u32; 3]; len];
+    for l in 0..lines {
+        let start = l * stride;
+        for (i, v) in line.iter_mut().enumerate() {
+            *v = data[start + i * step];
+        }
+        let mut sum = [0u32; 3];
+        for v in line.iter().take(radius + 1) {
+            add(&mut sum, v);
+        }
+        for i in 0..len {
+            data[start + i * step] = sum.map(|c| c / window);
+            if i + radius + 1 < len {
+                add(&mut sum, &line[i + radius + 1]);
+            }
+            if i >= radius {
+                let old = line[i - radius];
+                for c in 0..3 {
+                    sum[c] -= old[c];
+                }
+            }
+        }
+    }
+}
+
+fn add(sum: &mut [u32; 3], v: &[u32; 3]) {
+    for c in 0..3 {
+        sum[c] += v[c];
+    }
+}
+
+/// Aperture grille: every column favours one of red, green or blue.
+pub struct ShadowMask {
+    pub strength: f32,
+}
+
+impl Effect for ShadowMask {
+    fn apply(&mut self, b: &mut [u32], width: usize, height: usize) {
+        let dim = 1.0 - self.strength;
+        for y in 0..height {
+            for x in 0..width {
+                let mut mask = [dim; 3];
+                mask[x % 3] = 1.0;
+                let p = &mut b[y * width + x];
+                *p = scale_rgb(*p, mask);
+            }
+        }
+    }
+}
+
+/// Barrel distortion, bending the image like the glass of a CRT. Anything
+/// pulled in from outside the image is black.
+pub struct Curvature {
+    pub amount: f32,
+    scratch: Vec<u32>,
+}
+
+impl Effect for Curvature {
+    fn apply(&mut self, b: &mut [u32], width: usize, height: usize) {
+        self.scratch.clear();
+        self.scratch.extend_from_slice(b);
+        for y in 0..height {
+            let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+                let f = 1.0 + self.amount * (u * u + v * v);
+                let (su, sv) = (u * f, v * f);
+                b[y * width + x] = if su.abs() > 1.0 || sv.abs() > 1.0 {
+                    0
+                } else {
+                    let sx = (((su + 1.0) / 2.0) * width as f32) as usize;
+                    let sy = (((sv + 1.0) / 2.0) * height as f32) as usize;
+                    self.scratch[sy.min(height - 1) * width + sx.min(width - 1)]
+                };
+            }
+        }
+    }
+}
+
+fn rgb(p: u32) -> [u8; 3] {
+    [(p >> 16) as u8, (p >> 8) as u8, p as u8]
+}
+
+fn from_rgb(c: [u8; 3]) -> u32 {
+    (c[0] as u32) << 16 | (c[1] as u32) << 8 | c[2] as u32
+}
+
+fn scale_rgb(p: u32, k: [f32; 3]) -> u32 {
+    let c = rgb(p);
+    from_rgb([
+        (c[0] as f32 * k[0]) as u8,
+        (c[1] as f32 * k[1]) as u8,
+        (c[2] as f32 * k[2]) as u8,
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EffectKind, Pipeline};
+    use crate::capture::screenshot::check_golden;
+
+    const SCALE: usize = 4;
+    const W: usize = 16 * SCALE;
+    const H: usize = 8 * SCALE;
+
+    /// A 16x8 test card, nearest-neighbour upscaled like the presenter
+    /// does: a checkerboard on the left, a solid block on the right.
+    fn test_card() -> Vec<u32> {
+        let mut b = vec![0; W * H];
+        for y in 0..H {
+            for x in 0..W {
+                let (nx, ny) = (x / SCALE, y / SCALE);
+                let lit = if nx < 8 {
+                    (nx + ny) % 2 == 0
+                } else {
+                    nx < 14 && ny > 1
+                };
+                if lit {
+                    b[y * W + x] = 0x0000FFFF;
+                }
+            }
+        }
+        b
+    }
+
+    /// Compares against `data/golden/<name>.png`. Run with UPDATE_GOLDEN=1
+    /// to rewrite the golden images after an intended change.
+    fn check_stages(name: &str, kinds: &[EffectKind]) {
+        let mut b = test_card();
+        Pipeline::new(kinds, SCALE).apply(&mut b, W, H);
+        check_golden(format!("./data/golden/{name}.png"), &b, W, H).unwrap();
+    }
+
+    #[test]
+    fn test_parse_effect_list() {
+        assert_eq!(
+            EffectKind::parse_list("scanlines,bloom"),
+            Ok(vec![EffectKind::Scanlines, EffectKind::Bloom])
+        );
+        assert!(EffectKind::parse_list("scanlines,sparkles").is_err());
+    }
+
+    #[test]
+    fn test_golden_scanlines() {
+        check_stages("scanlines", &[EffectKind::Scanlines]);
+    }
+
+    #[test]
+    fn test_golden_grid() {
+        check_stages("grid", &[EffectKind::PixelGrid]);
+    }
+
+    #[test]
+    fn test_golden_bloom() {
+        check_stages("bloom", &[EffectKind::Bloom]);
+    }
+
+    #[test]
+    fn test_golden_mask() {
+        check_stages("mask", &[EffectKind::ShadowMask]);
+    }
+
+    #[test]
+    fn test_golden_curvature() {
+        check_stages("curvature", &[EffectKind::Curvature]);
+    }
+
+    #[test]
+    fn test_golden_all_stages() {
+        check_stages(
+            "crt",
+            &[
+                EffectKind::Bloom,
+                EffectKind::Scanlines,
+                EffectKind::ShadowMask,
+                EffectKind::Curvature,
+            ],
+        );
+    }
+}
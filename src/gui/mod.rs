@@ -1,34 +1,179 @@
+pub mod effects;
+pub mod phosphor;
+pub mod present;
 pub mod window;
 
+use present::Presenter;
 use softbuffer::Surface;
 use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::{Arc, RwLock};
-use winit::dpi::PhysicalSize;
+use std::sync::mpsc::Sender;
 use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, ControlFlow};
 use winit::keyboard::{Key, NamedKey};
 use winit::window::Window;
 
-#[derive(Default)]
-pub struct Controller {
-    pub pressing: Vec<Key>,
-    pub last_released: Option<Key>,
+use crate::config::Config;
+use crate::database::Settings;
+use crate::emulator::{Reply, Request, Speed};
+use crate::internals::display::{Dirty, DisplayCommand, HEIGHT, WIDTH};
+use crate::internals::keypad::{Button, Control, Keymap};
+use crate::internals::snapshot::Snapshot;
+use crate::internals::Reset;
+
+/// The keypad button a window key stands for, if any: hex digits press
+/// their own button, the arrows, enter and tab whatever the game binds.
+fn to_button(k: &Key, keymap: &Keymap) -> Option<Button> {
+    let control = match k {
+        Key::Character(s) => {
+            let mut chars = s.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Button::from_char(c),
+                _ => None,
+            };
+        }
+        Key::Named(NamedKey::ArrowUp) => Control::Up,
+        Key::Named(NamedKey::ArrowDown) => Control::Down,
+        Key::Named(NamedKey::ArrowLeft) => Control::Left,
+        Key::Named(NamedKey::ArrowRight) => Control::Right,
+        Key::Named(NamedKey::Enter) => Control::A,
+        Key::Named(NamedKey::Tab) => Control::B,
+        _ => return None,
+    };
+    keymap.get(control)
 }
 
 pub trait UserEvent {
-    fn transform(&self, b: &mut [u32]) -> ();
+    /// Applies the event to the native frame, returning the parts of it
+    /// that changed.
+    fn transform(&self, frame: &mut [u32; WIDTH * HEIGHT]) -> Dirty;
+}
+
+impl UserEvent for DisplayCommand {
+    fn transform(&self, frame: &mut [u32; WIDTH * HEIGHT]) -> Dirty {
+        match self {
+            DisplayCommand::ClearDisplay => {
+                frame.fill(0);
+                Dirty::full()
+            }
+            DisplayCommand::Draw(f) => {
+                for r in f.dirty.rects() {
+                    for y in r.y..r.y + r.height {
+                        let row = y * WIDTH + r.x..y * WIDTH + r.x + r.width;
+                        frame[row.clone()].copy_from_slice(&f.pixels[row]);
+                    }
+                }
+                f.dirty
+            }
+        }
+    }
+}
+
+/// The window's end of the channel to the emulator thread, along with what
+/// the emulator last told us about itself.
+pub struct Remote {
+    requests: Sender<Request>,
+    rom: PathBuf,
+    // what the database calls it
+    title: Option<String>,
+    keymap: Keymap,
+    paused: bool,
+    speed: Speed,
+    // the quick save slot
+    saved: Option<Box<Snapshot>>,
+    // saved on F10, with the ROMs loaded since
+    config: Config,
+}
+
+impl Remote {
+    pub fn new(
+        requests: Sender<Request>,
+        rom: impl AsRef<Path>,
+        settings: &Settings,
+        mut config: Config,
+    ) -> Self {
+        config.add_recent(&rom.as_ref().to_string_lossy());
+        Remote {
+            requests,
+            rom: rom.as_ref().to_path_buf(),
+            title: settings.title.clone(),
+            keymap: settings.keymap,
+            paused: false,
+            speed: Speed::Normal,
+            saved: None,
+            config,
+        }
+    }
+
+    pub fn send(&self, r: Request) {
+        if self.requests.send(r).is_err() {
+            println!("ERR: emulator thread stopped");
+        }
+    }
+
+    pub fn title(&self) -> String {
+        let rom = match &self.title {
+            Some(t) => t.into(),
+            None => self.rom.file_name().unwrap_or_default().to_string_lossy(),
+        };
+        match self.paused {
+            true => format!("chip8 - {rom} - paused ({})", self.speed),
+            false => format!("chip8 - {rom} - {}", self.speed),
+        }
+    }
+
+    /// Acts on a hotkey, returning false if it isn't one of ours.
+    fn hotkey(&mut self, key: NamedKey) -> bool {
+        let request = match key {
+            NamedKey::Space => Request::Pause(!self.paused),
+            NamedKey::F2 => Request::Reset(Reset::Warm),
+            NamedKey::F4 => Request::Reset(Reset::Cold),
+            NamedKey::F3 => match open_dialog() {
+                Some(path) => Request::LoadRom(path),
+                None => return true,
+            },
+            NamedKey::F5 => Request::SaveState,
+            NamedKey::F8 => match &self.saved {
+                Some(s) => Request::LoadState(s.clone()),
+                None => return true,
+            },
+            NamedKey::F6 => Request::StepFrame,
+            NamedKey::F7 => Request::StepInstruction,
+            NamedKey::F10 => {
+                match self.config.save() {
+                    Ok(path) => println!("saved {}", path.display()),
+                    Err(e) => println!("ERR: can't save the config: {e}"),
+                }
+                return true;
+            }
+            NamedKey::PageUp => Request::SetSpeed(self.speed.faster()),
+            NamedKey::PageDown => Request::SetSpeed(self.speed.slower()),
+            _ => return false,
+        };
+        self.send(request);
+        true
+    }
+}
+
+/// Asks for a ROM to run.
+fn open_dialog() -> Option<PathBuf> {
+    rfd::FileDialog::new()
+        .set_title("Load ROM")
+        .add_filter("CHIP-8 ROMs", &["ch8", "c8", "rom", "gif"])
+        .add_filter("All files", &["*"])
+        .pick_file()
 }
 
-pub fn handle_event<E>(
-    state: &mut (Rc<Window>, Surface<Rc<Window>, Rc<Window>>),
-    event: Event<E>,
-    elwt: &ActiveEventLoop,
-    cont: Arc<RwLock<Controller>>,
-) where
-    E: UserEvent,
-{
-    let (window, surface) = state;
+pub type State = (
+    Rc<Window>,
+    Surface<Rc<Window>, Rc<Window>>,
+    Presenter,
+    Remote,
+);
+
+pub fn handle_event(state: &mut State, event: Event<Reply>, elwt: &ActiveEventLoop) {
+    let (window, surface, presenter, remote) = state;
     elwt.set_control_flow(ControlFlow::Wait);
 
     match event {
@@ -36,17 +181,13 @@ pub fn handle_event<E>(
             window_id,
             event: WindowEvent::RedrawRequested,
         } if window_id == window.id() => {
-            if let (Some(width), Some(height)) = {
-                let size = PhysicalSize {
-                    width: 1280,
-                    height: 640,
-                };
-                (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
-            } {
+            let (width, height) = presenter.size();
+            if let (Some(width), Some(height)) = (
+                NonZeroU32::new(width as u32),
+                NonZeroU32::new(height as u32),
+            ) {
                 surface.resize(width, height).unwrap();
-                let mut buffer = surface.buffer_mut().unwrap();
-                buffer.fill(0);
-                buffer.present().unwrap();
+                presenter.present(surface, &Dirty::full()).unwrap();
             }
         }
         Event::WindowEvent {
@@ -62,8 +203,57 @@ pub fn handle_event<E>(
                 },
             window_id,
         } if window_id == window.id() => {
+            if let Err(e) = presenter.stop_recording() {
+                println!("ERR: recording failed: {e}");
+            }
             elwt.exit();
         }
+        Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            logical_key: Key::Named(NamedKey::F12),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                },
+            window_id,
+        } if window_id == window.id() => match presenter.screenshot() {
+            Ok(path) => println!("saved {}", path.display()),
+            Err(e) => println!("ERR: screenshot failed: {e}"),
+        },
+        Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            logical_key: Key::Named(NamedKey::F9),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                },
+            window_id,
+        } if window_id == window.id() => match presenter.toggle_recording() {
+            Ok(Some(path)) => println!("recording to {}", path.display()),
+            Ok(None) => println!("recording stopped"),
+            Err(e) => println!("ERR: recording failed: {e}"),
+        },
+        Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            logical_key: Key::Named(key),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                },
+            window_id,
+        } if window_id == window.id() && remote.hotkey(key) => {}
         Event::WindowEvent {
             event:
                 WindowEvent::KeyboardInput {
@@ -76,28 +266,42 @@ pub fn handle_event<E>(
                     ..
                 },
             window_id,
-        } if window_id == window.id() => match cont.try_write() {
-            Ok(mut v) => {
-                match state {
-                    ElementState::Pressed => {
-                        if !v.pressing.contains(&key) {
-                            v.pressing.push(key)
-                        }
-                    }
-                    ElementState::Released => {
-                        if let Some(k) = v.pressing.iter().position(|x| x == &key) {
-                            v.last_released = Some(v.pressing.remove(k));
-                        }
-                    }
-                };
-            }
-            Err(e) => println!("{}", e),
-        },
-        Event::UserEvent(e) => {
-            let mut buffer = surface.buffer_mut().unwrap();
-            e.transform(buffer.as_mut());
-            buffer.present().unwrap();
+        } if window_id == window.id() => {
+            let Some(b) = to_button(&key, &remote.keymap) else {
+                return;
+            };
+            remote.send(match state {
+                ElementState::Pressed => Request::Press(b),
+                ElementState::Released => Request::Release(b),
+            });
+        }
+        Event::UserEvent(Reply::Frame(frame)) => {
+            let dirty = presenter.apply(&DisplayCommand::Draw(frame));
+            presenter.present(surface, &dirty).unwrap();
+        }
+        Event::UserEvent(Reply::Status { paused, speed }) => {
+            (remote.paused, remote.speed) = (paused, speed);
+            window.set_title(&remote.title());
+        }
+        Event::UserEvent(Reply::State(s)) => {
+            remote.saved = Some(s);
+            println!("state saved");
+        }
+        Event::WindowEvent {
+            event: WindowEvent::DroppedFile(path),
+            window_id,
+        } if window_id == window.id() => remote.send(Request::LoadRom(path)),
+        Event::UserEvent(Reply::Loaded(path, settings)) => {
+            println!("loaded {}", path.display());
+            remote.config.add_recent(&path.to_string_lossy());
+            remote.rom = path;
+            remote.title = settings.title;
+            remote.keymap = settings.keymap;
+            window.set_title(&remote.title());
+            let dirty = presenter.set_palette(settings.palette);
+            presenter.present(surface, &dirty).unwrap();
         }
+        Event::UserEvent(Reply::Error(e)) => println!("ERR: {e}"),
         _ => {}
     }
 }
@@ -0,0 +1,187 @@
+use std::str::FromStr;
+
+use crate::internals::display::{Dirty, Rect, HEIGHT, WIDTH};
+
+const DEFAULT_DECAY: f32 = 0.6;
+
+/// How long lit pixels linger after the game turns them off. CHIP-8 games
+/// move sprites by XOR-erasing and redrawing them, so without any
+/// persistence they flicker badly.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Persistence {
+    #[default]
+    Off,
+    /// A pixel shows if it was lit in this frame or the one before.
+    MaxOfTwo,
+    /// Like a phosphor: each frame an unlit pixel keeps this fraction of
+    /// its previous brightness.
+    Decay(f32),
+}
+
+impl FromStr for Persistence {
+    type Err = String;
+
+    /// `off`, `max2`, `decay` or `decay:<fraction kept per frame>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "off" => Ok(Persistence::Off),
+            None if s == "max2" => Ok(Persistence::MaxOfTwo),
+            None if s == "decay" => Ok(Persistence::Decay(DEFAULT_DECAY)),
+            Some(("decay", k)) => match k.parse::<f32>() {
+                Ok(k) if (0.0..1.0).contains(&k) => Ok(Persistence::Decay(k)),
+                _ => Err(format!("decay needs a fraction in [0, 1), got {k}")),
+            },
+            _ => Err(format!("unknown persistence mode {s}")),
+        }
+    }
+}
+
+/// Temporal filter over the native frames the core publishes, run once
+/// per vblank.
+pub struct PhosphorFilter {
+    mode: Persistence,
+    previous: Box<[u32; WIDTH * HEIGHT]>,
+    output: Box<[u32; WIDTH * HEIGHT]>,
+}
+
+impl PhosphorFilter {
+    pub fn new(mode: Persistence) -> Self {
+        PhosphorFilter {
+            mode,
+            previous: Box::new([0; WIDTH * HEIGHT]),
+            output: Box::new([0; WIDTH * HEIGHT]),
+        }
+    }
+
+    pub fn mode(&self) -> Persistence {
+        self.mode
+    }
+
+    pub fn output(&self) -> &[u32; WIDTH * HEIGHT] {
+        &self.output
+    }
+
+    /// Filters the next frame, returning the parts of the output that
+    /// changed. Fading pixels keep changing after the game stops touching
+    /// them, so this can be dirty even when `frame` isn't.
+    pub fn process(&mut self, frame: &[u32; WIDTH * HEIGHT], dirty: &Dirty) -> Dirty {
+        if self.mode == Persistence::Off {
+            for r in dirty.rects() {
+                copy_rect(frame, &mut self.output, *r);
+            }
+            return *dirty;
+        }
+
+        let mut changed = Dirty::default();
+        for y in 0..HEIGHT {
+            let mut span: Option<(usize, usize)> = None;
+            for x in 0..WIDTH {
+                let i = y * WIDTH + x;
+                let pixel = match self.mode {
+                    Persistence::MaxOfTwo => max_channels(frame[i], self.previous[i]),
+                    Persistence::Decay(k) => max_channels(frame[i], fade(self.output[i], k)),
+                    Persistence::Off => frame[i],
+                };
+                if pixel != self.output[i] {
+                    self.output[i] = pixel;
+                    span = Some(span.map_or((x, x), |(start, _)| (start, x)));
+                }
+            }
+            if let Some((start, end)) = span {
+                changed.add(Rect {
+                    x: start,
+                    y,
+                    width: end - start + 1,
+                    height: 1,
+                });
+            }
+        }
+        self.previous.copy_from_slice(frame);
+        changed
+    }
+}
+
+fn copy_rect(from: &[u32; WIDTH * HEIGHT], to: &mut [u32; WIDTH * HEIGHT], r: Rect) {
+    for y in r.y..r.y + r.height {
+        let row = y * WIDTH + r.x..y * WIDTH + r.x + r.width;
+        to[row.clone()].copy_from_slice(&from[row]);
+    }
+}
+
+fn max_channels(a: u32, b: u32) -> u32 {
+    let a = a.to_le_bytes();
+    let b = b.to_le_bytes();
+    u32::from_le_bytes([
+        a[0].max(b[0]),
+        a[1].max(b[1]),
+        a[2].max(b[2]),
+        a[3].max(b[3]),
+    ])
+}
+
+fn fade(pixel: u32, k: f32) -> u32 {
+    u32::from_le_bytes(pixel.to_le_bytes().map(|c| (c as f32 * k) as u8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Persistence, PhosphorFilter};
+    use crate::internals::display::{Dirty, HEIGHT, WIDTH};
+
+    const ON: u32 = 0x0000FFFF;
+
+    fn frame_with(lit: &[usize]) -> [u32; WIDTH * HEIGHT] {
+        let mut f = [0; WIDTH * HEIGHT];
+        for i in lit {
+            f[*i] = ON;
+        }
+        f
+    }
+
+    #[test]
+    fn test_parse_modes() {
+        assert_eq!("off".parse(), Ok(Persistence::Off));
+        assert_eq!("max2".parse(), Ok(Persistence::MaxOfTwo));
+        assert_eq!("decay:0.25".parse(), Ok(Persistence::Decay(0.25)));
+        assert!("decay:2".parse::<Persistence>().is_err());
+        assert!("blur".parse::<Persistence>().is_err());
+    }
+
+    #[test]
+    fn test_off_passes_frames_through() {
+        let mut f = PhosphorFilter::new(Persistence::Off);
+        let dirty = f.process(&frame_with(&[5]), &Dirty::full());
+        assert_eq!(dirty, Dirty::full());
+        assert_eq!(f.output()[5], ON);
+        f.process(&frame_with(&[]), &Dirty::full());
+        assert_eq!(f.output()[5], 0);
+    }
+
+    #[test]
+    fn test_max_of_two_keeps_pixel_one_extra_frame() {
+        let mut f = PhosphorFilter::new(Persistence::MaxOfTwo);
+        f.process(&frame_with(&[5]), &Dirty::full());
+        f.process(&frame_with(&[6]), &Dirty::full());
+        assert_eq!(f.output()[5], ON);
+        assert_eq!(f.output()[6], ON);
+        let dirty = f.process(&frame_with(&[6]), &Dirty::default());
+        assert_eq!(f.output()[5], 0);
+        assert_eq!(dirty.bounds().x, 5);
+        assert_eq!(dirty.bounds().width, 1);
+    }
+
+    #[test]
+    fn test_decay_fades_out() {
+        let mut f = PhosphorFilter::new(Persistence::Decay(0.5));
+        f.process(&frame_with(&[5]), &Dirty::full());
+        f.process(&frame_with(&[]), &Dirty::full());
+        assert_eq!(f.output()[5], 0x00007F7F);
+        f.process(&frame_with(&[]), &Dirty::default());
+        assert_eq!(f.output()[5], 0x00003F3F);
+        for _ in 0..8 {
+            f.process(&frame_with(&[]), &Dirty::default());
+        }
+        assert_eq!(f.output()[5], 0);
+        assert!(f.process(&frame_with(&[]), &Dirty::default()).is_empty());
+    }
+}
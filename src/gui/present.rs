@@ -0,0 +1,325 @@
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use softbuffer::{SoftBufferError, Surface};
+use winit::window::Window;
+
+use crate::capture::recorder::{RecordError, Recorder};
+use crate::capture::screenshot::save_png;
+use crate::internals::display::{Dirty, Palette, Rect, HEIGHT, WIDTH};
+
+use super::effects::Pipeline;
+use super::phosphor::{Persistence, PhosphorFilter};
+use super::UserEvent;
+
+/// Window pixels per CHIP-8 pixel, unless configured otherwise.
+pub const SCALE: usize = 20;
+/// Recordings are much smaller than the window, GIFs get big fast.
+pub const RECORD_SCALE: usize = 4;
+
+/// Keeps the last frame the core sent us, colours it, runs it through the
+/// phosphor filter and pushes the parts of the result that changed to the window
+/// surface, through the post-processing effects if there are any.
+pub struct Presenter {
+    frame: Box<[u32; WIDTH * HEIGHT]>,
+    scale: usize,
+    palette: Palette,
+    coloured: Box<[u32; WIDTH * HEIGHT]>,
+    filter: PhosphorFilter,
+    effects: Pipeline,
+    // the clean upscale the effects start from each frame
+    scaled: Vec<u32>,
+    screenshot_dir: PathBuf,
+    recorder: Option<Recorder>,
+}
+
+impl Default for Presenter {
+    fn default() -> Self {
+        Presenter::new(Persistence::Off)
+    }
+}
+
+impl Presenter {
+    pub fn new(persistence: Persistence) -> Self {
+        Presenter {
+            frame: Box::new([0; WIDTH * HEIGHT]),
+            scale: SCALE,
+            palette: Palette::default(),
+            coloured: Box::new([0; WIDTH * HEIGHT]),
+            filter: PhosphorFilter::new(persistence),
+            effects: Pipeline::default(),
+            scaled: Vec::new(),
+            screenshot_dir: PathBuf::from("."),
+            recorder: None,
+        }
+    }
+
+    pub fn with_screenshot_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.screenshot_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Effects need building for the same scale, so set this first.
+    pub fn with_scale(mut self, scale: usize) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
+
+    pub fn with_effects(mut self, effects: Pipeline) -> Self {
+        if !effects.is_empty() {
+            let (width, height) = self.size();
+            self.scaled = vec![0; width * height];
+            scale_rect(
+                self.filter.output(),
+                &mut self.scaled,
+                Rect::full(),
+                self.scale,
+            );
+        }
+        self.effects = effects;
+        self
+    }
+
+    /// How big the window's picture is.
+    pub fn size(&self) -> (usize, usize) {
+        (WIDTH * self.scale, HEIGHT * self.scale)
+    }
+
+    /// The native frame as it's shown, after filtering.
+    pub fn frame(&self) -> &[u32; WIDTH * HEIGHT] {
+        self.filter.output()
+    }
+
+    /// Applies the next frame. Frames arrive once per vblank, so this is
+    /// also where they get recorded.
+    pub fn apply<E: UserEvent>(&mut self, e: &E) -> Dirty {
+        let dirty = e.transform(&mut self.frame);
+        self.colour(&dirty);
+        let dirty = self.filter.process(&self.coloured, &dirty);
+        if let Some(r) = &mut self.recorder {
+            if let Err(e) = r.push(self.filter.output()) {
+                println!("ERR: recording failed: {e}");
+                self.recorder = None;
+            }
+        }
+        dirty
+    }
+
+    /// Switches colours, returning what needs redrawing.
+    pub fn set_palette(&mut self, palette: Palette) -> Dirty {
+        self.palette = palette;
+        self.colour(&Dirty::full());
+        self.filter.process(&self.coloured, &Dirty::full())
+    }
+
+    fn colour(&mut self, dirty: &Dirty) {
+        for r in dirty.rects() {
+            for y in r.y..r.y + r.height {
+                for i in y * WIDTH + r.x..y * WIDTH + r.x + r.width {
+                    self.coloured[i] = self.palette.colour(self.frame[i]);
+                }
+            }
+        }
+    }
+
+    pub fn start_recording(
+        &mut self,
+        path: impl AsRef<Path>,
+        scale: usize,
+    ) -> Result<(), RecordError> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::create(path, scale)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<(), RecordError> {
+        match self.recorder.take() {
+            Some(r) => r.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Starts recording `chip8-<time>.gif` to the screenshot directory, or
+    /// stops the current recording.
+    pub fn toggle_recording(&mut self) -> Result<Option<PathBuf>, RecordError> {
+        if self.is_recording() {
+            return self.stop_recording().map(|_| None);
+        }
+        let path = self
+            .screenshot_dir
+            .join(format!("chip8-{}.gif", timestamp()));
+        self.start_recording(&path, RECORD_SCALE)?;
+        Ok(Some(path))
+    }
+
+    /// The whole frame as the window shows it: filtered, upscaled and run
+    /// through the effects.
+    pub fn render(&mut self) -> Vec<u32> {
+        let (width, height) = self.size();
+        let mut b = vec![0; width * height];
+        scale_rect(self.filter.output(), &mut b, Rect::full(), self.scale);
+        self.effects.apply(&mut b, width, height);
+        b
+    }
+
+    /// Saves both what the window shows and the native frame, as
+    /// `chip8-<time>.png` and `chip8-<time>-native.png`.
+    pub fn screenshot(&mut self) -> Result<PathBuf, png::EncodingError> {
+        let time = timestamp();
+        let path = self.screenshot_dir.join(format!("chip8-{time}.png"));
+        let (width, height) = self.size();
+        save_png(&path, &self.render(), width, height)?;
+        save_png(
+            self.screenshot_dir.join(format!("chip8-{time}-native.png")),
+            self.frame(),
+            WIDTH,
+            HEIGHT,
+        )?;
+        Ok(path)
+    }
+
+    /// Rescales the dirty regions into the surface and presents only those.
+    /// If the surface buffer doesn't hold our previous frame we can't trust
+    /// anything outside of the damage, so everything gets redrawn. Effects
+    /// aren't local to a pixel, so with any enabled the whole frame is.
+    pub fn present(
+        &mut self,
+        surface: &mut Surface<Rc<Window>, Rc<Window>>,
+        dirty: &Dirty,
+    ) -> Result<(), SoftBufferError> {
+        let mut buffer = surface.buffer_mut()?;
+        let dirty = if buffer.age() == 1 {
+            *dirty
+        } else {
+            Dirty::full()
+        };
+        if dirty.is_empty() {
+            return Ok(());
+        }
+
+        if !self.effects.is_empty() {
+            for r in dirty.rects() {
+                scale_rect(self.filter.output(), &mut self.scaled, *r, self.scale);
+            }
+            buffer.copy_from_slice(&self.scaled);
+            let (width, height) = self.size();
+            self.effects.apply(&mut buffer, width, height);
+            return buffer.present();
+        }
+
+        for r in dirty.rects() {
+            scale_rect(self.frame(), &mut buffer, *r, self.scale);
+        }
+        let damage: Vec<softbuffer::Rect> = dirty
+            .rects()
+            .iter()
+            .map(|r| to_damage(r, self.scale))
+            .collect();
+        buffer.present_with_damage(&damage)
+    }
+}
+
+fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// Nearest-neighbour upscale of `rect` from the native frame into a
+/// buffer `scale` times as big.
+pub fn scale_rect(frame: &[u32], b: &mut [u32], rect: Rect, scale: usize) {
+    let width = WIDTH * scale;
+    for y in rect.y..rect.y + rect.height {
+        let src = &frame[y * WIDTH + rect.x..y * WIDTH + rect.x + rect.width];
+        let row_start = y * scale * width + rect.x * scale;
+        let first_row = &mut b[row_start..row_start + rect.width * scale];
+        for (dst, pixel) in first_row.chunks_exact_mut(scale).zip(src) {
+            dst.fill(*pixel);
+        }
+        // every other line of this pixel row is identical to the first
+        for dy in 1..scale {
+            let start = row_start + dy * width;
+            b.copy_within(row_start..row_start + rect.width * scale, start);
+        }
+    }
+}
+
+fn to_damage(r: &Rect, scale: usize) -> softbuffer::Rect {
+    softbuffer::Rect {
+        x: (r.x * scale) as u32,
+        y: (r.y * scale) as u32,
+        width: NonZeroU32::new((r.width * scale) as u32).unwrap(),
+        height: NonZeroU32::new((r.height * scale) as u32).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{scale_rect, Presenter, SCALE};
+    use crate::internals::display::{Dirty, DisplayCommand, Frame, Palette, Rect, HEIGHT, WIDTH};
+
+    const SCALED_WIDTH: usize = WIDTH * SCALE;
+    const SCALED_HEIGHT: usize = HEIGHT * SCALE;
+
+    #[test]
+    fn test_scale_rect_only_touches_rect() {
+        let mut frame = [0; WIDTH * HEIGHT];
+        frame[2 * WIDTH + 3] = 7;
+        let mut b = vec![1; SCALED_WIDTH * SCALED_HEIGHT];
+        let rect = Rect {
+            x: 3,
+            y: 2,
+            width: 2,
+            height: 1,
+        };
+        scale_rect(&frame, &mut b, rect, SCALE);
+
+        for y in 0..SCALED_HEIGHT {
+            for x in 0..SCALED_WIDTH {
+                let expected = match (x / SCALE, y / SCALE) {
+                    (3, 2) => 7,
+                    (4, 2) => 0,
+                    _ => 1,
+                };
+                assert_eq!(b[y * SCALED_WIDTH + x], expected, "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_palette() {
+        let mut p = Presenter::default();
+        let mut pixels = [0; WIDTH * HEIGHT];
+        pixels[1] = 0xFFFF;
+        p.apply(&DisplayCommand::Draw(Box::new(Frame {
+            pixels,
+            dirty: Dirty::full(),
+        })));
+        assert_eq!(&p.frame()[..2], &[0, 0xFFFF]);
+
+        let dirty = p.set_palette(Palette {
+            off: 0x111111,
+            on: 0xEEEEEE,
+        });
+        assert!(!dirty.is_empty());
+        assert_eq!(&p.frame()[..3], &[0x111111, 0xEEEEEE, 0x111111]);
+    }
+
+    #[test]
+    fn test_scale_full_frame() {
+        let mut frame = [0; WIDTH * HEIGHT];
+        frame[WIDTH * HEIGHT - 1] = 5;
+        let mut b = vec![1; SCALED_WIDTH * SCALED_HEIGHT];
+        scale_rect(&frame, &mut b, Rect::full(), SCALE);
+        assert_eq!(b.iter().filter(|p| **p == 5).count(), SCALE * SCALE);
+        assert_eq!(b[SCALED_WIDTH * SCALED_HEIGHT - 1], 5);
+        assert_eq!(b[0], 0);
+    }
+}
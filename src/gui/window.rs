@@ -1,20 +1,12 @@
 use std::marker::PhantomData;
 use std::rc::Rc;
-use std::sync::{Arc, RwLock};
 
 use winit::application::ApplicationHandler;
 use winit::event::{Event, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, EventLoop};
 use winit::window::{Window, WindowAttributes, WindowId};
 
-use crate::Controller;
-
-use super::UserEvent;
-
-pub(crate) fn init<E>(event_loop: EventLoop<E>, mut app: impl ApplicationHandler<E>)
-where
-    E: UserEvent,
-{
+pub fn init<E: 'static>(event_loop: EventLoop<E>, mut app: impl ApplicationHandler<E>) {
     #[cfg(not(any(target_arch = "wasm32", target_arch = "wasm64")))]
     event_loop.run_app(&mut app).unwrap();
 
@@ -22,7 +14,7 @@ where
     winit::platform::web::EventLoopExtWebSys::spawn_app(event_loop, app);
 }
 
-pub(crate) fn make_window(
+pub fn make_window(
     elwt: &ActiveEventLoop,
     f: impl FnOnce(WindowAttributes) -> WindowAttributes,
 ) -> Rc<Window> {
@@ -33,21 +25,14 @@ pub(crate) fn make_window(
     Rc::new(window.unwrap())
 }
 
-pub(crate) struct WinitApp<T, Init, Handler, E>
-where
-    E: UserEvent,
-{
+pub struct WinitApp<T, Init, Handler, E> {
     init: Init,
     event: Handler,
     state: Option<T>,
-    controller: Arc<RwLock<Controller>>,
     _event_marker: Option<E>,
 }
 
-pub(crate) struct WinitAppBuilder<T, Init, E>
-where
-    E: UserEvent,
-{
+pub struct WinitAppBuilder<T, Init, E> {
     init: Init,
     _marker: PhantomData<Option<T>>,
     _event_marker: PhantomData<Option<E>>,
@@ -56,9 +41,8 @@ where
 impl<T, Init, E: 'static> WinitAppBuilder<T, Init, E>
 where
     Init: FnMut(&ActiveEventLoop) -> T,
-    E: UserEvent,
 {
-    pub(crate) fn with_init(init: Init) -> Self {
+    pub fn with_init(init: Init) -> Self {
         Self {
             init,
             _marker: PhantomData,
@@ -66,30 +50,23 @@ where
         }
     }
 
-    pub(crate) fn with_event_handler<F>(
-        self,
-        handler: F,
-        controller: Arc<RwLock<Controller>>,
-    ) -> WinitApp<T, Init, F, E>
+    pub fn with_event_handler<F>(self, handler: F) -> WinitApp<T, Init, F, E>
     where
-        F: FnMut(&mut T, Event<E>, &ActiveEventLoop, Arc<RwLock<Controller>>),
-        E: UserEvent,
+        F: FnMut(&mut T, Event<E>, &ActiveEventLoop),
     {
-        WinitApp::new(self.init, handler, controller)
+        WinitApp::new(self.init, handler)
     }
 }
 
 impl<T, Init, Handler, E> WinitApp<T, Init, Handler, E>
 where
     Init: FnMut(&ActiveEventLoop) -> T,
-    Handler: FnMut(&mut T, Event<E>, &ActiveEventLoop, Arc<RwLock<Controller>>),
-    E: UserEvent,
+    Handler: FnMut(&mut T, Event<E>, &ActiveEventLoop),
 {
-    pub(crate) fn new(init: Init, event: Handler, controller: Arc<RwLock<Controller>>) -> Self {
+    pub fn new(init: Init, event: Handler) -> Self {
         Self {
             init,
             event,
-            controller,
             state: None,
             _event_marker: None,
         }
@@ -99,8 +76,7 @@ where
 impl<T, Init, Handler, E: 'static> ApplicationHandler<E> for WinitApp<T, Init, Handler, E>
 where
     Init: FnMut(&ActiveEventLoop) -> T,
-    Handler: FnMut(&mut T, Event<E>, &ActiveEventLoop, Arc<RwLock<Controller>>),
-    E: UserEvent,
+    Handler: FnMut(&mut T, Event<E>, &ActiveEventLoop),
 {
     fn resumed(&mut self, el: &ActiveEventLoop) {
         debug_assert!(self.state.is_none());
@@ -120,32 +96,17 @@ where
         event: WindowEvent,
     ) {
         let state = self.state.as_mut().unwrap();
-        (self.event)(
-            state,
-            Event::WindowEvent { window_id, event },
-            event_loop,
-            Arc::clone(&self.controller),
-        );
+        (self.event)(state, Event::WindowEvent { window_id, event }, event_loop);
     }
 
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         if let Some(state) = self.state.as_mut() {
-            (self.event)(
-                state,
-                Event::AboutToWait,
-                event_loop,
-                Arc::clone(&self.controller),
-            );
+            (self.event)(state, Event::AboutToWait, event_loop);
         }
     }
 
     fn user_event(&mut self, event_loop: &ActiveEventLoop, event: E) {
         let state = self.state.as_mut().unwrap();
-        (self.event)(
-            state,
-            Event::UserEvent(event),
-            event_loop,
-            Arc::clone(&self.controller),
-        );
+        (self.event)(state, Event::UserEvent(event), event_loop);
     }
 }
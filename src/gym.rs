@@ -0,0 +1,336 @@
+use std::path::Path;
+
+use rand::prelude::*;
+use serde::Deserialize;
+
+use crate::batch::jumps_to_itself;
+use crate::internals::{
+    display::{HEIGHT, WIDTH},
+    keypad::Button,
+    memory::MEMORY_SIZE,
+    Chip8, Chip8Error, Reset,
+};
+
+/// What a game's score and end look like in its memory, so an agent can be
+/// rewarded for playing it. Kept in TOML, one file per game:
+///
+/// ```toml
+/// title = "Pong"
+/// # the buttons each action holds down, by action number
+/// actions = [[], [1], [4]]
+///
+/// # reward is each value's change times its weight
+/// [[reward]]
+/// register = 14
+/// [[reward]]
+/// register = 13
+/// weight = -1.0
+///
+/// # and the episode's over once any of these reaches its value
+/// [[done]]
+/// memory = 0x3F0
+/// at_least = 9
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Spec {
+    pub title: Option<String>,
+    /// Without any, there are 17: nothing held, then each key on its own.
+    pub actions: Vec<Vec<u8>>,
+    pub reward: Vec<Term>,
+    pub done: Vec<Until>,
+}
+
+/// Somewhere a program keeps a byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Register(u8),
+    Memory(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct Term {
+    #[serde(flatten)]
+    pub source: Source,
+    #[serde(default = "one")]
+    pub weight: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct Until {
+    #[serde(flatten)]
+    pub source: Source,
+    pub at_least: u8,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+impl Default for Spec {
+    fn default() -> Self {
+        Spec {
+            title: None,
+            actions: std::iter::once(Vec::new())
+                .chain((0..16).map(|k| vec![k]))
+                .collect(),
+            reward: Vec::new(),
+            done: Vec::new(),
+        }
+    }
+}
+
+impl Spec {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| Spec::parse(&s))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let spec: Spec = toml::from_str(s).map_err(|e| format!("bad spec: {e}"))?;
+        let sources = spec.reward.iter().map(|t| t.source);
+        for source in sources.chain(spec.done.iter().map(|u| u.source)) {
+            match source {
+                Source::Register(r) if r > 0xF => return Err(format!("no register v{r:x}")),
+                Source::Memory(a) if a as usize >= MEMORY_SIZE => {
+                    return Err(format!("{a:#x} is past the end of memory"))
+                }
+                _ => (),
+            }
+        }
+        if let Some(k) = spec.actions.iter().flatten().find(|k| **k > 0xF) {
+            return Err(format!("no key {k:x}"));
+        }
+        if spec.actions.is_empty() {
+            return Err("there has to be at least one action".to_string());
+        }
+        Ok(spec)
+    }
+}
+
+impl Source {
+    pub fn read(&self, chip8: &Chip8) -> u8 {
+        match *self {
+            Source::Register(r) => chip8.registers.r[r as usize],
+            Source::Memory(a) => chip8.memory.0[a as usize],
+        }
+    }
+}
+
+/// The frame buffer one bit per pixel: a `u64` per row, with the leftmost
+/// pixel in the top bit.
+pub type Observation = [u64; HEIGHT];
+
+/// What came of a step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    pub reward: f32,
+    /// The spec says the game's over, or the program has stopped.
+    pub done: bool,
+}
+
+/// A game to be played a frame at a time by a program rather than a
+/// person, gym style. Neither resetting nor stepping allocates, except for
+/// the JIT compiling code it hasn't reached since the last reset.
+pub struct Env {
+    pub chip8: Chip8,
+    pub instructions_per_frame: usize,
+    /// How many frames each step runs, its rewards summed.
+    pub frame_skip: usize,
+    /// The chance, each frame, that the previous frame's keys stay held
+    /// instead of the new action's.
+    pub sticky: f32,
+    spec: Spec,
+    // each action's keys, a bit per key
+    actions: Vec<u16>,
+    held: u16,
+    // the reward terms' values as of the last frame
+    values: Vec<u8>,
+    observation: Observation,
+    rng: StdRng,
+}
+
+impl Env {
+    /// `chip8` should have its ROM loaded already. It starts over on the
+    /// first reset.
+    pub fn new(mut chip8: Chip8, instructions_per_frame: usize, spec: Spec) -> Self {
+        // room for every key, so holding them doesn't allocate
+        chip8.keypad.pressing.reserve(16);
+        Env {
+            chip8,
+            instructions_per_frame,
+            frame_skip: 1,
+            sticky: 0.0,
+            actions: spec
+                .actions
+                .iter()
+                .map(|keys| keys.iter().fold(0, |mask, k| mask | 1 << k))
+                .collect(),
+            held: 0,
+            values: vec![0; spec.reward.len()],
+            spec,
+            observation: [0; HEIGHT],
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+
+    pub fn spec(&self) -> &Spec {
+        &self.spec
+    }
+
+    /// How many actions `step` takes.
+    pub fn actions(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Powers the machine back on with nothing held. The same seed and the
+    /// same actions play out the same way, sticky keys included.
+    pub fn reset(&mut self, seed: u64) -> &Observation {
+        self.chip8.reset(Reset::Cold);
+        self.chip8.seed(seed);
+        // a stream of its own, so sticking doesn't shadow Cxnn
+        self.rng = StdRng::seed_from_u64(!seed);
+        self.held = 0;
+        self.hold();
+        self.chip8.keypad.last_released = None;
+        for (v, t) in self.values.iter_mut().zip(&self.spec.reward) {
+            *v = t.source.read(&self.chip8);
+        }
+        self.observe();
+        &self.observation
+    }
+
+    /// Holds `action`'s keys, and nothing else, for the next `frame_skip`
+    /// frames. Panics if there's no such action.
+    pub fn step(&mut self, action: usize) -> Result<Step, Chip8Error> {
+        let keys = self.actions[action];
+        let mut step = Step {
+            reward: 0.0,
+            done: false,
+        };
+        for _ in 0..self.frame_skip.max(1) {
+            if self.sticky <= 0.0 || self.rng.gen::<f32>() >= self.sticky {
+                self.held = keys;
+            }
+            self.hold();
+            self.chip8.run_frame(self.instructions_per_frame)?;
+            step.reward += self.reward();
+            if self.done() {
+                step.done = true;
+                break;
+            }
+        }
+        self.observe();
+        Ok(step)
+    }
+
+    pub fn observation(&self) -> &Observation {
+        &self.observation
+    }
+
+    /// Presses what's in `held` and releases the rest.
+    fn hold(&mut self) {
+        for k in 0..16 {
+            match self.held & 1 << k != 0 {
+                true => self.chip8.keypad.press(Button::from_u8(k)),
+                false => self.chip8.keypad.release(Button::from_u8(k)),
+            }
+        }
+    }
+
+    /// How much the reward terms changed since the last frame.
+    fn reward(&mut self) -> f32 {
+        let mut reward = 0.0;
+        for (v, t) in self.values.iter_mut().zip(&self.spec.reward) {
+            let now = t.source.read(&self.chip8);
+            reward += t.weight * (now as f32 - *v as f32);
+            *v = now;
+        }
+        reward
+    }
+
+    fn done(&self) -> bool {
+        jumps_to_itself(&self.chip8)
+            || self
+                .spec
+                .done
+                .iter()
+                .any(|u| u.source.read(&self.chip8) >= u.at_least)
+    }
+
+    fn observe(&mut self) {
+        for (row, pixels) in self
+            .observation
+            .iter_mut()
+            .zip(self.chip8.frame_buffer.chunks_exact(WIDTH))
+        {
+            *row = pixels
+                .iter()
+                .fold(0, |bits, p| bits << 1 | (*p != 0) as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Source, Spec, Term, Until};
+
+    #[test]
+    fn test_spec() {
+        let s = Spec::parse(
+            r#"
+            title = "Pong"
+            actions = [[], [1], [4, 5]]
+            [[reward]]
+            register = 14
+            [[reward]]
+            memory = 0x300
+            weight = -0.5
+            [[done]]
+            register = 13
+            at_least = 9
+            "#,
+        )
+        .unwrap();
+        assert_eq!(s.title.as_deref(), Some("Pong"));
+        assert_eq!(s.actions[2], [4, 5]);
+        assert_eq!(
+            s.reward,
+            [
+                Term {
+                    source: Source::Register(14),
+                    weight: 1.0
+                },
+                Term {
+                    source: Source::Memory(0x300),
+                    weight: -0.5
+                }
+            ]
+        );
+        assert_eq!(
+            s.done,
+            [Until {
+                source: Source::Register(13),
+                at_least: 9
+            }]
+        );
+
+        let d = Spec::parse("").unwrap();
+        assert_eq!(d.actions.len(), 17);
+        assert_eq!(d.actions[16], [15]);
+    }
+
+    #[test]
+    fn test_bad_specs() {
+        assert!(Spec::parse("[[reward]]\nregister = 16").is_err());
+        assert!(Spec::parse("[[reward]]\nmemory = 4096").is_err());
+        assert!(Spec::parse("actions = [[16]]").is_err());
+        assert!(Spec::parse("actions = []").is_err());
+        assert!(Spec::parse("[[reward]]\nweight = 2.0").is_err());
+        assert!(Spec::parse("score = 1").is_err());
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use crate::database::{Database, Settings};
+use crate::internals::{memory::LoadError, Chip8, Chip8Error, Reset};
+
+/// Runs a machine without a window, as fast as it'll go.
+pub struct Headless {
+    pub chip8: Chip8,
+    pub instructions_per_frame: usize,
+    pub database: Database,
+    /// What ROMs the database doesn't know about run with.
+    pub settings: Settings,
+}
+
+impl Headless {
+    pub fn new(chip8: Chip8, instructions_per_frame: usize) -> Self {
+        Headless {
+            settings: Settings::of(&chip8, instructions_per_frame),
+            chip8,
+            instructions_per_frame,
+            database: Database::default(),
+        }
+    }
+
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<Settings, LoadError> {
+        let settings =
+            self.database
+                .load_file(&mut self.chip8, path, &self.settings, Reset::Cold)?;
+        self.instructions_per_frame = settings.instructions_per_frame;
+        Ok(settings)
+    }
+
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        self.chip8.run_frame(self.instructions_per_frame)
+    }
+
+    pub fn run_frames(&mut self, frames: usize) -> Result<(), Chip8Error> {
+        for _ in 0..frames {
+            self.run_frame()?;
+        }
+        Ok(())
+    }
+
+    pub fn frame(&self) -> &[u32] {
+        &self.chip8.frame_buffer
+    }
+}
@@ -0,0 +1,72 @@
+use super::FRAME_RATE;
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// The CHIP-8 buzzer: a square wave for as long as the sound timer is
+/// non-zero. It's rendered a frame at a time from the emulated timer, never
+/// from the wall clock, so the same run always produces the same samples.
+pub struct Beeper {
+    pub sample_rate: u32,
+    pub frequency: u32,
+    pub volume: i16,
+    phase: u32,
+}
+
+impl Default for Beeper {
+    fn default() -> Self {
+        Beeper {
+            sample_rate: SAMPLE_RATE,
+            frequency: 440,
+            volume: i16::MAX / 4,
+            phase: 0,
+        }
+    }
+}
+
+impl Beeper {
+    pub fn samples_per_frame(&self) -> usize {
+        (self.sample_rate / FRAME_RATE) as usize
+    }
+
+    /// Appends one frame's worth of mono samples to `out`.
+    pub fn render_frame(&mut self, beeping: bool, out: &mut Vec<i16>) {
+        for _ in 0..self.samples_per_frame() {
+            // phase counts in units of 1 / (frequency * sample_rate) of a
+            // cycle so it never drifts
+            self.phase = (self.phase + self.frequency) % self.sample_rate;
+            out.push(match beeping {
+                false => 0,
+                true if self.phase < self.sample_rate / 2 => self.volume,
+                true => -self.volume,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Beeper, SAMPLE_RATE};
+
+    #[test]
+    fn test_frame_is_735_samples() {
+        let mut b = Beeper::default();
+        let mut out = Vec::new();
+        b.render_frame(true, &mut out);
+        assert_eq!(out.len(), 735);
+        b.render_frame(false, &mut out);
+        assert_eq!(out.len(), 2 * 735);
+        assert!(out[735..].iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn test_square_wave_frequency() {
+        let mut b = Beeper::default();
+        let mut out = Vec::new();
+        for _ in 0..60 {
+            b.render_frame(true, &mut out);
+        }
+        assert_eq!(out.len(), SAMPLE_RATE as usize);
+        let rising = out.windows(2).filter(|w| w[0] < 0 && w[1] > 0).count();
+        assert!((439..=441).contains(&rising), "{rising}");
+    }
+}
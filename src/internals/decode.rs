@@ -0,0 +1,86 @@
+use std::ops::Range;
+
+use super::{memory::MEMORY_SIZE, parse_opcode, Instruction};
+
+/// Instructions already decoded, by the address they were fetched from.
+/// Anything that writes to memory has to invalidate what it wrote over,
+/// since programs are free to rewrite their own code.
+#[derive(Clone)]
+pub struct DecodeCache(Box<[Option<Instruction>; MEMORY_SIZE]>);
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        DecodeCache(Box::new([None; MEMORY_SIZE]))
+    }
+}
+
+impl DecodeCache {
+    /// The instruction at `pc`, decoding it from `memory` the first time.
+    /// Addresses wrap at the end of memory, so the last byte's instruction
+    /// carries on at the first.
+    pub fn fetch(&mut self, memory: &[u8; MEMORY_SIZE], pc: usize) -> Instruction {
+        let pc = pc % MEMORY_SIZE;
+        if let Some(i) = self.0[pc] {
+            return i;
+        }
+        let next = (pc + 1) % MEMORY_SIZE;
+        let i = parse_opcode(u16::from_be_bytes([memory[pc], memory[next]]));
+        self.0[pc] = Some(i);
+        i
+    }
+
+    /// Forgets instructions that overlap `range`, including one that
+    /// starts the byte before it.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        let start = range.start.saturating_sub(1);
+        let end = range.end.min(MEMORY_SIZE);
+        if start < end {
+            self.0[start..end].fill(None);
+        }
+        // the instruction that wraps round into the first byte
+        if range.start == 0 && !range.is_empty() {
+            self.0[MEMORY_SIZE - 1] = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.fill(None);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DecodeCache;
+    use crate::internals::{memory::MEMORY_SIZE, Instruction, Register};
+
+    #[test]
+    fn test_invalidate() {
+        let mut memory = [0; MEMORY_SIZE];
+        memory[0x200..0x204].copy_from_slice(&[0x12, 0x00, 0x60, 0x01]);
+        let mut cache = DecodeCache::default();
+        assert_eq!(cache.fetch(&memory, 0x200), Instruction::JumpTo(0x200));
+        assert_eq!(
+            cache.fetch(&memory, 0x202),
+            Instruction::LoadInto(Register::V0, 1)
+        );
+
+        memory[0x201] = 0x02;
+        memory[0x203] = 0x02;
+        // not told yet
+        assert_eq!(cache.fetch(&memory, 0x200), Instruction::JumpTo(0x200));
+        // the second byte of one instruction is the first of the one before
+        cache.invalidate(0x201..0x202);
+        assert_eq!(cache.fetch(&memory, 0x200), Instruction::JumpTo(0x202));
+        assert_eq!(
+            cache.fetch(&memory, 0x202),
+            Instruction::LoadInto(Register::V0, 1)
+        );
+        cache.invalidate(0x203..0x204);
+        assert_eq!(
+            cache.fetch(&memory, 0x202),
+            Instruction::LoadInto(Register::V0, 2)
+        );
+        cache.clear();
+        cache.invalidate(0..MEMORY_SIZE + 2);
+    }
+}
@@ -0,0 +1,282 @@
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+// more rects than this and we just fall back to their bounding box
+const MAX_RECTS: usize = 8;
+
+/// A rectangle of native (64x32) pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn full() -> Self {
+        Rect {
+            x: 0,
+            y: 0,
+            width: WIDTH,
+            height: HEIGHT,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    /// True when the two rects overlap or sit right next to each other.
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.x + other.width
+            && other.x <= self.x + self.width
+            && self.y <= other.y + other.height
+            && other.y <= self.y + self.height
+    }
+}
+
+/// The set of regions of the frame buffer that changed since the last
+/// time it was presented. Fixed capacity so the core never allocates
+/// while tracking it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Dirty {
+    rects: [Rect; MAX_RECTS],
+    len: usize,
+}
+
+impl Dirty {
+    pub fn full() -> Self {
+        let mut d = Dirty::default();
+        d.add(Rect::full());
+        d
+    }
+
+    /// Marks the area touched by a sprite of `rows` lines drawn at (x, y).
+    /// Sprites wrap around the screen edges, so this can be up to four rects.
+    pub fn sprite(x: u8, y: u8, rows: u8) -> Self {
+        let mut d = Dirty::default();
+        let (x, y) = (x as usize % WIDTH, y as usize % HEIGHT);
+        for (x, width) in wrap(x, 8, WIDTH) {
+            for (y, height) in wrap(y, rows as usize, HEIGHT) {
+                d.add(Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                });
+            }
+        }
+        d
+    }
+
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        let mut rect = rect;
+        // merge with everything it touches, repeating since the merged
+        // rect may now touch rects it didn't before
+        let mut i = 0;
+        while i < self.len {
+            if self.rects[i].touches(&rect) {
+                rect = rect.union(&self.rects[i]);
+                self.len -= 1;
+                self.rects[i] = self.rects[self.len];
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+        if self.len == MAX_RECTS {
+            rect = self.bounds().union(&rect);
+            self.len = 0;
+        }
+        self.rects[self.len] = rect;
+        self.len += 1;
+    }
+
+    pub fn merge(&mut self, other: &Dirty) {
+        for r in other.rects() {
+            self.add(*r);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects[..self.len]
+    }
+
+    /// The smallest rect covering every dirty region.
+    pub fn bounds(&self) -> Rect {
+        self.rects()
+            .iter()
+            .fold(None, |acc: Option<Rect>, r| {
+                Some(acc.map_or(*r, |a| a.union(r)))
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Splits a span starting at `start` that wraps at `max` into at most two
+/// non-wrapping (start, length) spans.
+fn wrap(start: usize, len: usize, max: usize) -> impl Iterator<Item = (usize, usize)> {
+    let len = len.min(max);
+    let first = len.min(max - start);
+    [(start, first), (0, len - first)]
+        .into_iter()
+        .filter(|(_, l)| *l > 0)
+}
+
+/// A copy of the frame buffer along with what changed in it.
+#[derive(Clone, PartialEq)]
+pub struct Frame {
+    pub pixels: [u32; WIDTH * HEIGHT],
+    pub dirty: Dirty,
+}
+
+/// What colours lit and unlit pixels are shown in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub off: u32,
+    pub on: u32,
+}
+
+impl Default for Palette {
+    /// The colours the core draws in, so this changes nothing.
+    fn default() -> Self {
+        Palette {
+            off: 0,
+            on: 0x0000FFFF,
+        }
+    }
+}
+
+impl Palette {
+    pub fn colour(&self, pixel: u32) -> u32 {
+        match pixel {
+            0 => self.off,
+            _ => self.on,
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub enum DisplayCommand {
+    ClearDisplay,
+    Draw(Box<Frame>),
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Dirty, Rect, HEIGHT, WIDTH};
+
+    #[test]
+    fn test_sprite_dirty_rect() {
+        let d = Dirty::sprite(10, 4, 5);
+        assert_eq!(
+            d.rects(),
+            &[Rect {
+                x: 10,
+                y: 4,
+                width: 8,
+                height: 5
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sprite_dirty_wraps() {
+        let d = Dirty::sprite(60, 30, 4);
+        let mut rects = d.rects().to_vec();
+        rects.sort_by_key(|r| (r.y, r.x));
+        assert_eq!(
+            rects,
+            vec![
+                Rect {
+                    x: 0,
+                    y: 0,
+                    width: 4,
+                    height: 2
+                },
+                Rect {
+                    x: 60,
+                    y: 0,
+                    width: 4,
+                    height: 2
+                },
+                Rect {
+                    x: 0,
+                    y: 30,
+                    width: 4,
+                    height: 2
+                },
+                Rect {
+                    x: 60,
+                    y: 30,
+                    width: 4,
+                    height: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dirty_merges_touching_rects() {
+        let mut d = Dirty::sprite(0, 0, 5);
+        d.merge(&Dirty::sprite(8, 0, 5));
+        d.merge(&Dirty::sprite(40, 20, 1));
+        assert_eq!(d.rects().len(), 2);
+        assert_eq!(
+            d.rects()[0],
+            Rect {
+                x: 0,
+                y: 0,
+                width: 16,
+                height: 5
+            }
+        );
+    }
+
+    #[test]
+    fn test_dirty_collapses_when_full() {
+        let mut d = Dirty::default();
+        for i in 0..16 {
+            d.add(Rect {
+                x: (i % 4) * 16,
+                y: (i / 4) * 8,
+                width: 1,
+                height: 1,
+            });
+        }
+        assert!(d.rects().len() <= 8);
+        assert_eq!(
+            d.bounds(),
+            Rect {
+                x: 0,
+                y: 0,
+                width: 49,
+                height: 25
+            }
+        );
+        assert!(d.bounds().width <= WIDTH && d.bounds().height <= HEIGHT);
+    }
+}
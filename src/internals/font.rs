@@ -0,0 +1,262 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Bytes per small (4x5) digit.
+pub const SMALL_HEIGHT: usize = 5;
+/// Bytes per big (8x10) digit.
+pub const BIG_HEIGHT: usize = 10;
+
+/// Whose hex digits to use. The small digits differ a little between
+/// interpreters, and only SCHIP and Octo came with big ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FontSet {
+    Vip,
+    Dream6800,
+    Eti660,
+    Schip,
+    #[default]
+    Octo,
+}
+
+impl FontSet {
+    pub fn small(&self) -> &'static [u8; 16 * SMALL_HEIGHT] {
+        match self {
+            FontSet::Vip => &VIP,
+            FontSet::Dream6800 => &DREAM_6800,
+            FontSet::Eti660 => &ETI_660,
+            FontSet::Schip | FontSet::Octo => &OCTO,
+        }
+    }
+
+    /// SCHIP only has big versions of 0 to 9.
+    pub fn big(&self) -> Option<&'static [u8]> {
+        match self {
+            FontSet::Schip => Some(&SCHIP_BIG),
+            FontSet::Octo => Some(&OCTO_BIG),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for FontSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vip" => Ok(FontSet::Vip),
+            "dream6800" => Ok(FontSet::Dream6800),
+            "eti660" => Ok(FontSet::Eti660),
+            "schip" => Ok(FontSet::Schip),
+            "octo" => Ok(FontSet::Octo),
+            _ => Err(format!("unknown font {s}")),
+        }
+    }
+}
+
+impl fmt::Display for FontSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontSet::Vip => write!(f, "vip"),
+            FontSet::Dream6800 => write!(f, "dream6800"),
+            FontSet::Eti660 => write!(f, "eti660"),
+            FontSet::Schip => write!(f, "schip"),
+            FontSet::Octo => write!(f, "octo"),
+        }
+    }
+}
+
+/// Which digits go where. The small digits start at `base` and the big
+/// ones, if there are any, straight after them. `Fx29` and `Fx30` point
+/// into here.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Font {
+    pub set: FontSet,
+    pub base: usize,
+}
+
+impl Font {
+    pub fn small_digit(&self, n: u8) -> usize {
+        self.base + (n as usize & 0xF) * SMALL_HEIGHT
+    }
+
+    /// Where the big digit would be. Sets without big digits, or without
+    /// one for `n`, leave whatever else is in memory there.
+    pub fn big_digit(&self, n: u8) -> usize {
+        self.base + 16 * SMALL_HEIGHT + (n as usize & 0xF) * BIG_HEIGHT
+    }
+
+    /// How many bytes the font takes up.
+    pub fn size(&self) -> usize {
+        16 * SMALL_HEIGHT + self.set.big().map_or(0, <[u8]>::len)
+    }
+
+    pub fn install(&self, memory: &mut [u8]) {
+        let small = self.set.small();
+        memory[self.base..self.base + small.len()].copy_from_slice(small);
+        if let Some(big) = self.set.big() {
+            let start = self.big_digit(0);
+            memory[start..start + big.len()].copy_from_slice(big);
+        }
+    }
+}
+
+#[rustfmt::skip]
+const VIP: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+#[rustfmt::skip]
+const DREAM_6800: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+#[rustfmt::skip]
+const ETI_660: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x20, 0x20, 0x20, 0x20, 0x20, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+/// The font most modern interpreters use, SCHIP's included.
+#[rustfmt::skip]
+const OCTO: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+#[rustfmt::skip]
+const SCHIP_BIG: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+#[rustfmt::skip]
+const OCTO_BIG: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+#[cfg(test)]
+mod test {
+    use super::{Font, FontSet, BIG_HEIGHT, SMALL_HEIGHT};
+
+    #[test]
+    fn test_install_at_base() {
+        let font = Font {
+            set: FontSet::Octo,
+            base: 0x50,
+        };
+        let mut memory = [0; 0x200];
+        font.install(&mut memory);
+        assert_eq!(font.small_digit(0xA), 0x50 + 50);
+        assert_eq!(
+            &memory[0x50 + 50..0x50 + 55],
+            &[0xF0, 0x90, 0xF0, 0x90, 0x90]
+        );
+        assert_eq!(font.big_digit(0), 0x50 + 80);
+        assert_eq!(memory[font.big_digit(1)], 0x18);
+        assert_eq!(font.size(), 80 + 160);
+        assert!(memory[..0x50].iter().all(|b| *b == 0));
+        assert!(memory[0x50 + font.size()..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_every_set_is_whole() {
+        for set in [
+            FontSet::Vip,
+            FontSet::Dream6800,
+            FontSet::Eti660,
+            FontSet::Schip,
+            FontSet::Octo,
+        ] {
+            assert_eq!(set.to_string().parse(), Ok(set));
+            // every digit has something lit
+            for digit in set.small().chunks(SMALL_HEIGHT) {
+                assert!(digit.iter().any(|b| *b != 0), "{set}");
+            }
+            for digit in set.big().unwrap_or_default().chunks(BIG_HEIGHT) {
+                assert!(digit.iter().any(|b| *b != 0), "{set}");
+            }
+        }
+        assert_eq!(FontSet::Schip.big().map(<[u8]>::len), Some(100));
+    }
+}
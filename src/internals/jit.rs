@@ -0,0 +1,419 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use super::{memory::MEMORY_SIZE, Chip8, Chip8Error, Instruction, InstructionResult};
+
+/// How `run_frame` runs instructions. Both run programs exactly the same
+/// way, down to the instruction a frame ends on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Decodes and dispatches one instruction at a time.
+    #[default]
+    Interpreter,
+    /// Translates straight runs of instructions into threaded code the
+    /// first time they're reached, and runs that from then on.
+    Jit,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interpreter" => Ok(Backend::Interpreter),
+            "jit" => Ok(Backend::Jit),
+            _ => Err(format!("unknown backend {s}")),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Backend::Interpreter => write!(f, "interpreter"),
+            Backend::Jit => write!(f, "jit"),
+        }
+    }
+}
+
+/// Blocks are found by the pages their code is in.
+const PAGE: usize = 0x100;
+/// Long runs of code are split into blocks this long, so compiling one
+/// never holds a frame up for long.
+const MAX_BLOCK: usize = 64;
+
+type Handler = fn(&mut Chip8, Op) -> Result<(), Chip8Error>;
+
+/// One instruction, with its operands pulled out and the code that runs it
+/// picked ahead of time.
+#[derive(Clone, Copy)]
+struct Op {
+    run: Handler,
+    x: u8,
+    y: u8,
+    /// A byte or an address, whichever the instruction has.
+    nn: u16,
+    i: Instruction,
+}
+
+impl Op {
+    fn x(self) -> usize {
+        self.x as usize & 0xF
+    }
+
+    fn y(self) -> usize {
+        self.y as usize & 0xF
+    }
+}
+
+/// A straight run of instructions, ending at the first one that always
+/// goes somewhere else. It's left early when a skip is taken.
+struct Block {
+    ops: Box<[Op]>,
+    // one past its last byte
+    end: usize,
+}
+
+/// Compiled blocks, by the address they start at.
+#[derive(Default)]
+pub struct BlockCache {
+    // empty until the first block is compiled
+    blocks: Vec<Option<Block>>,
+    // where the blocks with code in each page start and end
+    pages: [Vec<(u16, u16)>; MEMORY_SIZE / PAGE],
+    // whether anything's been invalidated since the running block started
+    hit: bool,
+}
+
+impl BlockCache {
+    /// Forgets every block with code in `range`. Programs often keep
+    /// their variables right next to their code, so it has to be exact.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        if self.blocks.is_empty() || range.is_empty() {
+            return;
+        }
+        // an instruction that starts the byte before still counts
+        let (low, high) = (range.start.saturating_sub(1), range.end.min(MEMORY_SIZE));
+        let mut dropped = Vec::new();
+        for page in low / PAGE..=(high - 1) / PAGE {
+            for &(start, end) in &self.pages[page] {
+                if (start as usize) < high && low < end as usize {
+                    dropped.push((start, end));
+                }
+            }
+        }
+        for (start, end) in dropped {
+            self.blocks[start as usize] = None;
+            self.hit = true;
+            // blocks can straddle pages
+            for page in start as usize / PAGE..=(end as usize - 1) / PAGE {
+                self.pages[page].retain(|e| *e != (start, end));
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.pages.iter_mut().for_each(Vec::clear);
+        self.hit = true;
+    }
+
+    /// Takes the block at `start` out to run it.
+    fn take(&mut self, start: usize) -> Option<Block> {
+        self.blocks.get_mut(start)?.take()
+    }
+
+    /// Puts a block back after running it, unless it's been written over
+    /// in the meantime.
+    fn put_back(&mut self, start: usize, block: Block) {
+        let entry = (start as u16, block.end as u16);
+        if !self.hit || self.pages[start / PAGE].contains(&entry) {
+            self.blocks[start] = Some(block);
+        }
+    }
+
+    fn insert(&mut self, start: usize, block: Block) {
+        if self.blocks.is_empty() {
+            self.blocks.resize_with(MEMORY_SIZE, || None);
+        }
+        let entry = (start as u16, block.end as u16);
+        for page in start / PAGE..=(block.end - 1) / PAGE {
+            if !self.pages[page].contains(&entry) {
+                self.pages[page].push(entry);
+            }
+        }
+        self.blocks[start] = Some(block);
+    }
+}
+
+impl Chip8 {
+    /// `run_frame`'s loop, a block at a time.
+    pub(super) fn run_compiled(&mut self, instructions: usize) -> Result<(), Chip8Error> {
+        let mut n = 0;
+        while n < instructions {
+            let pc = self.registers.pc as usize;
+            if let Some(block) = self.blocks.take(pc).or_else(|| self.compile(pc)) {
+                self.status = InstructionResult::Success;
+                self.blocks.hit = false;
+                let mut result = Ok(());
+                let mut next = pc;
+                for op in block.ops.iter().take(instructions - n) {
+                    n += 1;
+                    next += 2;
+                    result = (op.run)(self, *op);
+                    self.executed += 1;
+                    // a skip was taken, or a write might have changed what
+                    // comes next
+                    if result.is_err() || self.blocks.hit || self.registers.pc as usize != next {
+                        break;
+                    }
+                }
+                self.blocks.put_back(pc, block);
+                result?;
+                continue;
+            }
+            // draws and key waits, which can end the frame, are left to
+            // the interpreter
+            if self.quirks.display_wait && n > 0 && matches!(self.fetch(), Instruction::Draw(..)) {
+                break;
+            }
+            if let InstructionResult::Waiting = self.step()? {
+                break;
+            }
+            n += 1;
+        }
+        Ok(())
+    }
+
+    /// Translates the block starting at `start`, unless it starts with an
+    /// instruction that can't go in one.
+    fn compile(&mut self, start: usize) -> Option<Block> {
+        let mut ops = Vec::new();
+        let mut at = start;
+        // the last byte of memory can't hold an instruction
+        while ops.len() < MAX_BLOCK && at + 1 < MEMORY_SIZE {
+            let i = self.decoded.fetch(&self.memory.0, at);
+            if matches!(i, Instruction::Draw(..) | Instruction::WaitForKey(_)) {
+                break;
+            }
+            ops.push(compile(i));
+            at += 2;
+            if ends_block(i) {
+                break;
+            }
+        }
+        if ops.is_empty() {
+            return None;
+        }
+        let block = Block {
+            ops: ops.into_boxed_slice(),
+            end: at,
+        };
+        // it goes in now so that writes to it while it runs are noticed,
+        // and comes straight back out to run
+        self.blocks.insert(start, block);
+        self.blocks.take(start)
+    }
+}
+
+/// Whether the next instruction to run is never the next one along.
+fn ends_block(i: Instruction) -> bool {
+    use Instruction::*;
+    matches!(
+        i,
+        ReturnFromSubRoutine
+            | JumpTo(_)
+            | Call(_)
+            | JumpV0(_)
+            // doesn't move pc at all
+            | Nop
+    )
+}
+
+fn compile(i: Instruction) -> Op {
+    use Instruction::*;
+    let (run, x, y, nn): (Handler, _, _, _) = match i {
+        LoadInto(x, v) => (load, x as u8, 0, v as u16),
+        Add(x, v) => (add, x as u8, 0, v as u16),
+        LoadIntoRegister(x, y) => (copy, x as u8, y as u8, 0),
+        Or(x, y) => (or, x as u8, y as u8, 0),
+        And(x, y) => (and, x as u8, y as u8, 0),
+        Xor(x, y) => (xor, x as u8, y as u8, 0),
+        LoadIntoI(a) => (load_i, 0, 0, a),
+        JumpTo(a) => (jump, 0, 0, a),
+        SkipIf(x, v) => (skip_if, x as u8, 0, v as u16),
+        SkipIfNot(x, v) => (skip_if_not, x as u8, 0, v as u16),
+        SkipIfRegistersEqual(x, y) => (skip_if_equal, x as u8, y as u8, 0),
+        SkipIfNotEqual(x, y) => (skip_if_not_equal, x as u8, y as u8, 0),
+        // everything else is rare enough, or fiddly enough, to leave to
+        // the interpreter's version
+        _ => (interpret, 0, 0, 0),
+    };
+    Op { run, x, y, nn, i }
+}
+
+fn load(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.registers.r[op.x()] = op.nn as u8;
+    c.increment_pc(1);
+    Ok(())
+}
+
+fn add(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.registers.r[op.x()] = c.registers.r[op.x()].wrapping_add(op.nn as u8);
+    c.increment_pc(1);
+    Ok(())
+}
+
+fn copy(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.registers.r[op.x()] = c.registers.r[op.y()];
+    c.increment_pc(1);
+    Ok(())
+}
+
+fn or(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.registers.r[op.x()] |= c.registers.r[op.y()];
+    c.increment_pc(1);
+    Ok(())
+}
+
+fn and(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.registers.r[op.x()] &= c.registers.r[op.y()];
+    c.increment_pc(1);
+    Ok(())
+}
+
+fn xor(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.registers.r[op.x()] ^= c.registers.r[op.y()];
+    c.increment_pc(1);
+    Ok(())
+}
+
+fn load_i(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.registers.vi = op.nn;
+    c.increment_pc(1);
+    Ok(())
+}
+
+fn jump(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.registers.pc = op.nn;
+    Ok(())
+}
+
+fn skip_if(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.increment_pc(if c.registers.r[op.x()] == op.nn as u8 {
+        2
+    } else {
+        1
+    });
+    Ok(())
+}
+
+fn skip_if_not(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.increment_pc(if c.registers.r[op.x()] != op.nn as u8 {
+        2
+    } else {
+        1
+    });
+    Ok(())
+}
+
+fn skip_if_equal(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.increment_pc(if c.registers.r[op.x()] == c.registers.r[op.y()] {
+        2
+    } else {
+        1
+    });
+    Ok(())
+}
+
+fn skip_if_not_equal(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.increment_pc(if c.registers.r[op.x()] != c.registers.r[op.y()] {
+        2
+    } else {
+        1
+    });
+    Ok(())
+}
+
+fn interpret(c: &mut Chip8, op: Op) -> Result<(), Chip8Error> {
+    c.run_instruction(op.i).map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile, Backend, Block, BlockCache};
+    use crate::internals::{test::with_program, Instruction};
+
+    #[test]
+    fn test_budget_splits_blocks() {
+        // v0 += 1 five times, loop
+        let mut c8 = with_program(&[
+            0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x12, 0x00,
+        ]);
+        c8.backend = Backend::Jit;
+        c8.run_frame(4).unwrap();
+        assert_eq!(c8.registers.r[0], 4);
+        assert_eq!(c8.registers.pc, 0x208);
+        // the rest of that block, then the start of the first one again
+        c8.run_frame(4).unwrap();
+        assert_eq!(c8.registers.r[0], 7);
+        assert_eq!(c8.registers.pc, 0x204);
+    }
+
+    #[test]
+    fn test_writes_invalidate_blocks() {
+        // i = 0x207, v0 = 9, jump to 0x206: v1 = 1, store v0 over the 1,
+        // jump back to 0x206
+        let mut c8 = with_program(&[
+            0xA2, 0x07, 0x60, 0x09, 0x12, 0x06, 0x61, 0x01, 0xF0, 0x55, 0x12, 0x06,
+        ]);
+        c8.backend = Backend::Jit;
+        c8.run_frame(3).unwrap();
+        c8.run_frame(2).unwrap();
+        assert_eq!(c8.registers.r[1], 1);
+        assert_eq!(c8.memory.0[0x207], 9);
+        // the block at 0x206 was compiled before the store wrote over it
+        c8.run_frame(2).unwrap();
+        assert_eq!(c8.registers.r[1], 9);
+
+        // writes from outside have to say so
+        c8.registers.pc = 0x206;
+        c8.memory.0[0x207] = 7;
+        c8.invalidate(0x207..0x208);
+        c8.run_frame(1).unwrap();
+        assert_eq!(c8.registers.r[1], 7);
+    }
+
+    #[test]
+    fn test_blocks_straddling_pages() {
+        let mut cache = BlockCache::default();
+        let block = |end| Block {
+            ops: Box::new([compile(Instruction::Nop)]),
+            end,
+        };
+        cache.insert(0x2FA, block(0x306));
+        // written over in its second page while it ran
+        let running = cache.take(0x2FA).unwrap();
+        cache.hit = false;
+        cache.invalidate(0x303..0x304);
+        cache.put_back(0x2FA, running);
+        assert!(cache.take(0x2FA).is_none());
+
+        // a write next to it doesn't count
+        cache.insert(0x2FA, block(0x306));
+        let running = cache.take(0x2FA).unwrap();
+        cache.hit = false;
+        cache.invalidate(0x307..0x308);
+        cache.put_back(0x2FA, running);
+        assert!(cache.take(0x2FA).is_some());
+    }
+
+    #[test]
+    fn test_backend_names() {
+        for b in [Backend::Interpreter, Backend::Jit] {
+            assert_eq!(b.to_string().parse::<Backend>(), Ok(b));
+        }
+        assert!("dynarec".parse::<Backend>().is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use std::str::FromStr;
+
+/// One of the 16 keys on the hex keypad.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    B0 = 0x00,
+    B1 = 0x01,
+    B2 = 0x02,
+    B3 = 0x03,
+    B4 = 0x04,
+    B5 = 0x05,
+    B6 = 0x06,
+    B7 = 0x07,
+    B8 = 0x08,
+    B9 = 0x09,
+    BA = 0x0A,
+    BB = 0x0B,
+    BC = 0x0C,
+    BD = 0x0D,
+    BE = 0x0E,
+    BF = 0x0F,
+}
+
+impl Button {
+    pub fn from_u8(n: u8) -> Self {
+        match n & 0xF {
+            0x0 => Button::B0,
+            0x1 => Button::B1,
+            0x2 => Button::B2,
+            0x3 => Button::B3,
+            0x4 => Button::B4,
+            0x5 => Button::B5,
+            0x6 => Button::B6,
+            0x7 => Button::B7,
+            0x8 => Button::B8,
+            0x9 => Button::B9,
+            0xA => Button::BA,
+            0xB => Button::BB,
+            0xC => Button::BC,
+            0xD => Button::BD,
+            0xE => Button::BE,
+            _ => Button::BF,
+        }
+    }
+
+    /// The default layout, where each key is labelled with its own hex
+    /// digit. Front ends map whatever their host calls a key to this.
+    pub fn from_char(c: char) -> Option<Self> {
+        c.to_digit(16).map(|n| Button::from_u8(n as u8))
+    }
+}
+
+/// Keys a modern keyboard has that a game might want bound to its
+/// buttons, named the way the program database names them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Control {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+}
+
+impl FromStr for Control {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "up" => Ok(Control::Up),
+            "down" => Ok(Control::Down),
+            "left" => Ok(Control::Left),
+            "right" => Ok(Control::Right),
+            "a" => Ok(Control::A),
+            "b" => Ok(Control::B),
+            _ => Err(format!("unknown control {s}")),
+        }
+    }
+}
+
+/// Which button each control presses, on top of the hex keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keymap([Option<Button>; 6]);
+
+impl Keymap {
+    pub fn bind(&mut self, c: Control, b: Button) {
+        self.0[c as usize] = Some(b);
+    }
+
+    pub fn get(&self, c: Control) -> Option<Button> {
+        self.0[c as usize]
+    }
+}
+
+/// The keypad as the front end sees it. It doesn't know about any host's
+/// keys, front ends translate theirs to buttons before pressing them.
+#[derive(Default)]
+pub struct Controller {
+    pub pressing: Vec<Button>,
+    pub last_released: Option<Button>,
+}
+
+impl Controller {
+    pub fn press(&mut self, b: Button) {
+        if !self.pressing.contains(&b) {
+            self.pressing.push(b)
+        }
+    }
+
+    pub fn release(&mut self, b: Button) {
+        if let Some(i) = self.pressing.iter().position(|x| *x == b) {
+            self.last_released = Some(self.pressing.remove(i));
+        }
+    }
+
+    pub fn is_pressed(&self, b: Button) -> bool {
+        self.pressing.contains(&b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Button, Control, Controller, Keymap};
+
+    #[test]
+    fn test_hex_digits_map_to_buttons() {
+        assert_eq!(Button::from_char('0'), Some(Button::B0));
+        assert_eq!(Button::from_char('a'), Some(Button::BA));
+        assert_eq!(Button::from_char('F'), Some(Button::BF));
+        assert_eq!(Button::from_char('g'), None);
+    }
+
+    #[test]
+    fn test_keymap() {
+        let mut k = Keymap::default();
+        k.bind("up".parse::<Control>().unwrap(), Button::B5);
+        assert_eq!(k.get(Control::Up), Some(Button::B5));
+        assert_eq!(k.get(Control::A), None);
+        assert!("player2Up".parse::<Control>().is_err());
+    }
+
+    #[test]
+    fn test_release_remembers_last_key() {
+        let mut c = Controller::default();
+        c.press(Button::B5);
+        c.press(Button::B5);
+        assert_eq!(c.pressing, [Button::B5]);
+        c.release(Button::B7);
+        assert_eq!(c.last_released, None);
+        c.release(Button::B5);
+        assert!(!c.is_pressed(Button::B5));
+        assert_eq!(c.last_released, Some(Button::B5));
+    }
+}
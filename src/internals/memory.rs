@@ -1,7 +1,97 @@
-use std::fs::File;
-use std::io::BufReader;
-use std::io::Read;
+use std::fmt;
+use std::io;
+use std::path::Path;
 
+use super::font::Font;
+
+/// How much memory there is.
+pub const MEMORY_SIZE: usize = 4096;
+/// Where programs are loaded and start running, unless told otherwise.
+/// The ETI-660, for one, started them at 0x600.
+pub const PROGRAM_START: usize = 0x200;
+
+/// Why a ROM couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Empty,
+    /// There's only room for `max` bytes after the load address.
+    TooBig {
+        len: usize,
+        max: usize,
+    },
+    BadAddress(usize),
+    /// The file is some other kind of program that couldn't be unpacked.
+    Format(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{e}"),
+            LoadError::Empty => write!(f, "the ROM is empty"),
+            LoadError::TooBig { len, max } => {
+                write!(f, "the ROM is {len} bytes, only {max} fit in memory")
+            }
+            LoadError::BadAddress(a) => write!(f, "can't load a program at {a:#x}"),
+            LoadError::Format(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+/// What got loaded, and where.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomInfo {
+    pub start: usize,
+    pub len: usize,
+    /// SHA-1 of the ROM, in hex, the way program databases key them.
+    pub sha1: String,
+}
+
+impl RomInfo {
+    /// Checks that `rom` fits in memory at `start`.
+    pub fn check(rom: &[u8], start: usize) -> Result<RomInfo, LoadError> {
+        if start >= MEMORY_SIZE {
+            return Err(LoadError::BadAddress(start));
+        }
+        if rom.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        let max = MEMORY_SIZE - start;
+        if rom.len() > max {
+            return Err(LoadError::TooBig {
+                len: rom.len(),
+                max,
+            });
+        }
+        Ok(RomInfo {
+            start,
+            len: rom.len(),
+            sha1: sha1(rom),
+        })
+    }
+}
+
+/// The SHA-1 of a ROM, in hex.
+pub fn sha1(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+/// Reads a ROM file. Whether it fits is up to `RomInfo::check`, once the
+/// load address is known.
+pub fn read_rom(path: impl AsRef<Path>) -> Result<Vec<u8>, LoadError> {
+    Ok(std::fs::read(path)?)
+}
+
+#[derive(Clone)]
 pub struct Registers {
     pub r: [u8; 16],
     pub vi: u16,
@@ -19,36 +109,107 @@ impl Default for Registers {
             delay: 0,
             sound: 0,
             pc: 0x200,
-            stack: Vec::new(),
+            // the usual 16 levels, so calls don't allocate
+            stack: Vec::with_capacity(16),
         }
     }
 }
 
-pub struct Ram(pub [u8; 4096]);
+#[derive(Clone)]
+pub struct Ram(pub [u8; MEMORY_SIZE]);
 
 impl Ram {
-    pub fn init() -> Ram {
-        let mut r = Ram([0; 4096]);
-        r.load("./data/inital_ram_data.chip8");
+    /// Memory as it is at power on: empty apart from the font.
+    pub fn init(font: &Font) -> Ram {
+        let mut r = Ram::default();
+        font.install(&mut r.0);
         r
     }
 
-    pub fn load(&mut self, path: &str) {
-        let file = File::open(path);
-        let mut reader = BufReader::new(file.unwrap());
-        let mut buffer = vec![0; 1];
+    /// Copies `rom` into memory at `start`, as long as it fits.
+    pub fn load_rom(&mut self, rom: &[u8], start: usize) -> Result<RomInfo, LoadError> {
+        let info = RomInfo::check(rom, start)?;
+        self.0[start..start + rom.len()].copy_from_slice(rom);
+        Ok(info)
+    }
 
-        let mut index = 0;
-        while reader.read_exact(&mut buffer).is_ok() {
-            let number = u8::from_le_bytes(buffer.clone().try_into().unwrap());
-            self.0[index + 0x200] = number;
-            index += 1;
-        }
+    pub fn load_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        start: usize,
+    ) -> Result<RomInfo, LoadError> {
+        self.load_rom(&read_rom(path)?, start)
     }
 }
 
 impl Default for Ram {
     fn default() -> Self {
-        Ram([0; 4096])
+        Ram([0; MEMORY_SIZE])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LoadError, Ram, PROGRAM_START};
+
+    #[test]
+    fn test_load_rom() {
+        let mut ram = Ram::default();
+        let info = ram.load_rom(&[0x12, 0x00], 0x600).unwrap();
+        assert_eq!(&ram.0[0x600..0x602], &[0x12, 0x00]);
+        assert_eq!(info.len, 2);
+        assert_eq!(info.sha1, "92a5652d382a18e89c4881ec57041fc7d885ca80");
+    }
+
+    #[test]
+    fn test_rejects_what_doesnt_fit() {
+        let mut ram = Ram::default();
+        let rom = vec![0; 4096 - PROGRAM_START + 1];
+        assert!(matches!(
+            ram.load_rom(&rom, PROGRAM_START),
+            Err(LoadError::TooBig { max: 3584, .. })
+        ));
+        assert!(ram.load_rom(&rom[..2560], 0x600).is_ok());
+        assert!(matches!(
+            ram.load_rom(&rom[..2561], 0x600),
+            Err(LoadError::TooBig { max: 2560, .. })
+        ));
+        assert!(matches!(
+            ram.load_rom(&[], PROGRAM_START),
+            Err(LoadError::Empty)
+        ));
+        assert!(matches!(
+            ram.load_rom(&[0], 4096),
+            Err(LoadError::BadAddress(_))
+        ));
+        assert!(ram.0.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let mut ram = Ram::default();
+        let e = ram.load_file("no/such/rom.ch8", PROGRAM_START);
+        assert!(matches!(e, Err(LoadError::Io(_))));
+    }
+
+    #[test]
+    fn test_file_too_big_for_its_address() {
+        let path = crate::temp_dir().join("chip8-test-too-big.ch8");
+        std::fs::write(&path, vec![0; 4000]).unwrap();
+        let mut ram = Ram::default();
+        assert!(matches!(
+            ram.load_file(&path, PROGRAM_START),
+            Err(LoadError::TooBig {
+                len: 4000,
+                max: 3584
+            })
+        ));
+        assert!(matches!(
+            ram.load_file(&path, 0x600),
+            Err(LoadError::TooBig {
+                len: 4000,
+                max: 2560
+            })
+        ));
     }
 }
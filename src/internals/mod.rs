@@ -1,17 +1,34 @@
+pub mod audio;
+pub mod decode;
+pub mod display;
+pub mod font;
+pub mod jit;
+pub mod keypad;
 pub mod memory;
-use std::sync::{Arc, RwLock};
+pub mod quirks;
+pub mod snapshot;
+pub mod timing;
 
-use crate::{
-    gui::{Controller, UserEvent},
-    internals::memory::{Ram, Registers},
+use crate::internals::{
+    decode::DecodeCache,
+    display::{Dirty, DisplayCommand, Frame},
+    font::Font,
+    jit::{Backend, BlockCache},
+    keypad::{Button, Controller},
+    memory::{LoadError, Ram, Registers, RomInfo, MEMORY_SIZE, PROGRAM_START},
+    quirks::Quirks,
+    timing::Timing,
 };
 use rand::prelude::*;
-use winit::keyboard::Key;
+use std::ops::Range;
 
 const ON: u32 = 0b00000000_00000000_11111111_11111111;
 const OFF: u32 = 0;
 
-#[derive(Debug)]
+/// The vblank rate; timers tick and frames get published at this rate.
+pub const FRAME_RATE: u32 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Instruction {
     ClearDisplay,
     ReturnFromSubRoutine,
@@ -44,52 +61,36 @@ pub enum Instruction {
     LoadToSound(Register),
     AddToI(Register),
     LoadSpriteToI(Register),
+    LoadBigSpriteToI(Register),
     LoadBcd(Register),
     LoadToMemory(Register),
     LoadFromMemory(Register),
     Nop,
 }
 
-#[derive(PartialEq)]
-pub enum DisplayCommand {
-    ClearDisplay,
-    Draw([u32; 2048]),
-}
+/// Up to 15 rows, and how many of them there are.
+struct SpriteData([Nybble; 15], usize);
 
-impl UserEvent for DisplayCommand {
-    fn transform(&self, b: &mut [u32]) {
-        match self {
-            DisplayCommand::ClearDisplay => b.fill(0),
-            DisplayCommand::Draw(fb) => {
-                let original_width = 64;
-                let scale_factor = 20;
-                let original_height = 32;
-                // Calculate new dimensions
-                let new_width = original_width * scale_factor;
-
-                for y in 0..original_height {
-                    for x in 0..original_width {
-                        // Get the original pixel value
-                        let pixel = fb[y * original_width + x];
-
-                        // Calculate the position in the scaled framebuffer
-                        for dy in 0..scale_factor {
-                            for dx in 0..scale_factor {
-                                let new_x = x * scale_factor + dx;
-                                let new_y = y * scale_factor + dy;
-
-                                // Set the pixel in the scaled framebuffer
-                                b[new_y * new_width + new_x] = pixel;
-                            }
-                        }
-                    }
-                }
-            }
+impl SpriteData {
+    /// `rows` bytes from `start`, or as many as there are before the end
+    /// of memory.
+    fn read(memory: &[u8], start: usize, rows: Nybble) -> Self {
+        let mut data = SpriteData([0; 15], 0);
+        for (row, byte) in data
+            .0
+            .iter_mut()
+            .zip(memory.iter().skip(start).take(rows as usize))
+        {
+            *row = *byte;
+            data.1 += 1;
         }
+        data
     }
-}
 
-struct SpriteData(Vec<Nybble>);
+    fn rows(&self) -> &[Nybble] {
+        &self.0[..self.1]
+    }
+}
 
 pub struct Sprite {
     x: u8,
@@ -97,114 +98,209 @@ pub struct Sprite {
     data: SpriteData, // max length of 15 (0xF)
 }
 
-#[derive(PartialEq, Debug)]
-pub enum Button {
-    B0 = 0x00,
-    B1 = 0x01,
-    B2 = 0x02,
-    B3 = 0x03,
-    B4 = 0x04,
-    B5 = 0x05,
-    B6 = 0x06,
-    B7 = 0x07,
-    B8 = 0x08,
-    B9 = 0x09,
-    BA = 0x0A,
-    BB = 0x0B,
-    BC = 0x0C,
-    BD = 0x0D,
-    BE = 0x0E,
-    BF = 0x0F,
+pub struct Chip8 {
+    pub registers: Registers,
+    pub memory: Ram,
+    pub keypad: Controller,
+    pub frame_buffer: [u32; 2048],
+    pub status: InstructionResult,
+    pub quirks: Quirks,
+    /// Takes effect on the next reset.
+    pub font: Font,
+    /// Where the next ROM loaded goes, and so where it starts running.
+    pub program_start: usize,
+    pub backend: Backend,
+    pub timing: Timing,
+    // what changed in frame_buffer since the last take_frame
+    dirty: Dirty,
+    // whether the sound timer was running during the last frame
+    beeping: bool,
+    // the program and where it went, kept so a reset can put it back
+    rom: Vec<u8>,
+    rom_start: usize,
+    decoded: DecodeCache,
+    blocks: BlockCache,
+    // with VIP timing, what's left of this frame's machine cycles
+    cycles: i32,
+    // instructions run since the last reset
+    executed: u64,
+    // for Cxnn, so a seeded machine runs the same way every time
+    rng: StdRng,
 }
 
-impl Button {
-    fn to_button(k: &Key) -> Option<Self> {
-        match k {
-            Key::Named(_) => None,
-            Key::Character(c) => match c.as_str() {
-                "A" => Some(Button::BA),
-                "B" => Some(Button::BB),
-                "C" => Some(Button::BC),
-                "D" => Some(Button::BD),
-                "E" => Some(Button::BE),
-                "F" => Some(Button::BF),
-                "1" => Some(Button::B1),
-                "2" => Some(Button::B2),
-                "3" => Some(Button::B3),
-                "4" => Some(Button::B4),
-                "5" => Some(Button::B5),
-                "6" => Some(Button::B6),
-                "7" => Some(Button::B7),
-                "8" => Some(Button::B8),
-                "9" => Some(Button::B9),
-                "0" => Some(Button::B0),
-                &_ => None,
-            },
-            Key::Unidentified(_) => None,
-            Key::Dead(_) => None,
+/// How much of the machine a reset puts back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reset {
+    /// The reset button: the CPU, timers and display start over, but
+    /// memory outside of the program keeps whatever the program left there.
+    Warm,
+    /// Power cycling: memory is wiped too.
+    Cold,
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
+    }
+}
+
+impl Chip8 {
+    pub fn new() -> Self {
+        Chip8 {
+            registers: Registers::default(),
+            memory: Ram::init(&Font::default()),
+            keypad: Controller::default(),
+            frame_buffer: [0; 2048],
+            status: InstructionResult::Success,
+            quirks: Quirks::default(),
+            font: Font::default(),
+            program_start: PROGRAM_START,
+            backend: Backend::default(),
+            timing: Timing::default(),
+            dirty: Dirty::default(),
+            beeping: false,
+            rom: Vec::new(),
+            rom_start: PROGRAM_START,
+            decoded: DecodeCache::default(),
+            blocks: BlockCache::default(),
+            cycles: 0,
+            executed: 0,
+            rng: StdRng::from_entropy(),
         }
     }
 
-    fn from_u8(n: u8) -> Self {
-        match n {
-            0x0 => Button::B0,
-            0x1 => Button::B1,
-            0x2 => Button::B2,
-            0x3 => Button::B3,
-            0x4 => Button::B4,
-            0x5 => Button::B5,
-            0x6 => Button::B6,
-            0x7 => Button::B7,
-            0x8 => Button::B8,
-            0x9 => Button::B9,
-            0xA => Button::BA,
-            0xB => Button::BB,
-            0xC => Button::BC,
-            0xD => Button::BD,
-            0xE => Button::BE,
-            0xF => Button::BF,
-            _ => panic!("{n}"),
+    /// Makes `Cxnn` repeatable: two machines seeded the same and given the
+    /// same input run the same program the same way. Unseeded, it's
+    /// random.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// How many instructions have run since the last reset.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Puts `rom` in place of the current program and starts it. A warm
+    /// start clears what's left of a longer old program, but nothing else.
+    /// Nothing changes if the ROM doesn't fit at `program_start`.
+    pub fn load_rom(&mut self, rom: &[u8], kind: Reset) -> Result<RomInfo, LoadError> {
+        let info = RomInfo::check(rom, self.program_start)?;
+        if kind == Reset::Warm {
+            self.memory.0[self.rom_start..self.rom_start + self.rom.len()].fill(0);
         }
+        self.rom = rom.to_vec();
+        self.rom_start = info.start;
+        self.reset(kind);
+        Ok(info)
     }
-}
 
-pub struct Chip8Controller(pub Arc<RwLock<Controller>>);
+    /// Starts the current program over. Either way the font and the
+    /// program get reinstalled, in case the program wrote over them. Quirks
+    /// and whatever's held on the keypad stay as they are.
+    pub fn reset(&mut self, kind: Reset) {
+        match kind {
+            Reset::Warm => self.font.install(&mut self.memory.0),
+            Reset::Cold => self.memory = Ram::init(&self.font),
+        }
+        self.memory.0[self.rom_start..self.rom_start + self.rom.len()].copy_from_slice(&self.rom);
+        self.decoded.clear();
+        self.blocks.clear();
+        // keeping the stack's room, so starting over doesn't allocate
+        self.registers.stack.clear();
+        self.registers = Registers {
+            r: [0; 16],
+            vi: 0,
+            delay: 0,
+            sound: 0,
+            pc: self.rom_start as u16,
+            stack: std::mem::take(&mut self.registers.stack),
+        };
+        self.frame_buffer.fill(OFF);
+        self.status = InstructionResult::Success;
+        self.dirty = Dirty::full();
+        self.beeping = false;
+        self.cycles = 0;
+        self.executed = 0;
+    }
 
-impl Chip8Controller {
-    pub fn keys_to_buttons(&self) -> Vec<Option<Button>> {
-        self.0
-            .read()
-            .unwrap()
-            .pressing
-            .iter()
-            .map(Button::to_button)
-            .collect()
+    /// The instruction at pc, decoded once and then remembered until
+    /// something writes over it.
+    fn fetch(&mut self) -> Instruction {
+        self.decoded
+            .fetch(&self.memory.0, self.registers.pc as usize)
     }
-}
 
-pub struct Chip8 {
-    pub registers: Registers,
-    pub memory: Ram,
-    pub controller: Chip8Controller,
-    pub frame_buffer: [u32; 2048],
-    pub status: InstructionResult,
-}
+    /// Tells the decode and block caches that `range` of memory was
+    /// written to from outside, so any code there gets decoded again.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        self.decoded.invalidate(range.clone());
+        self.blocks.invalidate(range);
+    }
 
-impl Chip8 {
-    pub fn new(controller: Arc<RwLock<Controller>>) -> Self {
-        let mut c8 = Chip8 {
-            registers: Registers::default(),
-            memory: Ram::default(),
-            controller: Chip8Controller(controller),
-            frame_buffer: [0; 2048],
-            status: InstructionResult::Success,
+    /// Fetches, decodes and runs the instruction at pc.
+    pub fn step(&mut self) -> Result<InstructionResult, Chip8Error> {
+        let i = self.fetch();
+        let result = self.run_instruction(i)?;
+        self.executed += 1;
+        self.status = match result {
+            InstructionResult::Waiting => InstructionResult::Waiting,
+            _ => InstructionResult::Success,
         };
-        c8.memory.load("./data/inital_ram_data.chip8");
-        c8
+        Ok(result)
+    }
+
+    /// Runs up to `instructions` instructions and then does what the vblank
+    /// interrupt does: ticks the timers. The frame ends early while waiting
+    /// for a key, or before a second `Dxyn` with the display wait quirk.
+    /// With VIP timing it's the frame's machine cycles that run out instead.
+    pub fn run_frame(&mut self, instructions: usize) -> Result<(), Chip8Error> {
+        let result = match (self.timing, self.backend) {
+            // every instruction has to be costed, which the JIT can't do
+            (Timing::Vip, _) => self.run_cycles(),
+            (_, Backend::Interpreter) => self.run_interpreted(instructions),
+            (_, Backend::Jit) => self.run_compiled(instructions),
+        };
+        self.beeping = self.registers.sound > 0;
+        self.tick_timers();
+        result
+    }
+
+    fn run_interpreted(&mut self, instructions: usize) -> Result<(), Chip8Error> {
+        for n in 0..instructions {
+            if self.quirks.display_wait && n > 0 && matches!(self.fetch(), Instruction::Draw(..)) {
+                break;
+            }
+            if let InstructionResult::Waiting = self.step()? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the buzzer sounded during the last frame.
+    pub fn beeping(&self) -> bool {
+        self.beeping
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.registers.delay = self.registers.delay.saturating_sub(1);
+        self.registers.sound = self.registers.sound.saturating_sub(1);
+    }
+
+    /// The completed frame, along with everything that changed since the
+    /// previous call.
+    pub fn take_frame(&mut self) -> Frame {
+        let dirty = self.dirty;
+        self.dirty.clear();
+        Frame {
+            pixels: self.frame_buffer,
+            dirty,
+        }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Register {
     V0 = 0x00,
     V1 = 0x01,
@@ -227,30 +323,38 @@ pub enum Register {
 type Address = u16;
 type Nybble = u8;
 
+const REGISTERS: [Register; 16] = [
+    Register::V0,
+    Register::V1,
+    Register::V2,
+    Register::V3,
+    Register::V4,
+    Register::V5,
+    Register::V6,
+    Register::V7,
+    Register::V8,
+    Register::V9,
+    Register::VA,
+    Register::VB,
+    Register::VC,
+    Register::VD,
+    Register::VE,
+    Register::VF,
+];
+
 impl Register {
+    /// The register a nybble names. Only the low four bits count.
     fn from_nybble(i: Nybble) -> Self {
-        match i {
-            0x00 => Register::V0,
-            0x01 => Register::V1,
-            0x02 => Register::V2,
-            0x03 => Register::V3,
-            0x04 => Register::V4,
-            0x05 => Register::V5,
-            0x06 => Register::V6,
-            0x07 => Register::V7,
-            0x08 => Register::V8,
-            0x09 => Register::V9,
-            0x0A => Register::VA,
-            0x0B => Register::VB,
-            0x0C => Register::VC,
-            0x0D => Register::VD,
-            0x0E => Register::VE,
-            0x0F => Register::VF,
-            _ => panic!("{:?}", i),
-        }
+        REGISTERS[(i & 0xF) as usize]
     }
 }
 
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// `00EE` with nothing on the stack.
+    StackUnderflow,
+}
+
 #[derive(PartialEq)]
 pub enum InstructionResult {
     Success,
@@ -259,15 +363,18 @@ pub enum InstructionResult {
 }
 
 impl Chip8 {
+    /// Moves pc on, wrapping at the end of memory like the VIP's 12 bit
+    /// addresses did.
     pub fn increment_pc(&mut self, increments: u16) {
-        self.registers.pc += 2 * increments
+        self.registers.pc = self.registers.pc.wrapping_add(2 * increments) % MEMORY_SIZE as u16
     }
 
-    pub fn run_instruction(&mut self, i: Instruction) -> Result<InstructionResult, ()> {
+    pub fn run_instruction(&mut self, i: Instruction) -> Result<InstructionResult, Chip8Error> {
         match i {
             Instruction::ClearDisplay => {
                 self.increment_pc(1);
                 self.frame_buffer.fill(0);
+                self.dirty = Dirty::full();
                 Ok(InstructionResult::Display(DisplayCommand::ClearDisplay))
             }
             Instruction::ReturnFromSubRoutine => match self.registers.stack.pop() {
@@ -275,7 +382,7 @@ impl Chip8 {
                     self.registers.pc = addr;
                     Ok(InstructionResult::Success)
                 }
-                None => Err(()),
+                None => Err(Chip8Error::StackUnderflow),
             },
             Instruction::JumpTo(addr) => {
                 self.registers.pc = addr;
@@ -383,11 +490,12 @@ impl Chip8 {
                 Ok(InstructionResult::Success)
             }
             Instruction::JumpV0(addr) => {
-                self.registers.pc = addr + self.read(Register::V0) as u16;
+                self.registers.pc = (addr + self.read(Register::V0) as u16) % MEMORY_SIZE as u16;
                 Ok(InstructionResult::Success)
             }
             Instruction::Random(x, v) => {
-                self.write(x, rand::thread_rng().gen::<u8>() & v);
+                let r = self.rng.gen::<u8>();
+                self.write(x, r & v);
                 self.increment_pc(1);
                 Ok(InstructionResult::Success)
             }
@@ -396,18 +504,10 @@ impl Chip8 {
                 let s = Sprite {
                     x: self.read(x),
                     y: self.read(y),
-                    data: SpriteData(
-                        self.memory
-                            .0
-                            .iter()
-                            .skip(self.read_i() as usize)
-                            .take(l as usize)
-                            .cloned()
-                            .collect(),
-                    ),
+                    data: SpriteData::read(&self.memory.0, self.at_i(0), l),
                 };
 
-                for (i, byte) in s.data.0.iter().enumerate() {
+                for (i, byte) in s.data.rows().iter().enumerate() {
                     let y_coord = (s.y as usize + i) % 32;
                     let width = 64;
                     for j in (1..8).rev() {
@@ -424,36 +524,24 @@ impl Chip8 {
                     }
                 }
 
-                Ok(InstructionResult::Display(DisplayCommand::Draw(
-                    self.frame_buffer,
-                )))
+                // the frame goes out at vblank, through take_frame
+                self.dirty.merge(&Dirty::sprite(s.x, s.y, l));
+                Ok(InstructionResult::Success)
             }
             Instruction::SkipIfPressed(x) => {
-                self.increment_pc(
-                    if self
-                        .controller
-                        .keys_to_buttons()
-                        .contains(&Some(Button::from_u8(self.read(x))))
-                    {
-                        2
-                    } else {
-                        1
-                    },
-                );
+                self.increment_pc(if self.keypad.is_pressed(Button::from_u8(self.read(x))) {
+                    2
+                } else {
+                    1
+                });
                 Ok(InstructionResult::Success)
             }
             Instruction::SkipIfNotPressed(x) => {
-                self.increment_pc(
-                    if !self
-                        .controller
-                        .keys_to_buttons()
-                        .contains(&Some(Button::from_u8(self.read(x))))
-                    {
-                        2
-                    } else {
-                        1
-                    },
-                );
+                self.increment_pc(if !self.keypad.is_pressed(Button::from_u8(self.read(x))) {
+                    2
+                } else {
+                    1
+                });
                 Ok(InstructionResult::Success)
             }
             Instruction::LoadFromDelay(x) => {
@@ -463,30 +551,16 @@ impl Chip8 {
             }
             Instruction::WaitForKey(x) => {
                 if self.status != InstructionResult::Waiting {
-                    match self.controller.0.try_write() {
-                        Ok(mut c) => {
-                            c.last_released = None;
-                            Ok(InstructionResult::Waiting)
-                        }
-                        Err(_) => Err(()),
-                    }
-                } else {
-                    let con = Arc::clone(&self.controller.0);
-                    let r = con.try_read();
-                    match r {
-                        Ok(c) => match &c.last_released {
-                            Some(b) => {
-                                if Button::to_button(b).is_some() {
-                                    self.write(x, Button::to_button(b).unwrap() as u8);
-                                    Ok(InstructionResult::Success)
-                                } else {
-                                    Ok(InstructionResult::Waiting)
-                                }
-                            }
-                            None => Ok(InstructionResult::Waiting),
-                        },
-                        Err(_) => Err(()),
+                    self.keypad.last_released = None;
+                    return Ok(InstructionResult::Waiting);
+                }
+                match self.keypad.last_released {
+                    Some(b) => {
+                        self.write(x, b as u8);
+                        self.increment_pc(1);
+                        Ok(InstructionResult::Success)
                     }
+                    None => Ok(InstructionResult::Waiting),
                 }
             }
 
@@ -502,33 +576,38 @@ impl Chip8 {
             }
             Instruction::AddToI(x) => {
                 self.increment_pc(1);
-                self.write_i(self.read_i() + self.read(x) as u16);
+                self.write_i(self.read_i().wrapping_add(self.read(x) as u16));
                 Ok(InstructionResult::Success)
             }
             Instruction::LoadSpriteToI(x) => {
                 self.increment_pc(1);
                 // write to I the value of the sprite
                 // representing the hex value in x
-                self.write_i(self.read(x) as u16 * 5);
+                self.write_i(self.font.small_digit(self.read(x)) as u16);
+                Ok(InstructionResult::Success)
+            }
+            Instruction::LoadBigSpriteToI(x) => {
+                self.increment_pc(1);
+                self.write_i(self.font.big_digit(self.read(x)) as u16);
                 Ok(InstructionResult::Success)
             }
             Instruction::LoadBcd(x) => {
                 self.increment_pc(1);
-                let i = self.read_i() as usize;
-                let _x = self.read(x);
-                self.memory.0[i..=i + 2].copy_from_slice(&[_x / 100, (_x % 100) / 10, _x % 10]);
+                let v = self.read(x);
+                self.store(&[v / 100, (v % 100) / 10, v % 10]);
                 Ok(InstructionResult::Success)
             }
             Instruction::LoadToMemory(x) => {
                 self.increment_pc(1);
-                let range = self.read_i() as usize..=(self.read_i() + x as u16) as usize;
-                self.memory.0[range.clone()].copy_from_slice(&self.registers.r[0..=x as usize]);
+                let r = self.registers.r;
+                self.store(&r[..=x as usize]);
                 Ok(InstructionResult::Success)
             }
             Instruction::LoadFromMemory(x) => {
                 self.increment_pc(1);
-                let range = self.read_i() as usize..=(self.read_i() + x as u16) as usize;
-                self.registers.r[0..=x as usize].copy_from_slice(&self.memory.0[range]);
+                for n in 0..=x as usize {
+                    self.registers.r[n] = self.memory.0[self.at_i(n)];
+                }
                 Ok(InstructionResult::Success)
             }
             Instruction::Nop => Ok(InstructionResult::Success),
@@ -551,6 +630,25 @@ impl Chip8 {
         self.registers.vi = v
     }
 
+    /// The address `offset` bytes on from I, wrapped to memory.
+    fn at_i(&self, offset: usize) -> usize {
+        (self.read_i() as usize + offset) % MEMORY_SIZE
+    }
+
+    /// Writes `bytes` to memory from I on, wrapping round at the end.
+    fn store(&mut self, bytes: &[u8]) {
+        for (n, b) in bytes.iter().enumerate() {
+            let a = self.at_i(n);
+            self.memory.0[a] = *b;
+        }
+        let start = self.at_i(0);
+        let end = start + bytes.len();
+        self.invalidate(start..end.min(MEMORY_SIZE));
+        if end > MEMORY_SIZE {
+            self.invalidate(0..end - MEMORY_SIZE);
+        }
+    }
+
     fn read_delay(&self) -> u8 {
         self.registers.delay
     }
@@ -604,6 +702,7 @@ pub fn parse_opcode(i: u16) -> Instruction {
             0x0018 => Instruction::LoadToSound(x_register(i)),
             0x001E => Instruction::AddToI(x_register(i)),
             0x0029 => Instruction::LoadSpriteToI(x_register(i)),
+            0x0030 => Instruction::LoadBigSpriteToI(x_register(i)),
             0x0033 => Instruction::LoadBcd(x_register(i)),
             0x0055 => Instruction::LoadToMemory(x_register(i)),
             0x0065 => Instruction::LoadFromMemory(x_register(i)),
@@ -632,3 +731,305 @@ fn low_nybble(i: u16) -> Nybble {
 fn address(i: u16) -> Address {
     i & 0x0FFF
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        display::Rect,
+        font::{Font, FontSet},
+        keypad::Button,
+        Chip8, InstructionResult, Reset,
+    };
+
+    pub(super) fn with_program(program: &[u8]) -> Chip8 {
+        let mut c8 = Chip8::new();
+        c8.memory.0[0x200..0x200 + program.len()].copy_from_slice(program);
+        c8
+    }
+
+    #[test]
+    fn test_run_frame_ticks_timers_once() {
+        // loop: jump to self
+        let mut c8 = with_program(&[0x12, 0x00]);
+        c8.registers.delay = 3;
+        c8.registers.sound = 1;
+        c8.run_frame(10).unwrap();
+        assert_eq!(c8.registers.delay, 2);
+        assert_eq!(c8.registers.sound, 0);
+        c8.run_frame(10).unwrap();
+        assert_eq!(c8.registers.delay, 1);
+        assert_eq!(c8.registers.sound, 0);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // i = 0x206, v0 = 0x61, store v0 over the next instruction's first
+        // byte, then what was v0 = 0x00 runs as v1 = 0x00
+        let mut c8 = with_program(&[0xA2, 0x06, 0x60, 0x61, 0xF0, 0x55, 0x60, 0x00]);
+        c8.registers.r[1] = 0xFF;
+        // decode the old instruction first
+        c8.registers.pc = 0x206;
+        c8.step().unwrap();
+        assert_eq!(c8.registers.r[0], 0);
+        c8.registers.pc = 0x200;
+        for _ in 0..4 {
+            c8.step().unwrap();
+        }
+        assert_eq!(c8.registers.r[0], 0x61);
+        assert_eq!(c8.registers.r[1], 0);
+    }
+
+    #[test]
+    fn test_beeping_follows_sound_timer() {
+        // va = 2, st = va, loop
+        let mut c8 = with_program(&[0x6A, 0x02, 0xFA, 0x18, 0x12, 0x04]);
+        let beeps: Vec<bool> = (0..4)
+            .map(|_| {
+                c8.run_frame(10).unwrap();
+                c8.beeping()
+            })
+            .collect();
+        assert_eq!(beeps, [true, true, false, false]);
+    }
+
+    #[test]
+    fn test_display_wait_stalls_draw_until_vblank() {
+        // draw, draw, loop
+        let program = [0xD0, 0x15, 0xD0, 0x15, 0x12, 0x04];
+
+        let mut c8 = with_program(&program);
+        c8.run_frame(10).unwrap();
+        assert_eq!(c8.registers.pc, 0x204);
+
+        let mut c8 = with_program(&program);
+        c8.quirks.display_wait = true;
+        c8.run_frame(10).unwrap();
+        assert_eq!(c8.registers.pc, 0x202);
+        c8.run_frame(10).unwrap();
+        assert_eq!(c8.registers.pc, 0x204);
+    }
+
+    #[test]
+    fn test_frame_accumulates_draws() {
+        // v0 = 8, draw at (0, 0) and (8, 0), loop
+        let mut c8 = with_program(&[0xD1, 0x15, 0x60, 0x08, 0xD0, 0x15, 0x12, 0x06]);
+        c8.run_frame(10).unwrap();
+        let frame = c8.take_frame();
+        assert_eq!(
+            frame.dirty.rects(),
+            &[Rect {
+                x: 0,
+                y: 0,
+                width: 16,
+                height: 5
+            }]
+        );
+        c8.run_frame(10).unwrap();
+        assert!(c8.take_frame().dirty.is_empty());
+    }
+
+    #[test]
+    fn test_font_base_moves_sprites() {
+        // v0 = 3, i = small 3, v1 = i, i = big 3, loop
+        let program = [0x60, 0x03, 0xF0, 0x29, 0xF0, 0x30, 0x12, 0x06];
+        let mut c8 = Chip8::new();
+        c8.font = Font {
+            set: FontSet::Schip,
+            base: 0x50,
+        };
+        c8.load_rom(&program, Reset::Cold).unwrap();
+        c8.step().unwrap();
+        c8.step().unwrap();
+        assert_eq!(c8.registers.vi, 0x50 + 15);
+        assert_eq!(c8.memory.0[0x50 + 15], 0xF0);
+        c8.step().unwrap();
+        assert_eq!(c8.registers.vi, 0x50 + 80 + 30);
+        assert_eq!(c8.memory.0[0x50 + 80 + 30], 0x3C);
+        assert!(c8.memory.0[..0x50].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_font_is_built_in() {
+        // v0 = 0xA, i = sprite for v0, draw it, loop
+        let mut c8 = with_program(&[0x60, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06]);
+        c8.run_frame(10).unwrap();
+        assert_eq!(c8.registers.vi, 50);
+        assert_eq!(&c8.memory.0[50..55], &[0xF0, 0x90, 0xF0, 0x90, 0x90]);
+    }
+
+    /// Writes 0x42 to 0x300, clears the screen, draws and loops.
+    const SCRIBBLER: [u8; 12] = [
+        0xA3, 0x00, 0x60, 0x42, 0xF0, 0x55, 0xA0, 0x00, 0xD1, 0x15, 0x12, 0x0A,
+    ];
+
+    #[test]
+    fn test_load_address() {
+        let mut c8 = Chip8::new();
+        c8.program_start = 0x600;
+        let info = c8.load_rom(&[0x60, 0x07, 0x16, 0x02], Reset::Cold).unwrap();
+        assert_eq!(info.start, 0x600);
+        assert_eq!(c8.registers.pc, 0x600);
+        c8.run_frame(3).unwrap();
+        assert_eq!(c8.registers.r[0], 7);
+        assert_eq!(c8.registers.pc, 0x602);
+
+        // a ROM that doesn't fit leaves the old one running
+        assert!(c8.load_rom(&[0; 0xA01], Reset::Cold).is_err());
+        c8.reset(Reset::Warm);
+        assert_eq!(c8.registers.pc, 0x600);
+        assert_eq!(&c8.memory.0[0x600..0x604], &[0x60, 0x07, 0x16, 0x02]);
+    }
+
+    #[test]
+    fn test_cold_reset_wipes_memory() {
+        let mut c8 = Chip8::new();
+        c8.load_rom(&SCRIBBLER, Reset::Cold).unwrap();
+        c8.run_frame(10).unwrap();
+        c8.memory.0[0x200] = 0;
+        c8.memory.0[0] = 0;
+        assert_eq!(c8.memory.0[0x300], 0x42);
+
+        c8.reset(Reset::Cold);
+        assert_eq!(c8.registers.pc, 0x200);
+        assert_eq!(c8.registers.r[0], 0);
+        assert_eq!(c8.memory.0[0x300], 0);
+        assert_eq!(&c8.memory.0[0x200..0x20C], &SCRIBBLER);
+        assert_eq!(c8.memory.0[0], 0xF0);
+        assert!(c8.frame_buffer.iter().all(|p| *p == 0));
+        assert!(!c8.take_frame().dirty.is_empty());
+    }
+
+    #[test]
+    fn test_warm_reset_keeps_memory() {
+        let mut c8 = Chip8::new();
+        c8.load_rom(&SCRIBBLER, Reset::Cold).unwrap();
+        c8.run_frame(10).unwrap();
+        c8.memory.0[0x200] = 0;
+
+        c8.reset(Reset::Warm);
+        assert_eq!(c8.registers.pc, 0x200);
+        assert_eq!(c8.memory.0[0x300], 0x42);
+        assert_eq!(&c8.memory.0[0x200..0x20C], &SCRIBBLER);
+
+        // a shorter program doesn't leave the end of the old one behind
+        c8.load_rom(&[0x12, 0x00], Reset::Warm).unwrap();
+        assert_eq!(&c8.memory.0[0x200..0x204], &[0x12, 0x00, 0, 0]);
+        assert_eq!(c8.memory.0[0x300], 0x42);
+    }
+
+    #[test]
+    fn test_wait_for_key_ends_frame() {
+        let mut c8 = with_program(&[0xF0, 0x0A]);
+        c8.run_frame(10).unwrap();
+        assert!(c8.status == InstructionResult::Waiting);
+        assert_eq!(c8.registers.pc, 0x200);
+
+        c8.keypad.press(Button::B7);
+        c8.run_frame(1).unwrap();
+        assert_eq!(c8.registers.pc, 0x200);
+        c8.keypad.release(Button::B7);
+        c8.run_frame(1).unwrap();
+        assert_eq!(c8.registers.pc, 0x202);
+        assert_eq!(c8.registers.r[0], 7);
+    }
+
+    #[test]
+    fn test_seed() {
+        // v0..vf = rand & 0xff
+        let program: Vec<u8> = (0..16).flat_map(|x| [0xC0 | x, 0xFF]).collect();
+        let run = |seed| {
+            let mut c8 = with_program(&program);
+            c8.seed(seed);
+            c8.run_frame(16).unwrap();
+            assert_eq!(c8.executed(), 16);
+            c8.registers.r
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn test_memory_wraps() {
+        // I = 0xfff, v0 = 1, v1 = 2, store them, then read them back
+        let mut c8 = with_program(&[0xAF, 0xFF, 0x60, 0x01, 0x61, 0x02, 0xF1, 0x55]);
+        c8.run_frame(4).unwrap();
+        assert_eq!((c8.memory.0[0xFFF], c8.memory.0[0]), (1, 2));
+        c8.registers.r = [0; 16];
+        c8.run_instruction(super::Instruction::LoadFromMemory(super::Register::V1))
+            .unwrap();
+        assert_eq!(c8.registers.r[..2], [1, 2]);
+
+        // 255 in BCD from 0xffe
+        c8.registers.vi = 0xFFE;
+        c8.registers.r[0] = 255;
+        c8.run_instruction(super::Instruction::LoadBcd(super::Register::V0))
+            .unwrap();
+        assert_eq!(
+            (c8.memory.0[0xFFE], c8.memory.0[0xFFF], c8.memory.0[0]),
+            (2, 5, 5)
+        );
+
+        c8.registers.vi = 0xFFFF;
+        c8.registers.r[0] = 2;
+        c8.run_instruction(super::Instruction::AddToI(super::Register::V0))
+            .unwrap();
+        assert_eq!(c8.registers.vi, 1);
+    }
+
+    #[test]
+    fn test_pc_wraps() {
+        // 0xffe: v0 = 5, then carries on at 0x000
+        let mut c8 = Chip8::new();
+        c8.memory.0[0xFFE..].copy_from_slice(&[0x60, 0x05]);
+        c8.memory.0[..2].copy_from_slice(&[0x61, 0x06]);
+        c8.registers.pc = 0xFFE;
+        c8.run_frame(2).unwrap();
+        assert_eq!(c8.registers.r[..2], [5, 6]);
+        assert_eq!(c8.registers.pc, 0x002);
+
+        // an instruction straddling the end, and one past it
+        c8.memory.0[0xFFF] = 0x62;
+        c8.memory.0[0] = 0x07;
+        c8.registers.pc = 0xFFF;
+        c8.step().unwrap();
+        assert_eq!(c8.registers.r[2], 7);
+        c8.registers.pc = 0xFFFF;
+        c8.step().unwrap();
+
+        // jumping off the end
+        c8.registers.r[0] = 0xFF;
+        c8.run_instruction(super::Instruction::JumpV0(0xFFF))
+            .unwrap();
+        assert_eq!(c8.registers.pc, 0x0FE);
+    }
+
+    /// Whatever a program does, the machine keeps running.
+    #[test]
+    fn test_random_programs_dont_panic() {
+        use super::{jit::Backend, timing::Timing};
+        use rand::prelude::*;
+
+        let mut rng = StdRng::seed_from_u64(46);
+        for n in 0..150 {
+            let mut c8 = Chip8::new();
+            rng.fill(&mut c8.memory.0[0x200..]);
+            c8.registers.pc = rng.gen_range(0..0x1000);
+            c8.registers.vi = rng.gen();
+            match n % 3 {
+                0 => (),
+                1 => c8.backend = Backend::Jit,
+                _ => c8.timing = Timing::Vip,
+            }
+            c8.quirks.display_wait = n % 2 == 0;
+            for _ in 0..30 {
+                if c8.run_frame(50).is_err() {
+                    c8.registers.stack.push(0x200);
+                }
+                if c8.status == InstructionResult::Waiting {
+                    c8.keypad.press(Button::B1);
+                    c8.keypad.release(Button::B1);
+                }
+            }
+        }
+    }
+}
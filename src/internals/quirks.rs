@@ -0,0 +1,8 @@
+/// Behaviours that differ between CHIP-8 interpreters. The defaults match
+/// what this emulator has always done.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    /// `Dxyn` waits for the next vblank before drawing, like the COSMAC VIP
+    /// did, so at most one sprite gets drawn per frame.
+    pub display_wait: bool,
+}
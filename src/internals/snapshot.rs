@@ -0,0 +1,242 @@
+use super::{
+    display::{Dirty, HEIGHT, WIDTH},
+    memory::{Ram, Registers, MEMORY_SIZE},
+    quirks::Quirks,
+    Chip8, InstructionResult, ON,
+};
+
+/// Marks an encoded snapshot; the last byte is the version.
+const MAGIC: &[u8; 4] = b"C8S\x01";
+/// How deep the stack can be in an encoded snapshot.
+pub const ENCODED_STACK: usize = 16;
+/// Every encoded snapshot is exactly this long: the registers, the stack
+/// padded out to `ENCODED_STACK`, memory, the screen a bit per pixel and a
+/// byte of flags.
+pub const ENCODED_SIZE: usize =
+    MAGIC.len() + 16 + 2 + 2 + 2 + 1 + 2 * ENCODED_STACK + MEMORY_SIZE + WIDTH * HEIGHT / 8 + 1;
+
+/// Everything needed to put a machine back exactly where it was, short of
+/// what's being held on the keypad.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub registers: Registers,
+    pub memory: Ram,
+    pub frame_buffer: [u32; WIDTH * HEIGHT],
+    pub quirks: Quirks,
+    /// Halfway through an `Fx0A`.
+    pub waiting: bool,
+}
+
+impl Snapshot {
+    /// The snapshot as bytes, for front ends that keep states that way. It
+    /// fails if the stack is deeper than an encoded one can hold.
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let r = &self.registers;
+        if r.stack.len() > ENCODED_STACK {
+            return Err(format!(
+                "the stack is {} deep, only {ENCODED_STACK} can be saved",
+                r.stack.len()
+            ));
+        }
+        let mut out = Vec::with_capacity(ENCODED_SIZE);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&r.r);
+        out.extend_from_slice(&r.vi.to_le_bytes());
+        out.extend_from_slice(&[r.delay, r.sound]);
+        out.extend_from_slice(&r.pc.to_le_bytes());
+        out.push(r.stack.len() as u8);
+        for i in 0..ENCODED_STACK {
+            let a = r.stack.get(i).copied().unwrap_or(0);
+            out.extend_from_slice(&a.to_le_bytes());
+        }
+        out.extend_from_slice(&self.memory.0);
+        for pixels in self.frame_buffer.chunks_exact(8) {
+            out.push(pixels.iter().fold(0, |bits, p| bits << 1 | (*p != 0) as u8));
+        }
+        out.push(self.quirks.display_wait as u8 | (self.waiting as u8) << 1);
+        debug_assert_eq!(out.len(), ENCODED_SIZE);
+        Ok(out)
+    }
+
+    /// Reads back what `encode` wrote.
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != ENCODED_SIZE || !bytes.starts_with(MAGIC) {
+            return Err("not a saved state this version can read".to_string());
+        }
+        let mut rest = &bytes[MAGIC.len()..];
+        let mut take = |n: usize| {
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            head
+        };
+        let mut registers = Registers::default();
+        registers.r.copy_from_slice(take(16));
+        registers.vi = u16::from_le_bytes([take(1)[0], take(1)[0]]);
+        [registers.delay, registers.sound] = [take(1)[0], take(1)[0]];
+        registers.pc = u16::from_le_bytes([take(1)[0], take(1)[0]]);
+        let depth = take(1)[0] as usize;
+        if depth > ENCODED_STACK {
+            return Err(format!("a stack {depth} deep can't have been saved"));
+        }
+        let stack = take(2 * ENCODED_STACK);
+        registers.stack.extend(
+            stack
+                .chunks_exact(2)
+                .take(depth)
+                .map(|a| u16::from_le_bytes([a[0], a[1]])),
+        );
+        // every address the program could carry on from has to be in
+        // memory, though the last byte is fine: pc wraps from there
+        let fits = |address: u16| (address as usize) < MEMORY_SIZE;
+        if !fits(registers.pc) {
+            return Err(format!("pc {:#05x} is off the end of memory", registers.pc));
+        }
+        if let Some(a) = registers.stack.iter().find(|&&a| !fits(a)) {
+            return Err(format!("return address {a:#05x} is off the end of memory"));
+        }
+        let mut memory = Ram([0; MEMORY_SIZE]);
+        memory.0.copy_from_slice(take(MEMORY_SIZE));
+        let mut frame_buffer = [0; WIDTH * HEIGHT];
+        for (pixels, bits) in frame_buffer
+            .chunks_exact_mut(8)
+            .zip(take(WIDTH * HEIGHT / 8))
+        {
+            for (i, p) in pixels.iter_mut().enumerate() {
+                *p = if bits << i & 0x80 != 0 { ON } else { 0 };
+            }
+        }
+        let flags = take(1)[0];
+        Ok(Snapshot {
+            registers,
+            memory,
+            frame_buffer,
+            quirks: Quirks {
+                display_wait: flags & 1 != 0,
+            },
+            waiting: flags & 2 != 0,
+        })
+    }
+}
+
+impl Chip8 {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers.clone(),
+            memory: self.memory.clone(),
+            frame_buffer: self.frame_buffer,
+            quirks: self.quirks,
+            waiting: self.status == InstructionResult::Waiting,
+        }
+    }
+
+    pub fn restore(&mut self, s: &Snapshot) {
+        self.registers = s.registers.clone();
+        self.memory = s.memory.clone();
+        self.invalidate(0..MEMORY_SIZE);
+        self.frame_buffer = s.frame_buffer;
+        self.quirks = s.quirks;
+        self.status = match s.waiting {
+            true => InstructionResult::Waiting,
+            false => InstructionResult::Success,
+        };
+        self.dirty = Dirty::full();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Snapshot, ENCODED_SIZE};
+    use crate::internals::Chip8;
+
+    #[test]
+    fn test_restore_rewinds() {
+        // v0 += 1, draw, loop
+        let mut c8 = Chip8::new();
+        c8.memory.0[0x200..0x206].copy_from_slice(&[0x70, 0x01, 0xD0, 0x05, 0x12, 0x00]);
+        c8.run_frame(3).unwrap();
+        let saved = c8.snapshot();
+        let frame = c8.take_frame().pixels;
+
+        c8.run_frame(30).unwrap();
+        assert_ne!(c8.registers.r[0], saved.registers.r[0]);
+        c8.restore(&saved);
+        assert_eq!(c8.registers.r[0], 1);
+        assert_eq!(c8.registers.pc, saved.registers.pc);
+        let restored = c8.take_frame();
+        assert_eq!(restored.pixels, frame);
+        assert!(!restored.dirty.is_empty());
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // call a subroutine that draws the 0 glyph and waits for a key
+        let mut c8 = Chip8::new();
+        let program = [0x22, 0x04, 0x00, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0xF1, 0x0A];
+        c8.memory.0[0x200..0x20A].copy_from_slice(&program);
+        c8.quirks.display_wait = true;
+        // the draw holds on to the next frame
+        c8.run_frame(10).unwrap();
+        c8.run_frame(10).unwrap();
+        c8.registers.delay = 3;
+        let saved = c8.snapshot();
+        let bytes = saved.encode().unwrap();
+        assert_eq!(bytes.len(), ENCODED_SIZE);
+
+        let decoded = Snapshot::decode(&bytes).unwrap();
+        assert_eq!(decoded.registers.stack, [0x202]);
+        assert_eq!(decoded.registers.pc, saved.registers.pc);
+        assert_eq!(decoded.registers.delay, 3);
+        assert_eq!(decoded.memory.0, saved.memory.0);
+        assert_eq!(decoded.frame_buffer, saved.frame_buffer);
+        assert!(decoded.quirks.display_wait);
+        assert!(decoded.waiting);
+        assert_eq!(decoded.encode().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_end_of_memory_round_trip() {
+        // jump to the last byte, which holds a call back to it
+        let mut c8 = Chip8::new();
+        c8.memory.0[0x200..0x202].copy_from_slice(&[0x1F, 0xFF]);
+        c8.memory.0[0xFFF] = 0x2F;
+        c8.memory.0[0] = 0xFF;
+        c8.step().unwrap();
+        c8.step().unwrap();
+        assert_eq!(c8.registers.pc, 0xFFF);
+        assert_eq!(c8.registers.stack, [0x001]);
+        c8.registers.stack.push(0xFFF);
+
+        let bytes = c8.snapshot().encode().unwrap();
+        let decoded = Snapshot::decode(&bytes).unwrap();
+        assert_eq!(decoded.registers.pc, 0xFFF);
+        assert_eq!(decoded.registers.stack, [0x001, 0xFFF]);
+        let mut restored = Chip8::new();
+        restored.restore(&decoded);
+        restored.step().unwrap();
+        assert_eq!(restored.registers.pc, 0xFFF);
+    }
+
+    #[test]
+    fn test_decode_rejects() {
+        let mut c8 = Chip8::new();
+        let bytes = c8.snapshot().encode().unwrap();
+        assert!(Snapshot::decode(&bytes[1..]).is_err());
+        let mut other = bytes.clone();
+        other[3] = 2;
+        assert!(Snapshot::decode(&other).is_err());
+
+        c8.registers.pc = 0x1000;
+        let bytes = c8.snapshot().encode().unwrap();
+        assert!(Snapshot::decode(&bytes)
+            .err()
+            .unwrap()
+            .contains("pc 0x1000"));
+        c8.registers.pc = 0x200;
+        c8.registers.stack = vec![0x202, 0x1000];
+        let bytes = c8.snapshot().encode().unwrap();
+        assert!(Snapshot::decode(&bytes).err().unwrap().contains("0x1000"));
+
+        c8.registers.stack = vec![0x200; 17];
+        assert!(c8.snapshot().encode().is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::{Chip8, Chip8Error, Instruction, InstructionResult};
+
+/// How much of a program runs in each 60 Hz frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timing {
+    /// The same number of instructions every frame, whatever they are.
+    #[default]
+    Instructions,
+    /// The COSMAC VIP: every instruction costs what it took the original
+    /// interpreter, out of the machine cycles the display leaves free, and
+    /// `Dxyn` always waits for the display interrupt. The instructions per
+    /// frame setting doesn't apply.
+    Vip,
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instructions" => Ok(Timing::Instructions),
+            "vip" => Ok(Timing::Vip),
+            _ => Err(format!("unknown timing {s}")),
+        }
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Timing::Instructions => write!(f, "instructions"),
+            Timing::Vip => write!(f, "vip"),
+        }
+    }
+}
+
+/// The 1802 runs at 1.76 MHz and takes 8 clocks a machine cycle.
+pub const VIP_CYCLES_PER_FRAME: i32 = 3668;
+/// The 1861 takes a byte over DMA for each of its 128 lines' 8 bytes, and
+/// each byte is a machine cycle the interpreter doesn't get.
+pub const VIP_DISPLAY_CYCLES: i32 = 128 * 8;
+/// The display interrupt's own routine, which also counts the timers down.
+pub const VIP_INTERRUPT_CYCLES: i32 = 46;
+/// What's left over for the interpreter each frame.
+pub const VIP_BUDGET: i32 = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES - VIP_INTERRUPT_CYCLES;
+
+/// Machine cycles the VIP's interpreter takes to fetch, decode and run `i`,
+/// rounded from the timings usually quoted for it. Sprites cost by the row,
+/// and more when they straddle a byte of the display; `x` is where it's
+/// drawn.
+pub fn vip_cycles(i: Instruction, x: u8) -> i32 {
+    use Instruction::*;
+    match i {
+        ClearDisplay => 24,
+        ReturnFromSubRoutine | JumpTo(_) | Call(_) | JumpV0(_) => 23,
+        SkipIf(..) | SkipIfNot(..) | LoadIntoI(_) => 12,
+        SkipIfRegistersEqual(..) | SkipIfNotEqual(..) => 16,
+        SkipIfPressed(_) | SkipIfNotPressed(_) => 16,
+        LoadInto(..) => 6,
+        Add(..) => 10,
+        LoadIntoRegister(..) | Or(..) | And(..) | Xor(..) | AddRegisters(..) | Sub(..)
+        | ShiftRight(..) | SubBorrow(..) | ShiftLeft(..) => 44,
+        Random(..) => 36,
+        Draw(_, _, n) => {
+            let per_row = if x.is_multiple_of(8) { 9 } else { 17 };
+            26 + per_row * n as i32
+        }
+        LoadFromDelay(_) | WaitForKey(_) | LoadToDelay(_) | LoadToSound(_) => 10,
+        AddToI(_) => 19,
+        LoadSpriteToI(_) | LoadBigSpriteToI(_) => 20,
+        LoadBcd(_) => 204,
+        LoadToMemory(x) | LoadFromMemory(x) => 28 + 14 * (x as i32 + 1),
+        // machine code, which we can't run, so as if it returned at once
+        Nop => 23,
+    }
+}
+
+impl Chip8 {
+    /// `run_frame`'s loop with VIP timing. Cycles an instruction overran
+    /// the frame by come out of the next one; cycles spent waiting for the
+    /// display interrupt are lost.
+    pub(super) fn run_cycles(&mut self) -> Result<(), Chip8Error> {
+        self.cycles += VIP_BUDGET;
+        let mut first = true;
+        while self.cycles > 0 {
+            let i = self.fetch();
+            // Dxyn waits for the interrupt, so it's always first in a frame
+            if !first && matches!(i, Instruction::Draw(..)) {
+                break;
+            }
+            let x = match i {
+                Instruction::Draw(x, ..) => self.read(x),
+                _ => 0,
+            };
+            self.cycles -= vip_cycles(i, x);
+            if let InstructionResult::Waiting = self.step()? {
+                break;
+            }
+            first = false;
+        }
+        // idle until the interrupt
+        self.cycles = self.cycles.min(0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{vip_cycles, Timing, VIP_BUDGET};
+    use crate::internals::{test::with_program, Instruction, Register};
+
+    #[test]
+    fn test_budget() {
+        // v0 += 1 over and over, 10 cycles and then 23 for the jump
+        let mut program = [0x70, 0x01].repeat(1000);
+        program.extend([0x12, 0x00]);
+        let mut c8 = with_program(&program);
+        c8.timing = Timing::Vip;
+        // the instructions per frame setting doesn't count
+        assert_eq!(VIP_BUDGET, 2598);
+        // 260 adds, going 2 cycles over
+        c8.run_frame(1).unwrap();
+        assert_eq!(c8.registers.r[0], (260 % 256) as u8);
+        assert_eq!(c8.registers.pc, 0x200 + 2 * 260);
+        // which leaves 2596, for 260 more and 4 over
+        c8.run_frame(1).unwrap();
+        assert_eq!(c8.registers.pc, 0x200 + 2 * 520);
+        // then 2594, for 260 again and 6 over
+        c8.run_frame(1).unwrap();
+        assert_eq!(c8.registers.pc, 0x200 + 2 * 780);
+    }
+
+    #[test]
+    fn test_draw_waits_for_interrupt() {
+        // draw, draw, loop
+        let mut c8 = with_program(&[0xD0, 0x15, 0xD0, 0x15, 0x12, 0x04]);
+        c8.timing = Timing::Vip;
+        c8.run_frame(1000).unwrap();
+        assert_eq!(c8.registers.pc, 0x202);
+        c8.run_frame(1000).unwrap();
+        assert_eq!(c8.registers.pc, 0x204);
+        // timers still tick every frame
+        c8.registers.delay = 2;
+        c8.run_frame(1000).unwrap();
+        assert_eq!(c8.registers.delay, 1);
+    }
+
+    #[test]
+    fn test_sprite_costs() {
+        let draw = Instruction::Draw(Register::V0, Register::V1, 5);
+        assert!(vip_cycles(draw, 3) > vip_cycles(draw, 8));
+        assert_eq!(
+            vip_cycles(Instruction::LoadToMemory(Register::VF), 0),
+            28 + 14 * 16
+        );
+    }
+
+    #[test]
+    fn test_names() {
+        for t in [Timing::Instructions, Timing::Vip] {
+            assert_eq!(t.to_string().parse::<Timing>(), Ok(t));
+        }
+    }
+}
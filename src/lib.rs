@@ -0,0 +1,41 @@
+pub mod batch;
+pub mod capture;
+pub mod cartridge;
+#[cfg(feature = "desktop")]
+pub mod config;
+pub mod database;
+pub mod emulator;
+pub mod ffi;
+#[cfg(feature = "desktop")]
+pub mod gui;
+pub mod gym;
+pub mod headless;
+pub mod internals;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+#[cfg(feature = "desktop")]
+pub mod tui;
+#[cfg(feature = "web")]
+pub mod web;
+
+/// What a caught panic said, if it said anything.
+pub(crate) fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(s), _) => s.to_string(),
+        (_, Some(s)) => s.clone(),
+        _ => "no message".to_string(),
+    }
+}
+
+/// Somewhere tests can leave files. WASI has no temp directory of its own,
+/// so runners there map one in at /tmp.
+#[cfg(test)]
+pub(crate) fn temp_dir() -> std::path::PathBuf {
+    match cfg!(target_os = "wasi") {
+        true => "/tmp".into(),
+        false => std::env::temp_dir(),
+    }
+}
@@ -0,0 +1,823 @@
+//! The core as a libretro core, for RetroArch and the other front ends
+//! that load them. Build it with
+//!
+//! ```sh
+//! cargo build --release --no-default-features --features libretro
+//! ```
+//!
+//! and install `target/release/libchip8.so` as `chip8_libretro.so`.
+//!
+//! Each `retro_run` is one 60 Hz frame. The d-pad, A and B press whatever
+//! the program database binds for the game, or the usual 5, 8, 7, 9, 6
+//! and 4 if it binds nothing; a keyboard's hex keys press their own
+//! buttons. The database is read from `chip8-database` in the front end's
+//! system directory, if it's there.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, c_uint, c_void, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::cartridge::unpack;
+use crate::database::{Database, Settings};
+use crate::internals::{
+    audio::Beeper,
+    display::{HEIGHT, WIDTH},
+    keypad::{Button, Control},
+    memory::MEMORY_SIZE,
+    snapshot::{Snapshot, ENCODED_SIZE},
+    timing::Timing,
+    Chip8, Reset, FRAME_RATE,
+};
+use crate::panic_message;
+
+// the parts of libretro.h we use
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_ENVIRONMENT_SET_MESSAGE: c_uint = 6;
+const RETRO_ENVIRONMENT_GET_SYSTEM_DIRECTORY: c_uint = 9;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const RETRO_ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const RETRO_ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const RETRO_PIXEL_FORMAT_XRGB8888: u32 = 1;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_KEYBOARD: c_uint = 3;
+const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+const RETRO_REGION_NTSC: c_uint = 0;
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+struct RetroVariable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+struct RetroInputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+#[repr(C)]
+struct RetroMessage {
+    msg: *const c_char,
+    frames: c_uint,
+}
+
+pub type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = unsafe extern "C" fn();
+pub type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+/// The core options, as keys and the front end's "Label; choice|choice"
+/// descriptions. The first choice is the default.
+const VARIABLES: [(&CStr, &CStr); 3] = [
+    (
+        c"chip8_instructions_per_frame",
+        c"Speed (instructions per frame); auto|5|7|10|15|20|30|50|100|200|500|1000",
+    ),
+    (c"chip8_display_wait", c"Display wait quirk; auto|on|off"),
+    (c"chip8_timing", c"Timing; instructions|vip"),
+];
+
+/// Each RetroPad button we use, what it stands for, and what it presses
+/// when the game doesn't say.
+const PAD: [(c_uint, Control, Button, &CStr); 6] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, Control::Up, Button::B5, c"Up"),
+    (
+        RETRO_DEVICE_ID_JOYPAD_DOWN,
+        Control::Down,
+        Button::B8,
+        c"Down",
+    ),
+    (
+        RETRO_DEVICE_ID_JOYPAD_LEFT,
+        Control::Left,
+        Button::B7,
+        c"Left",
+    ),
+    (
+        RETRO_DEVICE_ID_JOYPAD_RIGHT,
+        Control::Right,
+        Button::B9,
+        c"Right",
+    ),
+    (RETRO_DEVICE_ID_JOYPAD_A, Control::A, Button::B6, c"A"),
+    (RETRO_DEVICE_ID_JOYPAD_B, Control::B, Button::B4, c"B"),
+];
+
+/// What the core options say, where they override the database.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Options {
+    instructions_per_frame: Option<usize>,
+    display_wait: Option<bool>,
+    timing: Timing,
+}
+
+impl Options {
+    /// Takes in one option's value. Anything unknown is left alone.
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "chip8_instructions_per_frame" => self.instructions_per_frame = value.parse().ok(),
+            "chip8_display_wait" => {
+                self.display_wait = match value {
+                    "on" => Some(true),
+                    "off" => Some(false),
+                    _ => None,
+                }
+            }
+            "chip8_timing" => self.timing = value.parse().unwrap_or_default(),
+            _ => (),
+        }
+    }
+}
+
+/// A loaded game.
+struct Core {
+    chip8: Chip8,
+    /// What the database says about the game.
+    settings: Settings,
+    options: Options,
+    beeper: Beeper,
+    /// Why the program stopped, if it has.
+    crashed: Option<String>,
+    screen: [u32; WIDTH * HEIGHT],
+    mono: Vec<i16>,
+    // interleaved, the way the front end takes it
+    stereo: Vec<i16>,
+}
+
+impl Core {
+    fn load(database: &Database, file: &[u8], options: Options) -> Result<Self, String> {
+        let mut chip8 = Chip8::new();
+        let (rom, cartridge) = unpack(file).map_err(|e| e.to_string())?;
+        let base = Settings::of(&chip8, 10);
+        let settings = database
+            .load_program(&mut chip8, &rom, cartridge.as_ref(), &base, Reset::Cold)
+            .map_err(|e| e.to_string())?;
+        let mut core = Core {
+            chip8,
+            settings,
+            options,
+            beeper: Beeper::default(),
+            crashed: None,
+            screen: [0; WIDTH * HEIGHT],
+            mono: Vec::new(),
+            stereo: Vec::new(),
+        };
+        core.configure(options);
+        Ok(core)
+    }
+
+    fn configure(&mut self, options: Options) {
+        self.options = options;
+        self.chip8.quirks.display_wait = options
+            .display_wait
+            .unwrap_or(self.settings.quirks.display_wait);
+        self.chip8.timing = options.timing;
+    }
+
+    fn instructions_per_frame(&self) -> usize {
+        self.options
+            .instructions_per_frame
+            .unwrap_or(self.settings.instructions_per_frame)
+    }
+
+    /// Holds the buttons in `pad`, a bit per RetroPad id, and `keys`, a bit
+    /// per hex key, and releases the rest.
+    fn hold(&mut self, pad: u16, keys: u16) {
+        let mut held = keys;
+        for (id, control, fallback, _) in PAD {
+            if pad & 1 << id != 0 {
+                held |= 1 << self.settings.keymap.get(control).unwrap_or(fallback) as u16;
+            }
+        }
+        for k in 0..16 {
+            match held & 1 << k != 0 {
+                true => self.chip8.keypad.press(Button::from_u8(k)),
+                false => self.chip8.keypad.release(Button::from_u8(k)),
+            }
+        }
+    }
+
+    /// Runs a frame, unless the program has stopped, and renders its
+    /// picture and sound. Returns why the program stopped, if it just did.
+    /// A panic stops the program too, rather than unwinding into the
+    /// front end, which would abort it.
+    fn run(&mut self) -> Option<String> {
+        let mut stopped = None;
+        if self.crashed.is_none() {
+            // the front end may have written over code through
+            // `retro_get_memory_data` since the last frame
+            self.chip8.invalidate(0..MEMORY_SIZE);
+            let instructions = self.instructions_per_frame();
+            let chip8 = &mut self.chip8;
+            let e = match catch_unwind(AssertUnwindSafe(|| chip8.run_frame(instructions))) {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(format!("the program stopped: {e:?}")),
+                Err(p) => Some(format!("the core panicked: {}", panic_message(&*p))),
+            };
+            if let Some(e) = e {
+                self.crashed = Some(e.clone());
+                stopped = Some(e);
+            }
+        }
+        let palette = self.settings.palette;
+        for (out, p) in self.screen.iter_mut().zip(&self.chip8.frame_buffer) {
+            *out = palette.colour(*p);
+        }
+        self.mono.clear();
+        let beeping = self.crashed.is_none() && self.chip8.beeping();
+        self.beeper.render_frame(beeping, &mut self.mono);
+        self.stereo.clear();
+        self.stereo.extend(self.mono.iter().flat_map(|s| [*s, *s]));
+        stopped
+    }
+
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let snapshot = Snapshot::decode(bytes)?;
+        self.chip8.restore(&snapshot);
+        self.crashed = None;
+        Ok(())
+    }
+}
+
+/// Everything the front end has handed over, and the game.
+struct Retro {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    core: Option<Core>,
+}
+
+static RETRO: Mutex<Retro> = Mutex::new(Retro {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    core: None,
+});
+
+fn retro() -> MutexGuard<'static, Retro> {
+    RETRO.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Retro {
+    unsafe fn environment(&self, cmd: c_uint, data: *mut c_void) -> bool {
+        self.environment.is_some_and(|f| f(cmd, data))
+    }
+
+    /// What the front end has the core options set to.
+    unsafe fn options(&self) -> Options {
+        let mut options = Options::default();
+        for (key, _) in VARIABLES {
+            let mut v = RetroVariable {
+                key: key.as_ptr(),
+                value: std::ptr::null(),
+            };
+            let found = self.environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut v as *mut _ as _);
+            if found && !v.value.is_null() {
+                if let (Ok(k), Ok(v)) = (key.to_str(), CStr::from_ptr(v.value).to_str()) {
+                    options.set(k, v);
+                }
+            }
+        }
+        options
+    }
+
+    /// The program database, from the system directory.
+    unsafe fn database(&self) -> Database {
+        let mut dir: *const c_char = std::ptr::null();
+        if !self.environment(
+            RETRO_ENVIRONMENT_GET_SYSTEM_DIRECTORY,
+            &mut dir as *mut _ as _,
+        ) || dir.is_null()
+        {
+            return Database::default();
+        }
+        let Ok(dir) = CStr::from_ptr(dir).to_str() else {
+            return Database::default();
+        };
+        let path = std::path::Path::new(dir).join("chip8-database");
+        match path.exists() {
+            true => Database::open(path).unwrap_or_default(),
+            false => Database::default(),
+        }
+    }
+
+    unsafe fn message(&self, msg: &str) {
+        let Ok(msg) = std::ffi::CString::new(msg) else {
+            return;
+        };
+        let mut m = RetroMessage {
+            msg: msg.as_ptr(),
+            frames: 5 * FRAME_RATE,
+        };
+        self.environment(RETRO_ENVIRONMENT_SET_MESSAGE, &mut m as *mut _ as _);
+    }
+
+    /// Which RetroPad buttons and hex keys are held.
+    unsafe fn input(&self) -> (u16, u16) {
+        let (Some(poll), Some(state)) = (self.input_poll, self.input_state) else {
+            return (0, 0);
+        };
+        poll();
+        let mut pad = 0;
+        for (id, ..) in PAD {
+            if state(0, RETRO_DEVICE_JOYPAD, 0, id) != 0 {
+                pad |= 1 << id;
+            }
+        }
+        let mut keys = 0;
+        for k in 0..16 {
+            // libretro's key codes for letters and digits are their ASCII
+            let code = char::from_digit(k, 16).unwrap() as c_uint;
+            if state(0, RETRO_DEVICE_KEYBOARD, 0, code) != 0 {
+                keys |= 1 << k;
+            }
+        }
+        (pad, keys)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(f: EnvironmentFn) {
+    let mut r = retro();
+    r.environment = Some(f);
+    let mut variables: Vec<RetroVariable> = VARIABLES
+        .iter()
+        .map(|(key, value)| RetroVariable {
+            key: key.as_ptr(),
+            value: value.as_ptr(),
+        })
+        .collect();
+    variables.push(RetroVariable {
+        key: std::ptr::null(),
+        value: std::ptr::null(),
+    });
+    r.environment(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as _);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(f: VideoRefreshFn) {
+    retro().video_refresh = Some(f);
+}
+
+/// Sound goes out a frame at a time, through the batch callback.
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(f: AudioSampleBatchFn) {
+    retro().audio_sample_batch = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(f: InputPollFn) {
+    retro().input_poll = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(f: InputStateFn) {
+    retro().input_state = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    retro().core = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    let Some(info) = info.as_mut() else {
+        return;
+    };
+    *info = RetroSystemInfo {
+        library_name: c"CHIP-8".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as _,
+        valid_extensions: c"ch8|c8|gif".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    let Some(info) = info.as_mut() else {
+        return;
+    };
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: FRAME_RATE as f64,
+            sample_rate: Beeper::default().sample_rate as f64,
+        },
+    };
+}
+
+/// There's only the one kind of controller.
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+/// The reset button.
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = &mut retro().core {
+        core.chip8.reset(Reset::Warm);
+        core.crashed = None;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    let mut r = retro();
+    let mut updated = false;
+    if r.environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+        &mut updated as *mut _ as _,
+    ) && updated
+    {
+        let options = r.options();
+        if let Some(core) = &mut r.core {
+            core.configure(options);
+        }
+    }
+    let (pad, keys) = r.input();
+    let Some(core) = &mut r.core else {
+        return;
+    };
+    core.hold(pad, keys);
+    let stopped = core.run();
+    let core = r.core.as_ref().unwrap();
+    if let Some(f) = r.video_refresh {
+        f(
+            core.screen.as_ptr() as _,
+            WIDTH as c_uint,
+            HEIGHT as c_uint,
+            WIDTH * 4,
+        );
+    }
+    if let Some(f) = r.audio_sample_batch {
+        let mut samples = &core.stereo[..];
+        while !samples.is_empty() {
+            let taken = f(samples.as_ptr(), samples.len() / 2);
+            if taken == 0 {
+                break;
+            }
+            samples = &samples[(2 * taken).min(samples.len())..];
+        }
+    }
+    if let Some(e) = stopped {
+        r.message(&e);
+    }
+}
+
+/// Every state is the same size.
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    ENCODED_SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let r = retro();
+    let Some(core) = &r.core else {
+        return false;
+    };
+    match core.chip8.snapshot().encode() {
+        Ok(bytes) if !data.is_null() && size >= bytes.len() => {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, bytes.len());
+            true
+        }
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let bytes = std::slice::from_raw_parts(data as *const u8, size);
+    match &mut retro().core {
+        Some(core) => core.restore(bytes).is_ok(),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    let Some(game) = game.as_ref() else {
+        return false;
+    };
+    if game.data.is_null() {
+        return false;
+    }
+    let mut r = retro();
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !r.environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut format as *mut _ as _,
+    ) {
+        return false;
+    }
+    let mut descriptors: Vec<RetroInputDescriptor> = PAD
+        .iter()
+        .map(|(id, _, _, description)| RetroInputDescriptor {
+            port: 0,
+            device: RETRO_DEVICE_JOYPAD,
+            index: 0,
+            id: *id,
+            description: description.as_ptr(),
+        })
+        .collect();
+    descriptors.push(RetroInputDescriptor {
+        port: 0,
+        device: 0,
+        index: 0,
+        id: 0,
+        description: std::ptr::null(),
+    });
+    r.environment(
+        RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+        descriptors.as_mut_ptr() as _,
+    );
+
+    let file = std::slice::from_raw_parts(game.data as *const u8, game.size);
+    match Core::load(&r.database(), file, r.options()) {
+        Ok(core) => {
+            r.core = Some(core);
+            true
+        }
+        Err(e) => {
+            r.message(&e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _type: c_uint,
+    _info: *const RetroGameInfo,
+    _num: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    retro().core = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+/// Memory, for cheats and achievements to look at. Writes to it go
+/// straight to the program, and code written over is decoded again at the
+/// start of the next frame.
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    match (&mut retro().core, id) {
+        (Some(core), RETRO_MEMORY_SYSTEM_RAM) => core.chip8.memory.0.as_mut_ptr() as _,
+        _ => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match (&retro().core, id) {
+        (Some(_), RETRO_MEMORY_SYSTEM_RAM) => MEMORY_SIZE,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{c_uint, c_void, CStr};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::internals::display::Palette;
+
+    // v0 = 5, draw the 5 glyph, buzz, then wait for key 5 before
+    // returning from a subroutine it never called
+    const PROGRAM: [u8; 14] = [
+        0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0xF0, 0x18, 0xE0, 0x9E, 0x12, 0x08, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn test_options() {
+        let mut o = Options::default();
+        o.set("chip8_instructions_per_frame", "200");
+        o.set("chip8_display_wait", "on");
+        o.set("chip8_timing", "vip");
+        o.set("chip8_volume", "11");
+        assert_eq!(
+            o,
+            Options {
+                instructions_per_frame: Some(200),
+                display_wait: Some(true),
+                timing: Timing::Vip,
+            }
+        );
+        o.set("chip8_instructions_per_frame", "auto");
+        o.set("chip8_display_wait", "auto");
+        assert_eq!(o.instructions_per_frame, None);
+        assert_eq!(o.display_wait, None);
+    }
+
+    #[test]
+    fn test_pad() {
+        let mut core = Core::load(&Database::default(), &PROGRAM, Options::default()).unwrap();
+        core.hold(1 << RETRO_DEVICE_ID_JOYPAD_UP, 1 << 0xC);
+        assert!(core.chip8.keypad.is_pressed(Button::B5));
+        assert!(core.chip8.keypad.is_pressed(Button::BC));
+        core.settings.keymap.bind(Control::Up, Button::B2);
+        core.hold(1 << RETRO_DEVICE_ID_JOYPAD_UP, 0);
+        assert!(core.chip8.keypad.is_pressed(Button::B2));
+        assert!(!core.chip8.keypad.is_pressed(Button::B5));
+        assert!(!core.chip8.keypad.is_pressed(Button::BC));
+    }
+
+    #[test]
+    fn test_run() {
+        let mut core = Core::load(&Database::default(), &PROGRAM, Options::default()).unwrap();
+        assert_eq!(core.run(), None);
+        let on = Palette::default().colour(1);
+        assert_eq!(core.screen.iter().filter(|p| **p == on).count(), 14);
+        assert_eq!(core.stereo.len(), 2 * 735);
+        assert!(core.stereo.iter().any(|s| *s != 0));
+        assert_eq!(core.stereo[..2], [core.stereo[0]; 2]);
+
+        core.hold(1 << RETRO_DEVICE_ID_JOYPAD_UP, 0);
+        assert!(core.run().is_some());
+        assert!(core.crashed.is_some());
+        // it stays stopped, and quiet
+        assert_eq!(core.run(), None);
+        assert!(core.stereo.iter().all(|s| *s == 0));
+    }
+
+    // what the fake front end saw
+    static SEEN: Mutex<(usize, usize, u32)> = Mutex::new((0, 0, 0));
+
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        match cmd {
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const u32) == 1,
+            RETRO_ENVIRONMENT_GET_VARIABLE => {
+                let v = &mut *(data as *mut RetroVariable);
+                match CStr::from_ptr(v.key).to_bytes() {
+                    b"chip8_instructions_per_frame" => v.value = c"3".as_ptr(),
+                    _ => return false,
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    unsafe extern "C" fn video(data: *const c_void, w: c_uint, h: c_uint, pitch: usize) {
+        assert_eq!((w, h, pitch), (64, 32, 256));
+        let pixels = std::slice::from_raw_parts(data as *const u32, 64 * 32);
+        SEEN.lock().unwrap().2 = pixels[0];
+    }
+
+    // takes a frame's sound in more than one go
+    unsafe extern "C" fn audio(_: *const i16, frames: usize) -> usize {
+        let taken = frames.min(500);
+        SEEN.lock().unwrap().1 += taken;
+        taken
+    }
+
+    extern "C" fn poll() {
+        SEEN.lock().unwrap().0 += 1;
+    }
+
+    extern "C" fn state(_: c_uint, _: c_uint, _: c_uint, _: c_uint) -> i16 {
+        0
+    }
+
+    /// The whole lifecycle, the way a front end drives it.
+    #[test]
+    fn test_front_end() {
+        unsafe {
+            assert_eq!(retro_api_version(), 1);
+            retro_set_environment(environment);
+            retro_set_video_refresh(video);
+            retro_set_audio_sample_batch(audio);
+            retro_set_input_poll(poll);
+            retro_set_input_state(state);
+            retro_init();
+            let mut av = std::mem::zeroed::<RetroSystemAvInfo>();
+            retro_get_system_av_info(&mut av);
+            assert_eq!((av.timing.fps, av.timing.sample_rate), (60.0, 44100.0));
+
+            let game = RetroGameInfo {
+                path: std::ptr::null(),
+                data: PROGRAM.as_ptr() as _,
+                size: PROGRAM.len(),
+                meta: std::ptr::null(),
+            };
+            assert!(retro_load_game(&game));
+            assert_eq!(retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM), 4096);
+            let memory = retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM) as *const u8;
+            assert_eq!(*memory.add(0x200), 0x60);
+
+            // 3 instructions a frame, from the options
+            retro_run();
+            assert_eq!(retro().core.as_ref().unwrap().chip8.registers.pc, 0x206);
+            assert_eq!(*SEEN.lock().unwrap(), (1, 735, 0));
+
+            let mut state = vec![0u8; retro_serialize_size()];
+            assert!(retro_serialize(state.as_mut_ptr() as _, state.len()));
+            retro_run();
+            assert!(retro_unserialize(state.as_ptr() as _, state.len()));
+            assert_eq!(retro().core.as_ref().unwrap().chip8.registers.pc, 0x206);
+            assert!(!retro_unserialize(state.as_ptr() as _, 10));
+
+            retro_reset();
+            assert_eq!(retro().core.as_ref().unwrap().chip8.registers.pc, 0x200);
+            retro_unload_game();
+            assert!(retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM).is_null());
+            assert!(!retro_serialize(state.as_mut_ptr() as _, state.len()));
+            retro_deinit();
+        }
+    }
+}
@@ -1,73 +1,340 @@
-use crate::gui::{handle_event, Controller};
-use internals::{Chip8, DisplayCommand, InstructionResult};
-use softbuffer::Surface;
-use std::{
-    rc::Rc,
-    sync::{Arc, RwLock},
+use chip8::batch::{Batch, Job};
+use chip8::capture::{screenshot::save_png, wav::WavWriter};
+use chip8::database::{Database, Settings};
+use chip8::emulator::{Emulator, Reply};
+use chip8::gui::{self, effects::Pipeline, handle_event, present::Presenter, Remote};
+use chip8::headless::Headless;
+use chip8::internals::{
+    audio::Beeper,
+    display::{DisplayCommand, HEIGHT, WIDTH},
+    Chip8, Reset, FRAME_RATE,
 };
-use winit::{
-    event_loop::{ActiveEventLoop, EventLoop},
-    window::Window,
+use chip8::tui::Tui;
+use std::{
+    fs::File,
+    io::BufWriter,
+    num::NonZeroU32,
+    path::Path,
+    sync::mpsc,
+    time::{Duration, Instant},
 };
+use winit::dpi::PhysicalSize;
+use winit::event_loop::{ActiveEventLoop, EventLoop};
 
-mod gui;
-mod internals;
+mod cli;
 
 fn main() {
-    let event_loop = EventLoop::<DisplayCommand>::with_user_event()
-        .build()
-        .unwrap();
-    let _event_loop_proxy = event_loop.create_proxy();
+    let options = cli::Options::from_env();
+    if let Some(path) = &options.batch {
+        return batch(path, &options);
+    }
+    if options.headless {
+        return headless(&options);
+    }
+    if options.tui {
+        return tui(&options);
+    }
+
+    let event_loop = EventLoop::<Reply>::with_user_event().build().unwrap();
+    let event_loop_proxy = event_loop.create_proxy();
 
-    let controller = Arc::new(RwLock::new(Controller::default()));
-    let (ro_controller, wo_controller) = (Arc::clone(&controller), Arc::clone(&controller));
+    let (persistence, effects, scale) =
+        (options.persistence, options.effects.clone(), options.scale);
+    let screenshot_dir = options.screenshot_dir.clone();
+    let rom = options.rom.clone();
+    let (record, record_scale) = (options.record.clone(), options.record_scale);
+
+    let mut emulator = Emulator::new(machine(&options), options.instructions_per_frame);
+    emulator.reload = options.reload;
+    emulator.database = database(&options);
+    emulator.settings = base(&emulator.chip8, &options);
+    let settings = match emulator.load(&options.rom) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("ERR: {e}");
+            std::process::exit(1);
+        }
+    };
+    let (requests, request_rx) = mpsc::channel();
+    let config = options.config.clone();
 
     std::thread::spawn(move || {
-        let mut chip8 = Chip8::new(ro_controller);
-        //chip8.memory.load("./data/2-ibm-logo.ch8");
-        //chip8.memory.load("./data/3-corax+.ch8");
-        chip8.memory.load("./data/pong.ch8");
-        loop {
-            let inst = internals::parse_opcode(
-                ((chip8.memory.0[chip8.registers.pc as usize] as u16) << 8)
-                    | chip8.memory.0[chip8.registers.pc as usize + 1] as u16,
-            );
-            match chip8.run_instruction(inst) {
-                Ok(s) => match s {
-                    internals::InstructionResult::Success | InstructionResult::Waiting => {
-                        println!("{:?}", chip8.controller.keys_to_buttons())
-                    }
-                    internals::InstructionResult::Display(d) => {
-                        match _event_loop_proxy.send_event(d) {
-                            Ok(()) => (),
-                            Err(..) => println!("ERR: Event loop Closed !"),
-                        }
-                    }
-                },
-                Err(e) => println!("{:?}", e),
+        let mut audio = options
+            .wav
+            .as_deref()
+            .and_then(|p| Audio::create(p, &options));
+        emulator.run(
+            request_rx,
+            |chip8| {
+                if let Some(a) = &mut audio {
+                    a.frame(chip8.beeping());
+                }
+            },
+            |reply| {
+                let sent = event_loop_proxy.send_event(reply);
+                if sent.is_err() {
+                    println!("ERR: Event loop Closed !");
+                }
+                sent.is_ok()
+            },
+        );
+    });
+
+    let app = gui::window::WinitAppBuilder::with_init(move |elwt| {
+        let mut presenter = Presenter::new(persistence)
+            .with_scale(scale)
+            .with_effects(Pipeline::new(&effects, scale))
+            .with_screenshot_dir(&screenshot_dir);
+        if let Some(path) = &record {
+            if let Err(e) = presenter.start_recording(path, record_scale) {
+                println!("ERR: recording failed: {e}");
             }
-            std::thread::sleep(std::time::Duration::from_millis(2));
-            if chip8.registers.delay > 0 {
-                chip8.registers.delay -= 1;
+        }
+        presenter.set_palette(settings.palette);
+        initalize(
+            elwt,
+            presenter,
+            Remote::new(requests.clone(), &rom, &settings, config.clone()),
+        )
+    })
+    .with_event_handler(handle_event);
+
+    gui::window::init(event_loop, app);
+}
+
+/// A machine set up the way the options ask, ready for a ROM.
+fn machine(options: &cli::Options) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.quirks = options.quirks;
+    chip8.font = options.font;
+    chip8.program_start = options.load_address;
+    chip8.backend = options.backend;
+    chip8.timing = options.timing;
+    chip8
+}
+
+/// What ROMs the database doesn't know about run with.
+fn base(chip8: &Chip8, options: &cli::Options) -> Settings {
+    let mut s = Settings::of(chip8, options.instructions_per_frame);
+    s.palette = options.config.palette();
+    s.keymap = options.config.keymap();
+    s
+}
+
+/// The program database and overrides, if there are any.
+fn database(options: &cli::Options) -> Database {
+    let mut db = match &options.database {
+        Some(dir) if Path::new(dir).exists() => Database::open(dir).unwrap_or_else(|e| {
+            println!("ERR: {e}");
+            Database::default()
+        }),
+        _ => Database::default(),
+    };
+    if Path::new(&options.overrides).exists() {
+        if let Err(e) = db.read_overrides(&options.overrides) {
+            println!("ERR: {e}");
+        }
+    }
+    db
+}
+
+fn headless(options: &cli::Options) {
+    let mut run = Headless::new(machine(options), options.instructions_per_frame);
+    run.database = database(options);
+    run.settings = base(&run.chip8, options);
+    let settings = match run.load(&options.rom) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("ERR: {}: {e}", options.rom);
+            std::process::exit(1);
+        }
+    };
+    // frames go through a presenter the same as in the window, so that
+    // recordings and screenshots match what you'd see there
+    let mut presenter = Presenter::new(options.persistence)
+        .with_scale(options.scale)
+        .with_effects(Pipeline::new(&options.effects, options.scale));
+    presenter.set_palette(settings.palette);
+    if let Some(path) = &options.record {
+        if let Err(e) = presenter.start_recording(path, options.record_scale) {
+            println!("ERR: recording failed: {e}");
+        }
+    }
+
+    let mut audio = options
+        .wav
+        .as_deref()
+        .and_then(|p| Audio::create(p, options));
+
+    for _ in 0..options.frames {
+        if let Err(e) = run.run_frame() {
+            println!("{:?}", e)
+        }
+        if let Some(a) = &mut audio {
+            a.frame(run.chip8.beeping());
+        }
+        presenter.apply(&DisplayCommand::Draw(Box::new(run.chip8.take_frame())));
+    }
+    if let Err(e) = presenter.stop_recording() {
+        println!("ERR: recording failed: {e}");
+    }
+
+    let Some(path) = &options.screenshot else {
+        return;
+    };
+    let saved = if options.screenshot_native {
+        save_png(path, run.frame(), WIDTH, HEIGHT)
+    } else {
+        let (width, height) = presenter.size();
+        save_png(path, &presenter.render(), width, height)
+    };
+    if let Err(e) = saved {
+        println!("ERR: screenshot failed: {e}");
+    }
+}
+
+fn batch(path: &str, options: &cli::Options) {
+    let jobs: Vec<Job> = match std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+    {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            println!("ERR: {path}: {e}");
+            std::process::exit(1);
+        }
+    };
+    let chip8 = machine(options);
+    let mut batch = Batch::new(base(&chip8, options));
+    batch.database = database(options);
+    batch.backend = chip8.backend;
+    batch.timing = chip8.timing;
+    if let Some(n) = options.threads {
+        batch.threads = n;
+    }
+    let report = serde_json::to_string_pretty(&batch.run(&jobs)).unwrap();
+    match &options.report {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, report + "\n") {
+                println!("ERR: {path}: {e}");
+                std::process::exit(1);
             }
-            match chip8.controller.0.try_read() {
-                Ok(v) => (),
-                Err(e) => println!("{}", e),
+        }
+        None => println!("{report}"),
+    }
+}
+
+fn tui(options: &cli::Options) {
+    let mut chip8 = machine(options);
+    let base = base(&chip8, options);
+    let settings = match database(options).load_file(&mut chip8, &options.rom, &base, Reset::Cold) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("ERR: {}: {e}", options.rom);
+            std::process::exit(1);
+        }
+    };
+    let mut presenter = Presenter::new(options.persistence);
+    presenter.set_palette(settings.palette);
+    if let Some(path) = &options.record {
+        if let Err(e) = presenter.start_recording(path, options.record_scale) {
+            println!("ERR: recording failed: {e}");
+        }
+    }
+    let mut audio = options
+        .wav
+        .as_deref()
+        .and_then(|p| Audio::create(p, options));
+
+    let mut terminal = match Tui::new() {
+        Ok(t) => t,
+        Err(e) => return println!("ERR: can't use the terminal: {e}"),
+    };
+    // printing would scribble over the picture, so errors wait until the
+    // terminal is back to normal
+    let mut error = None;
+    let frame_time = Duration::from_secs(1) / FRAME_RATE;
+    let mut next_frame = Instant::now();
+    loop {
+        match terminal.poll_input(&mut chip8.keypad, &settings.keymap) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => {
+                error = Some(format!("ERR: {e}"));
+                break;
             }
         }
-    });
+        if let Err(e) = chip8.run_frame(settings.instructions_per_frame) {
+            error = Some(format!("{:?}", e));
+        }
+        if let Some(a) = &mut audio {
+            a.frame(chip8.beeping());
+        }
+        let dirty = presenter.apply(&DisplayCommand::Draw(Box::new(chip8.take_frame())));
+        if let Err(e) = terminal.draw(presenter.frame(), &dirty) {
+            error = Some(format!("ERR: {e}"));
+            break;
+        }
+        next_frame += frame_time;
+        std::thread::sleep(next_frame.saturating_duration_since(Instant::now()));
+    }
+    drop(terminal);
+
+    if let Some(e) = error {
+        println!("{e}");
+    }
+    if let Err(e) = presenter.stop_recording() {
+        println!("ERR: recording failed: {e}");
+    }
+}
 
-    let app = gui::window::WinitAppBuilder::with_init(initalize)
-        .with_event_handler(handle_event, wo_controller);
+/// Renders the buzzer a frame at a time into a WAV file.
+struct Audio {
+    beeper: Beeper,
+    wav: WavWriter<BufWriter<File>>,
+    samples: Vec<i16>,
+}
 
-    gui::window::init(event_loop, app);
+impl Audio {
+    fn create(path: &str, options: &cli::Options) -> Option<Self> {
+        let beeper = options.config.beeper();
+        match WavWriter::create(path, beeper.sample_rate) {
+            Ok(wav) => Some(Audio {
+                beeper,
+                wav,
+                samples: Vec::new(),
+            }),
+            Err(e) => {
+                println!("ERR: can't write audio: {e}");
+                None
+            }
+        }
+    }
+
+    fn frame(&mut self, beeping: bool) {
+        self.samples.clear();
+        self.beeper.render_frame(beeping, &mut self.samples);
+        if let Err(e) = self.wav.write(&self.samples) {
+            println!("ERR: can't write audio: {e}");
+        }
+    }
 }
 
-fn initalize(elwt: &ActiveEventLoop) -> (Rc<Window>, Surface<Rc<Window>, Rc<Window>>) {
-    let window = gui::window::make_window(elwt, |w| w);
+fn initalize(elwt: &ActiveEventLoop, presenter: Presenter, remote: Remote) -> gui::State {
+    let (width, height) = presenter.size();
+    let window = gui::window::make_window(elwt, |w| {
+        w.with_title(remote.title())
+            .with_inner_size(PhysicalSize::new(width as u32, height as u32))
+    });
 
     let context = softbuffer::Context::new(window.clone()).unwrap();
-    let surface = softbuffer::Surface::new(&context, window.clone()).unwrap();
+    let mut surface = softbuffer::Surface::new(&context, window.clone()).unwrap();
+    surface
+        .resize(
+            NonZeroU32::new(width as u32).unwrap(),
+            NonZeroU32::new(height as u32).unwrap(),
+        )
+        .unwrap();
 
-    (window, surface)
+    (window, surface, presenter, remote)
 }
@@ -0,0 +1,169 @@
+use std::io::{self, BufWriter, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+
+use crate::internals::display::{Dirty, HEIGHT, WIDTH};
+use crate::internals::keypad::{Button, Control, Controller, Keymap};
+
+/// Most terminals only send presses, repeated while the key is held, so a
+/// key counts as released once it hasn't repeated for this long.
+const HOLD: Duration = Duration::from_millis(200);
+
+/// Two pixels per character cell: the upper half block in the top pixel's
+/// colour over the bottom pixel's colour.
+const ROWS: usize = HEIGHT / 2;
+
+/// Draws frames to the terminal with half blocks and 24 bit colour, and
+/// reads the keypad from raw mode stdin. The terminal is put back the way
+/// it was when this is dropped.
+pub struct Tui {
+    out: BufWriter<Stdout>,
+    // when each button was last seen going down, for terminals that can't
+    // tell us when it comes back up
+    held: [Option<Instant>; 16],
+    release_events: bool,
+    redraw: bool,
+}
+
+impl Tui {
+    pub fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = BufWriter::new(io::stdout());
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if release_events {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(Tui {
+            out,
+            held: [None; 16],
+            release_events,
+            redraw: true,
+        })
+    }
+
+    /// Applies any pending key events to the keypad, with the arrows, enter
+    /// and tab going through `keymap`. Returns false once the user asked to
+    /// quit with escape or ctrl-c.
+    pub fn poll_input(&mut self, keypad: &mut Controller, keymap: &Keymap) -> io::Result<bool> {
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(k) => {
+                    let quit = k.code == KeyCode::Esc
+                        || (k.code == KeyCode::Char('c')
+                            && k.modifiers.contains(KeyModifiers::CONTROL));
+                    if quit {
+                        return Ok(false);
+                    }
+                    let b = match k.code {
+                        KeyCode::Char(c) => Button::from_char(c),
+                        KeyCode::Up => keymap.get(Control::Up),
+                        KeyCode::Down => keymap.get(Control::Down),
+                        KeyCode::Left => keymap.get(Control::Left),
+                        KeyCode::Right => keymap.get(Control::Right),
+                        KeyCode::Enter => keymap.get(Control::A),
+                        KeyCode::Tab => keymap.get(Control::B),
+                        _ => None,
+                    };
+                    let Some(b) = b else {
+                        continue;
+                    };
+                    match k.kind {
+                        KeyEventKind::Press | KeyEventKind::Repeat => {
+                            keypad.press(b);
+                            self.held[b as usize] = Some(Instant::now());
+                        }
+                        KeyEventKind::Release => {
+                            keypad.release(b);
+                            self.held[b as usize] = None;
+                        }
+                    }
+                }
+                Event::Resize(..) => self.redraw = true,
+                _ => (),
+            }
+        }
+
+        if !self.release_events {
+            for (n, held) in self.held.iter_mut().enumerate() {
+                if held.is_some_and(|t| t.elapsed() > HOLD) {
+                    keypad.release(Button::from_u8(n as u8));
+                    *held = None;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Redraws the character rows covering `dirty`, or everything after
+    /// the terminal was resized.
+    pub fn draw(&mut self, frame: &[u32; WIDTH * HEIGHT], dirty: &Dirty) -> io::Result<()> {
+        let mut rows = [self.redraw; ROWS];
+        for r in dirty.rects() {
+            for row in &mut rows[r.y / 2..(r.y + r.height).div_ceil(2)] {
+                *row = true;
+            }
+        }
+        if self.redraw {
+            queue!(
+                self.out,
+                terminal::Clear(terminal::ClearType::All),
+                cursor::MoveTo(0, ROWS as u16 + 1),
+                Print("keys 0-9 and a-f, escape quits")
+            )?;
+            self.redraw = false;
+        }
+
+        for (row, _) in rows.iter().enumerate().filter(|(_, r)| **r) {
+            queue!(self.out, cursor::MoveTo(0, row as u16))?;
+            let (top, bottom) = frame[row * 2 * WIDTH..(row * 2 + 2) * WIDTH].split_at(WIDTH);
+            // only send a colour when it changes, this may be going over ssh
+            let mut last = None;
+            for (t, b) in top.iter().zip(bottom) {
+                if last != Some((*t, *b)) {
+                    queue!(
+                        self.out,
+                        SetForegroundColor(colour(*t)),
+                        SetBackgroundColor(colour(*b))
+                    )?;
+                    last = Some((*t, *b));
+                }
+                queue!(self.out, Print('▀'))?;
+            }
+            queue!(self.out, ResetColor)?;
+        }
+        self.out.flush()
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        if self.release_events {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(
+            self.out,
+            ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn colour(p: u32) -> Color {
+    Color::Rgb {
+        r: (p >> 16) as u8,
+        g: (p >> 8) as u8,
+        b: p as u8,
+    }
+}
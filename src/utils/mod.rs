@@ -1,81 +0,0 @@
-pub mod winit_app;
-
-use std::fs::File;
-use std::io::BufWriter;
-use std::io::Write;
-
-pub fn write_initial_ram_data(path: &str) {
-    let file = File::create(path).unwrap();
-    let mut writer = BufWriter::new(file);
-
-    let sprite_data: Vec<u8> = vec![
-        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-        0x20, 0x60, 0x20, 0x20, 0x70, // 1
-        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-        0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-    ];
-
-    for &byte in &sprite_data {
-        writer.write_all(&byte.to_le_bytes()).unwrap();
-    }
-
-    writer.flush().unwrap();
-}
-
-#[cfg(test)]
-mod test {
-    use super::write_initial_ram_data;
-    use std::fs::File;
-    use std::io::BufReader;
-    use std::io::Read;
-
-    #[test]
-    fn test_write_sprite_data() {
-        write_initial_ram_data("./data/inital_ram_data.chip8");
-
-        let file = File::open("./data/inital_ram_data.chip8");
-        let mut reader = BufReader::new(file.unwrap());
-        let mut buffer = vec![0; 1]; // Buffer to hold 4 bytes for i32
-        let mut numbers_read: Vec<u8> = Vec::new();
-
-        // Read the file in chunks of 4 bytes
-        while reader.read_exact(&mut buffer).is_ok() {
-            let number = u8::from_le_bytes(buffer.clone().try_into().unwrap());
-            numbers_read.push(number);
-        }
-
-        assert_eq!(
-            numbers_read,
-            vec![
-                0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-                0x20, 0x60, 0x20, 0x20, 0x70, // 1
-                0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-                0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-                0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-                0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-                0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-                0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-                0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-                0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-                0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-                0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-                0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-                0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-                0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-                0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-            ]
-        );
-    }
-}
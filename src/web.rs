@@ -0,0 +1,319 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+use web_sys::{AudioContext, CanvasRenderingContext2d, GainNode, ImageData, OscillatorType};
+
+use crate::cartridge::unpack;
+use crate::database::{Database, Settings};
+use crate::internals::{
+    audio::Beeper,
+    display::{Palette, HEIGHT, WIDTH},
+    keypad::{Button, Control, Keymap},
+    Chip8, Reset, FRAME_RATE,
+};
+
+/// How many frames one animation frame can catch up on, so a tab coming
+/// back from the background doesn't fast forward.
+const MAX_CATCH_UP: usize = 4;
+
+/// The keypad button a DOM key stands for, if any: hex digits press their
+/// own button, the arrows, enter and tab whatever the game binds.
+fn to_button(key: &str, keymap: &Keymap) -> Option<Button> {
+    let control = match key {
+        "ArrowUp" => Control::Up,
+        "ArrowDown" => Control::Down,
+        "ArrowLeft" => Control::Left,
+        "ArrowRight" => Control::Right,
+        "Enter" => Control::A,
+        "Tab" => Control::B,
+        _ => {
+            let mut chars = key.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Button::from_char(c),
+                _ => None,
+            };
+        }
+    };
+    keymap.get(control)
+}
+
+/// The buzzer, as a square wave that's turned up while the sound timer
+/// runs.
+struct Buzzer {
+    context: AudioContext,
+    gain: GainNode,
+    volume: f32,
+    on: bool,
+}
+
+impl Buzzer {
+    /// Browsers only allow this once the page has had a click or a key.
+    fn new(beeper: &Beeper) -> Result<Self, JsValue> {
+        let context = AudioContext::new()?;
+        let oscillator = context.create_oscillator()?;
+        oscillator.set_type(OscillatorType::Square);
+        oscillator.frequency().set_value(beeper.frequency as f32);
+        let gain = context.create_gain()?;
+        gain.gain().set_value(0.0);
+        oscillator.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&context.destination())?;
+        oscillator.start()?;
+        Ok(Buzzer {
+            context,
+            gain,
+            volume: beeper.volume as f32 / i16::MAX as f32,
+            on: false,
+        })
+    }
+
+    fn set(&mut self, on: bool) {
+        if on != self.on {
+            self.on = on;
+            self.gain
+                .gain()
+                .set_value(if on { self.volume } else { 0.0 });
+        }
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+        if self.on {
+            self.gain.gain().set_value(volume);
+        }
+    }
+}
+
+impl Drop for Buzzer {
+    fn drop(&mut self) {
+        let _ = self.context.close();
+    }
+}
+
+/// A machine for a web page to run. The page hands it ROM files and keys,
+/// and calls `tick` on every animation frame; it keeps to 60 frames a
+/// second whatever the display's refresh rate.
+#[wasm_bindgen]
+pub struct Web {
+    chip8: Chip8,
+    instructions_per_frame: usize,
+    database: Database,
+    /// What ROMs the database doesn't know about run with.
+    settings: Settings,
+    keymap: Keymap,
+    palette: Palette,
+    beeper: Beeper,
+    buzzer: Option<Buzzer>,
+    paused: bool,
+    // when the next frame is due, in the page's milliseconds
+    next_frame: Option<f64>,
+    // the screen as the canvas wants it
+    rgba: Vec<u8>,
+}
+
+impl Default for Web {
+    fn default() -> Self {
+        Web::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Web {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        let chip8 = Chip8::new();
+        Web {
+            settings: Settings::of(&chip8, 10),
+            chip8,
+            instructions_per_frame: 10,
+            database: Database::default(),
+            keymap: Keymap::default(),
+            palette: Palette::default(),
+            beeper: Beeper::default(),
+            buzzer: None,
+            paused: true,
+            next_frame: None,
+            rgba: vec![0; WIDTH * HEIGHT * 4],
+        }
+    }
+
+    /// Starts a ROM or Octo cartridge, returning its title if it's a
+    /// program the database knows.
+    pub fn load(&mut self, file: &[u8]) -> Result<Option<String>, JsError> {
+        self.start_audio();
+        self.load_file(file).map_err(|e| JsError::new(&e))
+    }
+
+    /// Passes on a key going down or up, returning whether it meant
+    /// something, so the page knows not to act on it too. Space pauses,
+    /// F2 and F4 reset.
+    pub fn key(&mut self, key: &str, down: bool) -> bool {
+        self.start_audio();
+        self.press(key, down)
+    }
+
+    /// Runs whatever frames are due by `now`, from
+    /// `requestAnimationFrame`, and draws the last of them.
+    pub fn tick(&mut self, canvas: &CanvasRenderingContext2d, now: f64) -> Result<(), JsError> {
+        for _ in 0..self.frames_due(now) {
+            self.chip8
+                .run_frame(self.instructions_per_frame)
+                .map_err(|e| JsError::new(&format!("{e:?}")))?;
+        }
+        if let Some(b) = &mut self.buzzer {
+            b.set(!self.paused && self.chip8.beeping());
+        }
+        self.draw(canvas)
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.next_frame = None;
+    }
+
+    /// Between 0 and 1.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.beeper.volume = (volume.clamp(0.0, 1.0) * i16::MAX as f32) as i16;
+        if let Some(b) = &mut self.buzzer {
+            b.set_volume(volume.clamp(0.0, 1.0));
+        }
+    }
+}
+
+impl Web {
+    fn load_file(&mut self, file: &[u8]) -> Result<Option<String>, String> {
+        let (rom, options) = unpack(file).map_err(|e| e.to_string())?;
+        let settings = self
+            .database
+            .load_program(
+                &mut self.chip8,
+                &rom,
+                options.as_ref(),
+                &self.settings,
+                Reset::Cold,
+            )
+            .map_err(|e| e.to_string())?;
+        self.instructions_per_frame = settings.instructions_per_frame;
+        self.keymap = settings.keymap;
+        self.palette = settings.palette;
+        self.set_paused(false);
+        Ok(settings.title)
+    }
+
+    fn press(&mut self, key: &str, down: bool) -> bool {
+        match (key, down) {
+            (" ", true) => self.set_paused(!self.paused),
+            ("F2", true) => self.chip8.reset(Reset::Warm),
+            ("F4", true) => self.chip8.reset(Reset::Cold),
+            (" " | "F2" | "F4", false) => (),
+            _ => match to_button(key, &self.keymap) {
+                Some(b) if down => self.chip8.keypad.press(b),
+                Some(b) => self.chip8.keypad.release(b),
+                None => return false,
+            },
+        }
+        true
+    }
+
+    /// How many frames to run to catch up with `now`.
+    fn frames_due(&mut self, now: f64) -> usize {
+        if self.paused {
+            return 0;
+        }
+        let frame = 1000.0 / FRAME_RATE as f64;
+        let next = self.next_frame.get_or_insert(now);
+        let mut due = 0;
+        while *next <= now && due < MAX_CATCH_UP {
+            *next += frame;
+            due += 1;
+        }
+        // too far behind to catch up: carry on from here
+        if *next <= now {
+            *next = now + frame;
+        }
+        due
+    }
+
+    fn render(&mut self) {
+        for (out, p) in self.rgba.chunks_exact_mut(4).zip(&self.chip8.frame_buffer) {
+            let [_, r, g, b] = self.palette.colour(*p).to_be_bytes();
+            out.copy_from_slice(&[r, g, b, 0xFF]);
+        }
+    }
+
+    fn draw(&mut self, canvas: &CanvasRenderingContext2d) -> Result<(), JsError> {
+        self.render();
+        let image = ImageData::new_with_u8_clamped_array_and_sh(
+            Clamped(&self.rgba),
+            WIDTH as u32,
+            HEIGHT as u32,
+        )
+        .map_err(|_| JsError::new("can't make the image"))?;
+        canvas
+            .put_image_data(&image, 0.0, 0.0)
+            .map_err(|_| JsError::new("can't draw to the canvas"))
+    }
+
+    fn start_audio(&mut self) {
+        if self.buzzer.is_none() {
+            self.buzzer = Buzzer::new(&self.beeper).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{to_button, Web};
+    use crate::internals::keypad::{Button, Control, Keymap};
+
+    #[test]
+    fn test_keys() {
+        let mut keymap = Keymap::default();
+        keymap.bind(Control::Up, Button::B2);
+        assert_eq!(to_button("a", &keymap), Some(Button::BA));
+        assert_eq!(to_button("F", &keymap), Some(Button::BF));
+        assert_eq!(to_button("ArrowUp", &keymap), Some(Button::B2));
+        assert_eq!(to_button("ArrowDown", &keymap), None);
+        assert_eq!(to_button("g", &keymap), None);
+        assert_eq!(to_button("Shift", &keymap), None);
+    }
+
+    #[test]
+    fn test_frames_keep_time() {
+        let mut w = Web::new();
+        assert_eq!(w.frames_due(0.0), 0);
+        w.load_file(&[0x12, 0x00]).unwrap();
+        assert_eq!(w.frames_due(1000.0), 1);
+        // a 120Hz display gets a frame every other tick
+        let due: usize = (1..=120)
+            .map(|n| w.frames_due(1001.0 + n as f64 * 1000.0 / 120.0))
+            .sum();
+        assert_eq!(due, 60);
+        // back from the background
+        assert_eq!(w.frames_due(60_000.0), 4);
+        assert_eq!(w.frames_due(60_001.0), 0);
+        w.set_paused(true);
+        assert_eq!(w.frames_due(70_000.0), 0);
+    }
+
+    #[test]
+    fn test_load() {
+        let mut w = Web::new();
+        assert!(w.load_file(&[]).is_err());
+        let cartridge = std::fs::read("./data/cartridges/bounce.gif").unwrap();
+        assert_eq!(w.load_file(&cartridge), Ok(None));
+        assert!(!w.paused);
+        assert!(w.press(" ", true));
+        assert!(w.paused);
+        assert!(w.press("5", true));
+        assert!(w.chip8.keypad.is_pressed(Button::B5));
+        assert!(!w.press("Escape", true));
+
+        w.chip8.frame_buffer[1] = 1;
+        w.render();
+        let [_, r, g, b] = w.palette.colour(1).to_be_bytes();
+        assert_eq!(w.rgba[4..8], [r, g, b, 0xFF]);
+    }
+}
@@ -0,0 +1,39 @@
+use std::io::Cursor;
+
+use chip8::capture::wav::WavWriter;
+use chip8::internals::{audio::Beeper, Chip8};
+
+/// Renders `frames` frames of a program's buzzer to an in-memory WAV.
+fn render(program: &[u8], frames: usize) -> Vec<u8> {
+    let mut c8 = Chip8::new();
+    c8.memory.0[0x200..0x200 + program.len()].copy_from_slice(program);
+    let mut beeper = Beeper::default();
+    let mut wav = WavWriter::new(Cursor::new(Vec::new()), beeper.sample_rate).unwrap();
+    let mut samples = Vec::new();
+    for _ in 0..frames {
+        c8.run_frame(10).unwrap();
+        samples.clear();
+        beeper.render_frame(c8.beeping(), &mut samples);
+        wav.write(&samples).unwrap();
+    }
+    wav.into_inner().into_inner()
+}
+
+#[test]
+fn test_half_second_beep() {
+    // va = 30, st = va, loop
+    let program = [0x6A, 0x1E, 0xFA, 0x18, 0x12, 0x04];
+    let wav = render(&program, 60);
+
+    let samples: Vec<i16> = wav[44..]
+        .chunks_exact(2)
+        .map(|s| i16::from_le_bytes([s[0], s[1]]))
+        .collect();
+    assert_eq!(samples.len(), 60 * 735);
+    // 30 frames of tone, then silence
+    assert!(samples[..30 * 735].iter().all(|s| *s != 0));
+    assert!(samples[30 * 735..].iter().all(|s| *s == 0));
+
+    // and it's the same every time
+    assert_eq!(render(&program, 60), wav);
+}
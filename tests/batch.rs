@@ -0,0 +1,188 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chip8::batch::{Batch, Input, Job, Stop};
+use chip8::database::Settings;
+use chip8::internals::Chip8;
+
+fn batch(threads: usize) -> Batch {
+    let mut b = Batch::new(Settings::of(&Chip8::new(), 10));
+    b.threads = threads;
+    b
+}
+
+/// WASI has no temp directory of its own, so runners there map one in.
+fn temp_dir() -> PathBuf {
+    match cfg!(target_os = "wasi") {
+        true => "/tmp".into(),
+        false => std::env::temp_dir(),
+    }
+}
+
+/// Writes `program` where a job can load it from.
+fn rom(name: &str, program: &[u8]) -> PathBuf {
+    let path = temp_dir().join(format!("chip8-test-batch-{name}.ch8"));
+    std::fs::write(&path, program).unwrap();
+    path
+}
+
+/// Scribbles random bytes over the screen forever.
+fn noise(name: &str) -> PathBuf {
+    rom(
+        name,
+        &[
+            0xC0, 0x3F, // v0 = rand & 63
+            0xC1, 0x1F, // v1 = rand & 31
+            0xC2, 0xFF, // v2 = rand
+            0xA3, 0x00, // i = 0x300
+            0xF2, 0x55, // [i] = v0..v2
+            0xD0, 0x11, // draw 1 row at v0, v1
+            0x12, 0x00, // again
+        ],
+    )
+}
+
+#[test]
+fn test_threads_dont_matter() {
+    // a machine can be handed to another thread
+    fn send<T: Send>() {}
+    send::<Chip8>();
+
+    let noise = noise("noise");
+    let mut jobs: Vec<Job> = (0..12)
+        .map(|seed| Job {
+            seed,
+            frames: 30,
+            ..Job::new(&noise)
+        })
+        .collect();
+    jobs.push(Job::new("./data/1-chip8-logo.ch8"));
+    jobs.push(Job::new("./data/test.ch8"));
+
+    let one = batch(1).run(&jobs);
+    let many = batch(4).run(&jobs);
+    assert_eq!(one, many);
+    assert_eq!(one.len(), jobs.len());
+    assert!(one[..12].iter().all(|o| o.stop == Stop::Frames));
+    // different seeds, different noise
+    assert_ne!(one[0].state, one[1].state);
+    assert_eq!(one[0].instructions, 30 * 10);
+    assert_eq!(one[12].name, "./data/1-chip8-logo.ch8");
+}
+
+#[test]
+fn test_halts() {
+    let jobs = [
+        // ends by jumping to itself
+        Job::new(rom("loop-halts", &[0x60, 0x01, 0x12, 0x02])),
+        // waits for a key that never comes
+        Job::new(rom("wait-forever", &[0xF0, 0x0A, 0x12, 0x02])),
+        // waits for a key, gets it, then ends
+        Job {
+            movie: vec![
+                Input {
+                    frame: 5,
+                    key: 7,
+                    down: true,
+                },
+                Input {
+                    frame: 6,
+                    key: 7,
+                    down: false,
+                },
+            ],
+            ..Job::new(rom("wait", &[0xF0, 0x0A, 0x12, 0x02]))
+        },
+        // or doesn't stop for it
+        Job {
+            halt: false,
+            frames: 20,
+            ..Job::new(rom("loop", &[0x60, 0x01, 0x12, 0x02]))
+        },
+    ];
+    let o = batch(2).run(&jobs);
+    assert_eq!((o[0].stop, o[0].frames), (Stop::Halted, 1));
+    assert_eq!((o[1].stop, o[1].frames), (Stop::Halted, 1));
+    assert_eq!((o[2].stop, o[2].frames), (Stop::Halted, 7));
+    assert_eq!((o[3].stop, o[3].frames), (Stop::Frames, 20));
+}
+
+#[test]
+fn test_limits() {
+    let draws = |frame: &[u32]| frame.iter().any(|p| *p != 0);
+    let jobs = [
+        Job {
+            until: Some(Arc::new(draws)),
+            ..Job::new("./data/1-chip8-logo.ch8")
+        },
+        Job {
+            until_lit: Some((63, 31)),
+            frames: 10,
+            halt: false,
+            ..Job::new("./data/1-chip8-logo.ch8")
+        },
+        Job {
+            instructions: Some(95),
+            ..Job::new(noise("limited-noise"))
+        },
+        Job::new("./data/no-such-rom.ch8"),
+        Job::new(rom("bad", &[0x00, 0xEE])),
+    ];
+    let o = batch(3).run(&jobs);
+    assert_eq!(o[0].stop, Stop::Predicate);
+    assert!(o[0].frames < 10);
+    // the corner never lights up
+    assert_eq!((o[1].stop, o[1].frames), (Stop::Frames, 10));
+    assert_eq!(
+        (o[2].stop, o[2].frames, o[2].instructions),
+        (Stop::Instructions, 10, 100)
+    );
+    assert_eq!(o[3].stop, Stop::Error);
+    assert!(o[3].error.as_deref().unwrap().contains("no-such-rom"));
+    // returning with nothing on the stack
+    assert_eq!((o[4].stop, o[4].frames), (Stop::Error, 1));
+}
+
+/// A job that panics is reported, and the others carry on.
+#[test]
+#[cfg_attr(target_family = "wasm", ignore = "panics abort on wasm")]
+fn test_panics_stay_in_their_job() {
+    let boom = |_: &[u32]| -> bool { panic!("boom") };
+    let jobs = [
+        Job {
+            until: Some(Arc::new(boom)),
+            ..Job::new("./data/1-chip8-logo.ch8")
+        },
+        // i = 0xfff, save v0 and v1 over the end of memory
+        Job {
+            frames: 5,
+            halt: false,
+            ..Job::new(rom("off-the-end", &[0xAF, 0xFF, 0xF1, 0x55]))
+        },
+        Job::new("./data/1-chip8-logo.ch8"),
+    ];
+    let o = batch(2).run(&jobs);
+    assert_eq!(o[0].stop, Stop::Error);
+    assert_eq!(o[0].error.as_deref(), Some("panicked: boom"));
+    assert_eq!((o[1].stop, o[1].frames), (Stop::Frames, 5));
+    assert_eq!(o[2].stop, Stop::Halted);
+}
+
+#[test]
+fn test_report() {
+    let shot = temp_dir().join("chip8-test-batch.png");
+    let _ = std::fs::remove_file(&shot);
+    let jobs: Vec<Job> = serde_json::from_str(&format!(
+        r#"[{{"name": "logo", "rom": "./data/1-chip8-logo.ch8", "frames": 60,
+             "screenshot": {:?}}}]"#,
+        shot
+    ))
+    .unwrap();
+    let o = batch(1).run(&jobs);
+    assert!(shot.exists());
+    let report: serde_json::Value = serde_json::to_value(&o).unwrap();
+    assert_eq!(report[0]["name"], "logo");
+    assert_eq!(report[0]["stop"], "halted");
+    assert_eq!(report[0]["state"].as_str().unwrap().len(), 40);
+    assert!(report[0].get("error").is_none());
+}
@@ -0,0 +1,81 @@
+/* Drives the library through include/chip8.h, the way other programs
+ * will. Built and run in CI against the cdylib. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "chip8.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                  \
+    do {                                                             \
+        if (!(cond)) {                                               \
+            fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #cond); \
+            failures++;                                              \
+        }                                                            \
+    } while (0)
+
+int main(void) {
+    /* v0 += 1, draw the 0 glyph at v1, v1, then loop until key 5 is
+     * held */
+    const uint8_t program[] = {
+        0x70, 0x01, 0xF1, 0x29, 0xD1, 0x15,
+        0x61, 0x05, 0xE1, 0x9E, 0x12, 0x08, 0x12, 0x0C,
+    };
+    uint8_t screen[CHIP8_WIDTH * CHIP8_HEIGHT];
+    uint8_t bytes[4];
+    Chip8Registers r;
+
+    CHECK(chip8_abi_version() == CHIP8_ABI_VERSION);
+    Chip8Machine *m = chip8_new();
+    CHECK(m != NULL);
+    CHECK(chip8_load_rom(m, program, 0) == CHIP8_STATUS_EMPTY_ROM);
+    CHECK(chip8_load_rom(m, program, sizeof program) == CHIP8_STATUS_OK);
+    CHECK(chip8_run_frames(m, 2, 10) == CHIP8_STATUS_OK);
+
+    CHECK(chip8_framebuffer(m, screen, sizeof screen) == CHIP8_STATUS_OK);
+    CHECK(memcmp(screen, "\1\1\1\1\0", 5) == 0);
+    CHECK(chip8_get_registers(m, &r) == CHIP8_STATUS_OK);
+    CHECK(r.v[0] == 1 && r.v[1] == 5 && r.sp == 0);
+
+    Chip8State *saved = chip8_save_state(m);
+    CHECK(saved != NULL);
+    CHECK(chip8_set_keys(m, 1 << 5) == CHIP8_STATUS_OK);
+    CHECK(chip8_run_frames(m, 1, 10) == CHIP8_STATUS_OK);
+    CHECK(chip8_get_registers(m, &r) == CHIP8_STATUS_OK);
+    CHECK(r.pc == 0x20C);
+    CHECK(chip8_set_key(m, 5, false) == CHIP8_STATUS_OK);
+
+    r.v[0xE] = 42;
+    CHECK(chip8_set_registers(m, &r) == CHIP8_STATUS_OK);
+    CHECK(chip8_write_memory(m, 0x300, (const uint8_t *)"\xAB\xCD", 2) == CHIP8_STATUS_OK);
+    CHECK(chip8_read_memory(m, 0x2FF, bytes, 4) == CHIP8_STATUS_OK);
+    CHECK(memcmp(bytes, "\0\xAB\xCD\0", 4) == 0);
+    CHECK(chip8_read_memory(m, 0xFFF, bytes, 2) == CHIP8_STATUS_OUT_OF_RANGE);
+
+    CHECK(chip8_restore_state(m, saved) == CHIP8_STATUS_OK);
+    CHECK(chip8_get_registers(m, &r) == CHIP8_STATUS_OK);
+    CHECK(r.v[0xE] == 0 && r.pc == 0x208);
+    chip8_free_state(saved);
+
+    r.pc = 0xFFFF;
+    CHECK(chip8_set_registers(m, &r) == CHIP8_STATUS_OUT_OF_RANGE);
+
+    /* I at the top of memory, then store v0..vF through it, which once
+     * panicked and took the whole program down */
+    const uint8_t top[] = {0xAF, 0xFF, 0xF1, 0x55};
+    CHECK(chip8_load_rom(m, top, sizeof top) == CHIP8_STATUS_OK);
+    CHECK(chip8_run_frames(m, 1, 10) == CHIP8_STATUS_OK);
+
+    CHECK(chip8_load_file(m, "data/no-such-rom.ch8") == CHIP8_STATUS_IO);
+    CHECK(chip8_step(NULL, 1) == CHIP8_STATUS_NULL);
+    chip8_free(m);
+
+    if (failures) {
+        fprintf(stderr, "%d failed\n", failures);
+        return 1;
+    }
+    puts("ok");
+    return 0;
+}
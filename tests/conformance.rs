@@ -0,0 +1,212 @@
+//! Runs programs on the interpreter and the JIT side by side, checking that
+//! the machines agree after every frame.
+
+use chip8::capture::screenshot::check_golden;
+use chip8::headless::Headless;
+use chip8::internals::display::{HEIGHT, WIDTH};
+use chip8::internals::jit::Backend;
+use chip8::internals::keypad::Button;
+use chip8::internals::{Chip8, Reset};
+
+fn machine(backend: Backend, rom: &[u8], display_wait: bool) -> Chip8 {
+    let mut c8 = Chip8::new();
+    c8.backend = backend;
+    c8.quirks.display_wait = display_wait;
+    c8.load_rom(rom, Reset::Cold).unwrap();
+    c8
+}
+
+fn assert_same(a: &Chip8, b: &Chip8, frame: usize) {
+    let (ra, rb) = (&a.registers, &b.registers);
+    assert_eq!(ra.r, rb.r, "V registers after frame {frame}");
+    assert_eq!(ra.vi, rb.vi, "I after frame {frame}");
+    assert_eq!(ra.pc, rb.pc, "pc after frame {frame}");
+    assert_eq!(ra.stack, rb.stack, "stack after frame {frame}");
+    assert_eq!(
+        (ra.delay, ra.sound),
+        (rb.delay, rb.sound),
+        "timers after frame {frame}"
+    );
+    assert!(a.memory.0 == b.memory.0, "memory after frame {frame}");
+    assert!(
+        a.frame_buffer == b.frame_buffer,
+        "frame buffer after frame {frame}"
+    );
+    assert_eq!(a.beeping(), b.beeping(), "buzzer after frame {frame}");
+}
+
+/// Runs `rom` on both backends for `frames` frames of `ipf` instructions,
+/// pressing and releasing keys as `keys` says before each frame.
+fn lockstep(rom: &[u8], ipf: usize, frames: usize, display_wait: bool) {
+    lockstep_with_keys(rom, ipf, frames, display_wait, |_, _| ());
+}
+
+fn lockstep_with_keys(
+    rom: &[u8],
+    ipf: usize,
+    frames: usize,
+    display_wait: bool,
+    keys: impl Fn(usize, &mut Chip8),
+) {
+    let mut interpreted = machine(Backend::Interpreter, rom, display_wait);
+    let mut compiled = machine(Backend::Jit, rom, display_wait);
+    for frame in 0..frames {
+        keys(frame, &mut interpreted);
+        keys(frame, &mut compiled);
+        let a = interpreted.run_frame(ipf);
+        let b = compiled.run_frame(ipf);
+        assert_eq!(a.is_err(), b.is_err(), "errors in frame {frame}");
+        assert_same(&interpreted, &compiled, frame);
+        // a frame's budget can end anywhere in a block
+        assert_eq!(interpreted.take_frame().dirty, compiled.take_frame().dirty);
+    }
+}
+
+#[test]
+fn test_roms() {
+    for rom in ["./data/1-chip8-logo.ch8", "./data/test.ch8"] {
+        let rom = std::fs::read(rom).unwrap();
+        for ipf in [1, 7, 10, 100] {
+            lockstep(&rom, ipf, 120, false);
+            lockstep(&rom, ipf, 120, true);
+        }
+    }
+}
+
+#[test]
+fn test_arithmetic() {
+    let rom = [
+        0x60, 0xF0, // v0 = 0xF0
+        0x61, 0x20, // v1 = 0x20
+        0x80, 0x14, // v0 += v1, carry
+        0x82, 0x05, // v2 -= v0
+        0x83, 0x06, // v3 = v0 >> 1
+        0x84, 0x17, // v4 = v1 - v4
+        0x85, 0x0E, // v5 = v0 << 1
+        0x86, 0x11, // v6 |= v1
+        0x87, 0x02, // v7 &= v0
+        0x88, 0x13, // v8 ^= v1
+        0x89, 0x00, // v9 = v0
+        0x7A, 0x03, // va += 3
+        0x8F, 0x04, // vf += v0, then the carry
+        0xFA, 0x1E, // i += va
+        0xF0, 0x29, // i = digit v0
+        0xF0, 0x30, // i = big digit v0
+        0x12, 0x02, // loop
+    ];
+    for ipf in [1, 3, 16, 50] {
+        lockstep(&rom, ipf, 60, false);
+    }
+}
+
+#[test]
+fn test_control_flow() {
+    let rom = [
+        0x70, 0x01, // 200: v0 += 1
+        0x30, 0x05, // 202: skip if v0 == 5
+        0x12, 0x0A, // 204: jump 20A
+        0x60, 0x00, // 206: v0 = 0
+        0x22, 0x14, // 208: call 214
+        0x50, 0x10, // 20A: skip if v0 == v1
+        0x71, 0x01, // 20C: v1 += 1
+        0x90, 0x10, // 20E: skip if v0 != v1
+        0xB2, 0x00, // 210: jump 200 + v0
+        0x12, 0x00, // 212: jump 200
+        0x72, 0x01, // 214: v2 += 1
+        0x42, 0x03, // 216: skip if v2 != 3
+        0x62, 0x00, // 218: v2 = 0
+        0x00, 0xEE, // 21A: return
+    ];
+    for ipf in [1, 2, 5, 11, 64] {
+        lockstep(&rom, ipf, 120, false);
+    }
+}
+
+#[test]
+fn test_self_modifying_code() {
+    let rom = [
+        0xA2, 0x07, // 200: i = 207
+        0x70, 0x01, // 202: v0 += 1
+        0x12, 0x06, // 204: jump 206
+        0x61, 0x00, // 206: v1 = whatever v0 was last stored here
+        0xF0, 0x55, // 208: store v0 at 207
+        0xF1, 0x33, // 20A: bcd v1 at 207, over 61 0x and the store
+        0xA2, 0x07, // 20C: i = 207
+        0x12, 0x02, // 20E: loop
+    ];
+    for ipf in [1, 4, 9, 30] {
+        lockstep(&rom, ipf, 60, false);
+    }
+}
+
+#[test]
+fn test_draws_and_timers() {
+    let rom = [
+        0x00, 0xE0, // 200: clear
+        0x60, 0x05, // 202: v0 = 5
+        0xF0, 0x15, // 204: delay = v0
+        0xF0, 0x18, // 206: sound = v0
+        0xF1, 0x29, // 208: i = digit v1
+        0xD1, 0x25, // 20A: draw at v1, v2
+        0x71, 0x05, // 20C: v1 += 5
+        0x72, 0x01, // 20E: v2 += 1
+        0xD1, 0x25, // 210: draw again
+        0xF3, 0x07, // 212: v3 = delay
+        0x33, 0x00, // 214: skip if v3 == 0
+        0x12, 0x12, // 216: jump 212
+        0x12, 0x00, // 218: loop
+    ];
+    for ipf in [1, 3, 10, 40] {
+        lockstep(&rom, ipf, 90, false);
+        lockstep(&rom, ipf, 90, true);
+    }
+}
+
+#[test]
+fn test_keys() {
+    let rom = [
+        0xF0, 0x0A, // 200: v0 = next key
+        0x71, 0x01, // 202: v1 += 1
+        0xE0, 0x9E, // 204: skip if v0 is held
+        0x12, 0x00, // 206: wait again
+        0xE0, 0xA1, // 208: skip if v0 isn't held
+        0x12, 0x08, // 20A: until it's let go
+        0x12, 0x00, // 20C: loop
+    ];
+    let keys = |frame: usize, c8: &mut Chip8| match frame % 7 {
+        2 => c8.keypad.press(Button::from_u8(frame as u8)),
+        4 => c8.keypad.release(Button::from_u8(frame as u8 - 2)),
+        _ => (),
+    };
+    for ipf in [1, 5, 20] {
+        lockstep_with_keys(&rom, ipf, 90, false, keys);
+    }
+}
+
+#[test]
+fn test_stack_underflow() {
+    // return without a call
+    let rom = [0x60, 0x01, 0x00, 0xEE];
+    lockstep(&rom, 10, 3, false);
+}
+
+#[test]
+fn test_golden_roms() {
+    for (rom, name) in [
+        ("./data/1-chip8-logo.ch8", "1-chip8-logo"),
+        ("./data/test.ch8", "test"),
+    ] {
+        let mut chip8 = Chip8::new();
+        chip8.backend = Backend::Jit;
+        let mut run = Headless::new(chip8, 10);
+        run.load(rom).unwrap();
+        run.run_frames(60).unwrap();
+        check_golden(
+            format!("./data/golden/{name}.png"),
+            run.frame(),
+            WIDTH,
+            HEIGHT,
+        )
+        .unwrap();
+    }
+}
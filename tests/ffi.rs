@@ -0,0 +1,26 @@
+use std::fs;
+use std::path::Path;
+
+/// `include/chip8.h` has to say what `src/ffi.rs` does. Setting
+/// UPDATE_GOLDEN rewrites it.
+#[test]
+fn test_header_is_current() {
+    let config = cbindgen::Config::from_file("cbindgen.toml").unwrap();
+    let mut header = Vec::new();
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("src/ffi.rs")
+        .generate()
+        .unwrap()
+        .write(&mut header);
+    let path = Path::new("include/chip8.h");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(path, &header).unwrap();
+    }
+    let current = fs::read(path).unwrap_or_default();
+    assert!(
+        current == header,
+        "{} is out of date, run the tests with UPDATE_GOLDEN set",
+        path.display()
+    );
+}
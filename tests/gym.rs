@@ -0,0 +1,162 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use chip8::gym::{Env, Spec};
+use chip8::internals::{jit::Backend, Chip8, Reset};
+
+/// Counts this thread's allocations, so tests running alongside don't
+/// get in the way.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Draws noise, and scores a point every time around while key 5 is held.
+const GAME: [u8; 14] = [
+    0x61, 0x05, // v1 = 5
+    0xC0, 0x3F, // v0 = rand & 63
+    0xA2, 0x00, // i = 0x200
+    0xD0, 0x31, // draw 1 row at v0, v3
+    0xE1, 0xA1, // skip if key v1 isn't held
+    0x7E, 0x01, // ve += 1
+    0x12, 0x02, // again
+];
+
+const SPEC: &str = r#"
+actions = [[], [5]]
+[[reward]]
+register = 14
+[[done]]
+register = 14
+at_least = 50
+"#;
+
+fn env(program: &[u8], spec: &str) -> Env {
+    let mut c8 = Chip8::new();
+    c8.load_rom(program, Reset::Cold).unwrap();
+    Env::new(c8, 10, Spec::parse(spec).unwrap())
+}
+
+/// Every step's reward and observation, holding key 5 every other step.
+fn play(env: &mut Env, seed: u64, steps: usize) -> Vec<(f32, bool, [u64; 32])> {
+    env.reset(seed);
+    (0..steps)
+        .map(|n| {
+            let s = env.step(n % 2).unwrap();
+            (s.reward, s.done, *env.observation())
+        })
+        .collect()
+}
+
+#[test]
+fn test_seeds() {
+    let mut a = env(&GAME, SPEC);
+    let mut b = env(&GAME, SPEC);
+    assert_eq!(a.actions(), 2);
+    assert_eq!(a.reset(1), &[0; 32]);
+    assert_eq!(play(&mut a, 1, 20), play(&mut b, 1, 20));
+    assert_ne!(play(&mut a, 1, 20), play(&mut a, 2, 20));
+    // something got drawn
+    assert!(play(&mut a, 3, 1)[0].2.iter().any(|row| *row != 0));
+}
+
+#[test]
+fn test_reward_and_done() {
+    let mut e = env(&GAME, SPEC);
+    e.reset(0);
+    assert_eq!(e.step(0).unwrap().reward, 0.0);
+    let held = e.step(1).unwrap();
+    assert!(held.reward > 0.0);
+    assert!(!held.done);
+    let mut total = held.reward;
+    while !e
+        .step(1)
+        .map(|s| {
+            total += s.reward;
+            s.done
+        })
+        .unwrap()
+    {}
+    assert_eq!(total, e.chip8.registers.r[0xE] as f32);
+    assert!(total >= 50.0);
+
+    // jumping to itself is the end too
+    let mut e = env(&[0x12, 0x00], "");
+    e.reset(0);
+    assert!(e.step(0).unwrap().done);
+}
+
+#[test]
+fn test_frame_skip() {
+    let mut one = env(&GAME, SPEC);
+    let mut four = env(&GAME, SPEC);
+    four.frame_skip = 4;
+    one.reset(7);
+    four.reset(7);
+    let mut reward = 0.0;
+    for _ in 0..4 {
+        reward += one.step(1).unwrap().reward;
+    }
+    assert_eq!(four.step(1).unwrap().reward, reward);
+    assert_eq!(four.observation(), one.observation());
+}
+
+#[test]
+fn test_sticky() {
+    // always sticky: the first action never lets go
+    let mut e = env(&GAME, SPEC);
+    e.sticky = 1.0;
+    e.reset(0);
+    assert_eq!(e.step(1).unwrap().reward, 0.0);
+
+    let mut a = env(&GAME, SPEC);
+    let mut b = env(&GAME, SPEC);
+    a.sticky = 0.5;
+    b.sticky = 0.5;
+    assert_eq!(play(&mut a, 4, 30), play(&mut b, 4, 30));
+}
+
+#[test]
+fn test_steps_dont_allocate() {
+    let allocations = || ALLOCATIONS.with(Cell::get);
+    let mut e = env(&GAME, SPEC);
+    e.frame_skip = 2;
+    e.sticky = 0.25;
+    e.reset(0);
+    let before = allocations();
+    for n in 0..200 {
+        if e.step(n % 2).unwrap().done {
+            e.reset(n as u64);
+        }
+    }
+    assert_eq!(allocations(), before);
+
+    // the JIT compiles blocks from wherever frames end the first time
+    // around
+    let mut e = env(&GAME, "actions = [[], [5]]");
+    e.chip8.backend = Backend::Jit;
+    e.reset(0);
+    for n in 0..20 {
+        e.step(n % 2).unwrap();
+    }
+    let before = allocations();
+    for n in 0..200 {
+        e.step(n % 2).unwrap();
+    }
+    assert_eq!(allocations(), before);
+}
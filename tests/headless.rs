@@ -0,0 +1,49 @@
+use chip8::capture::screenshot::check_golden;
+use chip8::headless::Headless;
+use chip8::internals::display::{HEIGHT, WIDTH};
+use chip8::internals::memory::LoadError;
+use chip8::internals::Chip8;
+
+/// Runs a ROM for a while and compares the final frame with
+/// `data/golden/<name>.png`.
+fn check_rom(rom: &str, name: &str, frames: usize) {
+    let mut run = Headless::new(Chip8::new(), 10);
+    run.load(rom).unwrap();
+    run.run_frames(frames).unwrap();
+    check_golden(
+        format!("./data/golden/{name}.png"),
+        run.frame(),
+        WIDTH,
+        HEIGHT,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_chip8_logo() {
+    check_rom("./data/1-chip8-logo.ch8", "1-chip8-logo", 60);
+}
+
+#[test]
+fn test_test_rom() {
+    check_rom("./data/test.ch8", "test", 60);
+}
+
+#[test]
+fn test_missing_rom() {
+    let mut run = Headless::new(Chip8::new(), 10);
+    assert!(matches!(
+        run.load("./data/no-such-rom.ch8"),
+        Err(LoadError::Io(_))
+    ));
+}
+
+#[test]
+fn test_cartridge() {
+    let mut run = Headless::new(Chip8::new(), 10);
+    let settings = run.load("./data/cartridges/bounce.gif").unwrap();
+    assert_eq!(settings.instructions_per_frame, 15);
+    assert!(run.chip8.quirks.display_wait);
+    run.run_frames(30).unwrap();
+    assert!(run.frame().iter().any(|p| *p != 0));
+}
@@ -0,0 +1,55 @@
+import init, { Web } from './pkg/chip8.js';
+
+await init();
+const web = new Web();
+const canvas = document.getElementById('screen');
+const context = canvas.getContext('2d');
+const status = document.getElementById('status');
+const rom = document.getElementById('rom');
+const volume = document.getElementById('volume');
+
+rom.addEventListener('change', async () => {
+  const file = rom.files[0];
+  if (!file) {
+    return;
+  }
+  try {
+    const title = web.load(new Uint8Array(await file.arrayBuffer()));
+    status.textContent = title ?? file.name;
+  } catch (e) {
+    status.textContent = e.message;
+  }
+  // so the keys go to the game from here on
+  rom.blur();
+});
+
+volume.addEventListener('input', () => web.set_volume(Number(volume.value)));
+web.set_volume(Number(volume.value));
+
+for (const [type, down] of [['keydown', true], ['keyup', false]]) {
+  window.addEventListener(type, (e) => {
+    if (e.target === volume) {
+      return;
+    }
+    // held keys stay held, but the page shouldn't scroll either
+    if (e.repeat) {
+      e.preventDefault();
+      return;
+    }
+    if (web.key(e.key, down)) {
+      e.preventDefault();
+    }
+  });
+}
+
+function frame(now) {
+  try {
+    web.tick(context, now);
+  } catch (e) {
+    status.textContent = e.message;
+    web.set_paused(true);
+  }
+  canvas.classList.toggle('paused', web.paused());
+  requestAnimationFrame(frame);
+}
+requestAnimationFrame(frame);